@@ -9,11 +9,13 @@ pub use hyper::{body::Incoming, Result};
 pub use restate_sdk::{
     connection::*,
     context::{
-        CombinableFuture, Context, ContextBase, ContextData, ContextDate, ContextWorkflowShared,
-        DurablePromise, JournalIndex, KeyValueStore, KeyValueStoreReadOnly, ObjectContext,
-        ObjectSharedContext, WorkflowContext, WorkflowSharedContext,
+        CombinableFuture, CombinableFutureImpl, Context, ContextBase, ContextData, ContextDate,
+        ContextInstance, ContextWorkflowShared, CustomJournalEntry, DurableFuture, DurablePromise,
+        JournalIndex, KeyValueStore, KeyValueStoreReadOnly, MapOutput, ObjectContext, ObjectSharedContext,
+        WorkflowContext, WorkflowSharedContext,
     },
     endpoint::{self, *},
+    payload::{Empty, Json, Payload, Raw},
 };
 pub use restate_sdk_client::{HttpIngress, Ingress};
 pub use restate_sdk_derive::{bundle, handler, main, object, service, workflow};
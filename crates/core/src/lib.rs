@@ -12,18 +12,18 @@ pub trait RestateWorkflowSharedContext: RestateObjectSharedContext {}
 
 pub trait RestateWorkflowContext: RestateObjectContext + RestateWorkflowSharedContext {}
 
-/// Trait to represent a run action
-pub trait RunAction: Fn() -> Self::OutputFuture {
+/// Trait to represent a run action, its future may borrow from the caller
+pub trait RunAction: FnOnce() -> Self::OutputFuture {
     /// Output type of the async function
     type Output;
     /// Future of the output
-    type OutputFuture: Future<Output = <Self as RunAction>::Output> + Send + 'static;
+    type OutputFuture: Future<Output = <Self as RunAction>::Output> + Send;
 }
 
-impl<F: ?Sized, Fut> RunAction for F
+impl<F, Fut> RunAction for F
 where
-    F: Fn() -> Fut,
-    Fut: Future + Send + 'static,
+    F: FnOnce() -> Fut,
+    Fut: Future + Send,
 {
     type Output = Fut::Output;
     type OutputFuture = Fut;
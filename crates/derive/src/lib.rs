@@ -1,20 +1,62 @@
 //! Restate Rust SDK Macros
 
 use convert_case::{Case, Casing};
+use darling::{ast::NestedMeta, util::Flag, FromMeta};
 use proc_macro::TokenStream;
-use quote::{format_ident, quote, quote_spanned};
+use quote::{format_ident, quote};
 use restate_sdk_types::endpoint_manifest::{
-    Endpoint, Handler, HandlerName, HandlerType, ProtocolMode, Service, ServiceName, ServiceType,
+    Handler, HandlerName, HandlerType, InputPayload, OutputPayload, Service, ServiceName, ServiceType,
 };
+use std::collections::HashSet;
 use syn::{
-    parse_quote, parse_quote_spanned, spanned::Spanned, token::Brace, Attribute, Block, Expr, FnArg,
-    ImplItem, ImplItemFn, Item, ItemFn, ItemImpl, Lit, Pat, Receiver, ReturnType, Stmt, Type,
+    Attribute, Error, Expr, ExprLit, FnArg, GenericArgument, Ident, ImplItem, ImplItemFn, Item, ItemFn,
+    ItemImpl, ItemTrait, Lit, LitStr, Meta, Pat, PathArguments, ReturnType, Signature, TraitItem,
+    TraitItemFn, Type,
 };
 use tracing::debug;
 
 const SERVICE_ATTRIBUTE: &str = "restate::service";
 const OBJECT_ATTRIBUTE: &str = "restate::object";
 const WORKFLOW_ATTRIBUTE: &str = "restate::workflow";
+const HANDLER_ATTRIBUTE: &str = "restate::handler";
+
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Arguments of `#[restate::service]`, `#[restate::object]` and `#[restate::workflow]`.
+#[derive(Default, FromMeta)]
+#[darling(default)]
+struct ServiceArgs {
+    /// Name the service is registered with, the name of its struct otherwise
+    name: Option<LitStr>,
+    /// Only other services can invoke the service, it is not exposed by the ingress
+    ingress_private: Flag,
+}
+
+/// Arguments of `#[restate::handler]`.
+#[derive(Default, FromMeta)]
+#[darling(default)]
+struct HandlerArgs {
+    /// Name the handler is registered with, the name of its function otherwise
+    name: Option<LitStr>,
+    /// Runs concurrently to the other handlers of the same key, only for objects and workflows
+    shared: Flag,
+    /// Runs the workflow, exactly one handler of a workflow
+    workflow: Flag,
+    ingress_private: Flag,
+}
+
+/// Arguments given to an attribute macro.
+fn macro_args<Args: FromMeta>(args: TokenStream) -> syn::Result<Args> {
+    Ok(Args::from_list(&NestedMeta::parse_meta_list(args.into())?)?)
+}
+
+/// Arguments of an attribute found on an item, the defaults when it has none.
+fn attribute_args<Args: FromMeta + Default>(attribute: &Attribute) -> syn::Result<Args> {
+    match &attribute.meta {
+        Meta::Path(_) => Ok(Args::default()),
+        meta => Ok(Args::from_meta(meta)?),
+    }
+}
 
 #[proc_macro_attribute]
 #[cfg(not(test))]
@@ -35,395 +77,961 @@ pub fn main(args: TokenStream, item: TokenStream) -> TokenStream {
 #[cfg(not(test))]
 pub fn bundle(args: TokenStream, item: TokenStream) -> TokenStream {
     let endpoint = syn::parse_macro_input!(item as Item);
-    let mut services = vec![];
-    match &endpoint {
-        Item::Mod(module) => {
+    create_bundle(&endpoint)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn create_bundle(endpoint: &Item) -> syn::Result<proc_macro2::TokenStream> {
+    let mut definitions = vec![];
+    match endpoint {
+        Item::Mod(module) if module.content.is_some() => {
             debug!("Module path {:?}", module.ident.to_string());
+            let bundle = &module.ident;
             if let Some((_, items)) = &module.content {
                 for item in items {
                     match item {
-                        Item::Impl(item) => {
-                            if let Some(attr) = find_attribute(
+                        Item::Impl(item)
+                            if find_attribute(
                                 &[SERVICE_ATTRIBUTE, OBJECT_ATTRIBUTE, WORKFLOW_ATTRIBUTE],
                                 &item.attrs,
-                            ) {
-                                let service_type = if attr.eq(SERVICE_ATTRIBUTE) {
-                                    ServiceType::Service
-                                } else if attr.eq(OBJECT_ATTRIBUTE) {
-                                    ServiceType::VirtualObject
-                                } else if attr.eq(WORKFLOW_ATTRIBUTE) {
-                                    ServiceType::Workflow
-                                } else {
-                                    panic!("Invalid service type");
-                                };
-                                services.push(create_service_manifest(service_type, &item))
-                            }
+                            )
+                            .is_some() =>
+                        {
+                            let service_ident = service_ident(item)?;
+                            definitions.push(quote!(#bundle::#service_ident.into_definition()));
+                        }
+                        Item::Trait(item) if find_attribute(&[SERVICE_ATTRIBUTE], &item.attrs).is_some() => {
+                            let service_ident = &item.ident;
+                            let implementation = trait_implementation(item, items)?;
+                            // Created once, the invocations of the handlers share the implementation
+                            definitions.push(quote!(
+                                #bundle::#service_ident::serve(<#bundle::#implementation as Default>::default())
+                                    .into_definition()
+                            ));
                         }
                         _ => {}
                     }
                 }
             }
         }
-        _ => {
-            panic!("Only on mod")
+        item => {
+            return Err(Error::new_spanned(
+                item,
+                "`#[restate::bundle]` only applies to a module declared inline, `mod bundle { ... }`",
+            ));
         }
     };
 
-    // TODO: Read from attributes
-    let manifest = Endpoint {
-        max_protocol_version: 1,
-        min_protocol_version: 1,
-        protocol_mode: Some(ProtocolMode::BidiStream),
-        services,
-    };
-
-    let manifest_json = serde_json::to_string(&manifest).unwrap();
-
-    /*
-    use std::{fs::File, io::Write};
-    let mut file = File::create("manifest.json").expect("Unable to create manifest file");
-    file.write_all(manifest_json.as_bytes())
-        .expect("Unable to write manifest file");
-     */
-
-    let methods = manifest
-        .services
-        .iter()
-        .flat_map(|service| handler_methods(service))
-        .collect::<Vec<_>>();
-
-    quote!(
+    Ok(quote!(
         #endpoint
-        use restate_sdk_api::{
-            empty, full, http2_handler, setup_connection, BodyExt, BoxBody, Bytes, Incoming, Method, Request,
-            Response, StatusCode,
-        };
-        pub async fn service(req: Request<Incoming>) -> restate::Result<Response<BoxBody<Bytes, anyhow::Error>>> {
-            match (req.method(), req.uri().path()) {
-                (&Method::GET, "/discover") => {
-                    let manifest = #manifest_json;
-                    let response = Response::builder()
-                        .status(StatusCode::OK)
-                        .header("content-type", "application/vnd.restate.endpointmanifest.v1+json")
-                        .header("x-restate-server", "restate-sdk-rust/0.1.0")
-                        .body(full(manifest).map_err(|e| e.into()).boxed())
-                        .unwrap();
-                    Ok(response)
-                }
-                #(#methods)*
-                // Return the 404 Not Found for other routes.
-                _ => {
-                    let response = Response::builder()
-                        .status(StatusCode::NOT_FOUND)
-                        .body(empty().map_err(|e| e.into()).boxed())
-                        .unwrap();
-                    Ok(response)
-                }
-            }
+
+        /// Services declared in the bundle, the discovery manifest and the invocations are served by
+        /// the registry of the endpoint they are bound to.
+        pub fn services() -> Vec<restate_sdk_api::endpoint::ServiceDefinition> {
+            use restate_sdk_api::endpoint::IntoServiceDefinition;
+            vec![#(#definitions),*]
         }
-    )
-    .into()
+    ))
 }
 
-fn handler_methods(service: &Service) -> Vec<proc_macro2::TokenStream> {
-    let mut routes = vec![];
-    for handler in &service.handlers {
-        let service = service.name.to_string();
-        let handler = handler.name.to_string();
-        let route = format!("/invoke/{}/{}", service, handler);
-        let service = format_ident!("{}", service);
-        let handler = format_ident!("{}", handler);
-        routes.push(quote!(
-           (&Method::POST, #route) => {
-                let (receiver, sender, boxed_body) = setup_connection(req);
-                tokio::spawn(
-                    async move {
-                        http2_handler::handle(bundle::#service::#handler, None, receiver, sender, false).await;
-                    },
-                );
-                let response = Response::builder()
-                    .status(StatusCode::OK)
-                    .header("content-type", "application/vnd.restate.invocation.v1")
-                    .header("x-restate-server", "restate-sdk-rust/0.1.0")
-                    .body(boxed_body)
-                    .unwrap();
-                Ok(response)
+/// Implementation of a service trait in the bundle, the one struct the trait is implemented for.
+fn trait_implementation<'a>(service: &ItemTrait, items: &'a [Item]) -> syn::Result<&'a Ident> {
+    let mut implementations = items.iter().filter_map(|item| match item {
+        Item::Impl(item) => match &item.trait_ {
+            Some((_, path, _))
+                if path
+                    .segments
+                    .last()
+                    .is_some_and(|segment| segment.ident == service.ident) =>
+            {
+                Some(item)
             }
+            _ => None,
+        },
+        _ => None,
+    });
+    let implementation = implementations.next().ok_or_else(|| {
+        Error::new_spanned(
+            &service.ident,
+            format!(
+                "a service trait in a bundle is implemented in it, `impl {} for Implementation {{ ... }}`",
+                service.ident
+            ),
+        )
+    })?;
+    if let Some(other) = implementations.next() {
+        return Err(Error::new_spanned(
+            &other.self_ty,
+            "a service trait in a bundle has a single implementation in it",
         ));
     }
-    routes
+    match implementation.self_ty.as_ref() {
+        Type::Path(path) if path.qself.is_none() && path.path.segments.len() == 1 => {
+            Ok(&path.path.segments[0].ident)
+        }
+        ty => Err(Error::new_spanned(
+            ty,
+            "a service trait in a bundle is implemented for a struct declared in it",
+        )),
+    }
 }
 
-fn create_service_manifest(service_type: ServiceType, item: &ItemImpl) -> Service {
-    let service_name = match item.self_ty.as_ref() {
-        Type::Path(path) => path.path.segments[0].ident.to_string(),
-        _ => {
-            panic!("Only on impl struct");
-        }
-    };
-    let mut service = Service {
-        handlers: vec![],
-        name: ServiceName::try_from(&service_name).unwrap(),
-        ty: service_type,
+/// Implements `IntoServiceDefinition`, binding the handlers of the service to an endpoint.
+fn create_service_definition(manifest: &Service, item: &ItemImpl) -> syn::Result<proc_macro2::TokenStream> {
+    let service_name = service_ident(item)?;
+    let service_literal = manifest.name.to_string();
+    let service_type = match manifest.ty {
+        ServiceType::Service => format_ident!("Service"),
+        ServiceType::VirtualObject => format_ident!("VirtualObject"),
+        ServiceType::Workflow => format_ident!("Workflow"),
     };
-    for item in item.items.iter() {
-        match item {
-            ImplItem::Const(property) => {
-                let name = property.ident.to_string();
-                if name.eq("TYPE") {
-                    // Should be only static string
-                    let value = match &property.expr {
-                        Expr::Lit(expr) => match &expr.lit {
-                            Lit::Str(value) => value.value(),
-                            _ => {
-                                panic!("Only literal string");
-                            }
-                        },
-                        _ => {
-                            panic!("Only literal string")
-                        }
-                    };
-                    service.ty = ServiceType::try_from(value).unwrap();
-                    debug!("Found property {:?}", service);
-                }
-            }
-            ImplItem::Fn(handler) => {
-                if handler.sig.asyncness.is_some() {
-                    if find_attribute(&["restate::handler"], &handler.attrs).is_some() {
-                        let name = handler.sig.ident.to_string();
-                        let handler_type = match service_type {
-                            ServiceType::VirtualObject => Some(HandlerType::Exclusive),
-                            ServiceType::Service => None,
-                            ServiceType::Workflow => {
-                                if name.eq("run") {
-                                    Some(HandlerType::Workflow)
-                                } else {
-                                    Some(HandlerType::Shared)
-                                }
-                            }
-                        };
-                        service.handlers.push(Handler {
-                            input: None,
-                            name: HandlerName::try_from(name).unwrap(),
-                            output: None,
-                            ty: handler_type,
-                        })
-                    }
-                }
-            }
-            _ => {
-                panic!("Only consts and fns");
+    let options = service_options(manifest);
+    let handlers = manifest
+        .handlers
+        .iter()
+        .zip(handler_fns(item))
+        .map(|(handler, handler_fn)| {
+            let handler_literal = handler.name.to_string();
+            let options = handler_options(handler);
+            let handler = &handler_fn.sig.ident;
+            let handler = payload_handler(
+                quote!(#service_name::#handler),
+                &HandlerSignature::parse(&handler_fn.sig, true)?,
+            );
+            Ok(quote!(.handler_with_options(#handler_literal, #handler, #options)))
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+    Ok(quote!(
+        impl restate_sdk_api::endpoint::IntoServiceDefinition for #service_name {
+            fn into_definition(self) -> restate_sdk_api::endpoint::ServiceDefinition {
+                restate_sdk_api::endpoint::ServiceDefinition::new(
+                    #service_literal,
+                    restate_sdk_api::endpoint::ServiceType::#service_type,
+                )
+                .options(#options)
+                #(#handlers)*
             }
         }
+    ))
+}
+
+/// `ServiceOptions` announcing the settings of the service in the manifest.
+fn service_options(manifest: &Service) -> proc_macro2::TokenStream {
+    let documentation = optional_string(&manifest.documentation);
+    let ingress_private = manifest.ingress_private.unwrap_or_default();
+    quote!(restate_sdk_api::endpoint::ServiceOptions {
+        documentation: #documentation,
+        ingress_private: #ingress_private,
+        ..Default::default()
+    })
+}
+
+/// `HandlerOptions` announcing the settings of the handler in the manifest.
+fn handler_options(handler: &Handler) -> proc_macro2::TokenStream {
+    let documentation = optional_string(&handler.documentation);
+    let ingress_private = handler.ingress_private.unwrap_or_default();
+    let handler_type = match handler.ty {
+        Some(HandlerType::Exclusive) => quote!(Some(restate_sdk_api::endpoint::HandlerType::Exclusive)),
+        Some(HandlerType::Shared) => quote!(Some(restate_sdk_api::endpoint::HandlerType::Shared)),
+        Some(HandlerType::Workflow) => quote!(Some(restate_sdk_api::endpoint::HandlerType::Workflow)),
+        None => quote!(None),
+    };
+    quote!(restate_sdk_api::endpoint::HandlerOptions {
+        ty: #handler_type,
+        documentation: #documentation,
+        ingress_private: #ingress_private,
+        ..Default::default()
+    })
+}
+
+fn optional_string(value: &Option<String>) -> proc_macro2::TokenStream {
+    match value {
+        Some(value) => quote!(Some(#value.to_string())),
+        None => quote!(None),
     }
-    service
 }
 
-#[proc_macro_attribute]
-#[cfg(not(test))]
-pub fn service(args: TokenStream, item: TokenStream) -> TokenStream {
-    let service = syn::parse_macro_input!(item as ItemImpl);
-    let service_name = match service.self_ty.as_ref() {
-        Type::Path(path) => {
-            let service = path.path.segments[0].ident.to_string();
-            format_ident!("{}", service)
+/// Name of the struct the service is implemented on.
+fn service_ident(item: &ItemImpl) -> syn::Result<&Ident> {
+    match (&item.trait_, item.self_ty.as_ref()) {
+        (None, Type::Path(path)) if path.qself.is_none() && path.path.segments.len() == 1 => {
+            Ok(&path.path.segments[0].ident)
         }
-        _ => {
-            panic!("Only on impl struct");
+        (Some((_, path, _)), _) => Err(Error::new_spanned(
+            path,
+            "a service is implemented on its own struct, `impl Service { ... }`, not as a trait implementation",
+        )),
+        (None, ty) => Err(Error::new_spanned(
+            ty,
+            "a service is implemented on a struct named after it, `impl Service { ... }`",
+        )),
+    }
+}
+
+/// Functions of the service marked with `#[restate::handler]`.
+fn handler_fns(item: &ItemImpl) -> impl Iterator<Item = &ImplItemFn> {
+    item.items.iter().filter_map(|item| match item {
+        ImplItem::Fn(handler) if find_attribute(&[HANDLER_ATTRIBUTE], &handler.attrs).is_some() => {
+            Some(handler)
         }
-    };
-    let mut methods = vec![];
-    for item in service.items.iter() {
+        _ => None,
+    })
+}
+
+fn create_service_manifest(
+    service_type: ServiceType,
+    args: &ServiceArgs,
+    item: &ItemImpl,
+) -> syn::Result<Service> {
+    let service_ident = service_ident(item)?;
+    for item in item.items.iter() {
         match item {
-            ImplItem::Const(_) => {}
-            ImplItem::Fn(handler) => {
-                if handler.sig.asyncness.is_some() {
-                    if find_attribute(&["restate::handler"], &handler.attrs).is_some() {
-                        debug!("Handler {}", handler.sig.ident.to_string());
-                        let method = create_service_client_fn(service_name.clone(), handler);
-                        methods.push(method);
-                    }
-                }
+            ImplItem::Const(property) if property.ident == "NAME" => {
+                return Err(Error::new_spanned(
+                    property,
+                    "the name of a service is given as an argument, `#[restate::service(name = \"...\")]`",
+                ));
             }
-            _ => {
-                panic!("Only consts and fns");
+            ImplItem::Const(property) if property.ident == "TYPE" => {
+                return Err(Error::new_spanned(
+                    property,
+                    "the type of a service follows from its attribute, `#[restate::service]`, `#[restate::object]` or `#[restate::workflow]`",
+                ));
+            }
+            ImplItem::Const(_) | ImplItem::Fn(_) => {}
+            item => {
+                return Err(Error::new_spanned(
+                    item,
+                    "a service only holds consts and handler functions",
+                ));
             }
         }
     }
-    let service_client = format_ident!("{}ClientImpl", service_name.to_string());
-    let service_client_ext = format_ident!("{}ClientExt", service_name.to_string());
-    let service_client_indent = service_name.to_string().to_case(Case::Snake);
-    let service_client_indent = format_ident!("{}_client", service_client_indent);
-    quote!(
-        pub struct #service_name;
-        #service
-        struct #service_client<'a> {
-            ctx: &'a Context,
+    let service = service_manifest(
+        service_type,
+        args,
+        service_ident,
+        &item.attrs,
+        handler_fns(item).map(|handler| (&handler.attrs[..], &handler.sig)),
+        true,
+    )?;
+    if service_type == ServiceType::VirtualObject {
+        for (handler, handler_fn) in service.handlers.iter().zip(handler_fns(item)) {
+            check_object_context(&handler_fn.sig, handler.ty)?;
         }
-        impl<'a> #service_client<'a> {
-           #(#methods)*
+    }
+    Ok(service)
+}
+
+/// Manifest of a service declared with the given attributes and handler signatures, the arguments of
+/// the handlers are read from their `#[restate::handler]` attribute. The handlers of service traits do
+/// not take their context.
+fn service_manifest<'a>(
+    service_type: ServiceType,
+    args: &ServiceArgs,
+    service_ident: &Ident,
+    attrs: &[Attribute],
+    handlers: impl Iterator<Item = (&'a [Attribute], &'a Signature)>,
+    takes_context: bool,
+) -> syn::Result<Service> {
+    let (service_name, span) = match &args.name {
+        Some(name) => (name.value(), name.span()),
+        None => (service_ident.to_string(), service_ident.span()),
+    };
+    let mut service = Service {
+        abort_timeout: None,
+        documentation: documentation(attrs),
+        handlers: vec![],
+        idempotency_retention: None,
+        inactivity_timeout: None,
+        ingress_private: args.ingress_private.is_present().then_some(true),
+        journal_retention: None,
+        metadata: Default::default(),
+        name: ServiceName::try_from(&service_name).map_err(|err| {
+            Error::new(
+                span,
+                format!("`{}` is not a valid service name: {}", service_name, err),
+            )
+        })?,
+        ty: service_type,
+    };
+    let mut handler_names = HashSet::new();
+    let mut workflow_handler = None;
+    for (attrs, sig) in handlers {
+        if sig.asyncness.is_none() {
+            return Err(Error::new_spanned(sig, "a handler is an `async fn`"));
         }
-        trait #service_client_ext {
-            fn #service_client_indent(&self) -> #service_client;
+        let signature = HandlerSignature::parse(sig, takes_context)?;
+        let args: HandlerArgs = match find_attribute(&[HANDLER_ATTRIBUTE], attrs) {
+            Some((_, attribute)) => attribute_args(attribute)?,
+            None => HandlerArgs::default(),
+        };
+        let (name, span) = match &args.name {
+            Some(name) => (name.value(), name.span()),
+            None => (sig.ident.to_string(), sig.ident.span()),
+        };
+        if !handler_names.insert(name.clone()) {
+            return Err(Error::new(
+                span,
+                format!("the service already has a handler named `{}`", name),
+            ));
         }
-
-        impl #service_client_ext for Context {
-            fn #service_client_indent(&self) -> #service_client {
-                #service_client { ctx: &self }
-            }
+        let handler_type = handler_type(service_type, &args)?;
+        if handler_type == Some(HandlerType::Workflow) && workflow_handler.replace(sig).is_some() {
+            return Err(Error::new(
+                args.workflow.span(),
+                "a workflow has a single handler running it",
+            ));
         }
-    )
-    .into()
+        service.handlers.push(Handler {
+            abort_timeout: None,
+            documentation: documentation(attrs),
+            idempotency_retention: None,
+            inactivity_timeout: None,
+            ingress_private: args.ingress_private.is_present().then_some(true),
+            input: Some(signature.input_type().input_payload()),
+            journal_retention: None,
+            metadata: Default::default(),
+            name: HandlerName::try_from(&name).map_err(|err| {
+                Error::new(span, format!("`{}` is not a valid handler name: {}", name, err))
+            })?,
+            output: Some(signature.output_type().output_payload()),
+            ty: handler_type,
+            workflow_completion_retention: None,
+        })
+    }
+    if service_type == ServiceType::Workflow && workflow_handler.is_none() {
+        return Err(Error::new_spanned(
+            service_ident,
+            "a workflow has a handler running it, `#[restate::handler(workflow)]`",
+        ));
+    }
+    Ok(service)
+}
 
-    /*
-    impl ServiceHandler for #service_name {
-            fn name(&self) -> &'static str {
-                Self::NAME
-            }
+/// Shared handlers of objects take `ObjectSharedContext`, which only reads the state, for their writes
+/// to be rejected at compile time.
+fn check_object_context(handler: &Signature, handler_type: Option<HandlerType>) -> syn::Result<()> {
+    let Some(FnArg::Typed(context)) = handler.inputs.first() else {
+        return Ok(());
+    };
+    let shared_context = match context.ty.as_ref() {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "ObjectSharedContext"),
+        _ => false,
+    };
+    match handler_type {
+        Some(HandlerType::Shared) if !shared_context => Err(Error::new_spanned(
+            &context.ty,
+            "a shared handler takes `ObjectSharedContext`, which only reads the state",
+        )),
+        Some(HandlerType::Exclusive) if shared_context => Err(Error::new_spanned(
+            &context.ty,
+            "a handler taking `ObjectSharedContext` is declared shared, `#[restate::handler(shared)]`",
+        )),
+        _ => Ok(()),
+    }
+}
 
-            fn handlers(&self) -> &'static [&'static str] {
-                &["service", "greet"]
-            }
-        }
-     */
+/// Kind of a handler announced in the manifest, the handlers of objects are exclusive and the ones of
+/// workflows are shared unless declared otherwise.
+fn handler_type(service_type: ServiceType, args: &HandlerArgs) -> syn::Result<Option<HandlerType>> {
+    match service_type {
+        ServiceType::Service if args.shared.is_present() => Err(Error::new(
+            args.shared.span(),
+            "only the handlers of objects and workflows are shared",
+        )),
+        ServiceType::Service | ServiceType::VirtualObject if args.workflow.is_present() => Err(Error::new(
+            args.workflow.span(),
+            "only a handler of a workflow runs it, `#[restate::workflow]`",
+        )),
+        ServiceType::Workflow if args.shared.is_present() && args.workflow.is_present() => Err(Error::new(
+            args.shared.span(),
+            "the handler running the workflow is not shared",
+        )),
+        ServiceType::Service => Ok(None),
+        ServiceType::VirtualObject if args.shared.is_present() => Ok(Some(HandlerType::Shared)),
+        ServiceType::VirtualObject => Ok(Some(HandlerType::Exclusive)),
+        ServiceType::Workflow if args.workflow.is_present() => Ok(Some(HandlerType::Workflow)),
+        ServiceType::Workflow => Ok(Some(HandlerType::Shared)),
+    }
+}
+
+#[proc_macro_attribute]
+#[cfg(not(test))]
+pub fn service(args: TokenStream, item: TokenStream) -> TokenStream {
+    let service = syn::parse_macro_input!(item as Item);
+    macro_args(args)
+        .and_then(|args| match &service {
+            Item::Impl(service) => create_service(ServiceType::Service, format_ident!("Context"), &args, service),
+            Item::Trait(service) => create_service_trait(&args, service),
+            item => Err(Error::new_spanned(
+                item,
+                "`#[restate::service]` applies to the implementation of a service, `impl Service { ... }`, or to its trait, `trait Service { ... }`",
+            )),
+        })
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
 }
 
 #[proc_macro_attribute]
 #[cfg(not(test))]
 pub fn object(args: TokenStream, item: TokenStream) -> TokenStream {
-    let service = syn::parse_macro_input!(item as ItemImpl);
-    let service_name = match service.self_ty.as_ref() {
-        Type::Path(path) => {
-            let service = path.path.segments[0].ident.to_string();
-            format_ident!("{}", service)
-        }
-        _ => {
-            panic!("Only on impl struct");
-        }
-    };
-    let mut methods = vec![];
-    for item in service.items.iter() {
-        match item {
-            ImplItem::Const(_) => {}
-            ImplItem::Fn(handler) => {
-                if handler.sig.asyncness.is_some() {
-                    if find_attribute(&["restate::handler"], &handler.attrs).is_some() {
-                        debug!("Handler {}", handler.sig.ident.to_string());
-                        let method = create_service_client_fn(service_name.clone(), handler);
-                        methods.push(method);
-                    }
-                }
-            }
-            _ => {
-                panic!("Only consts and fns");
-            }
-        }
+    let service = syn::parse_macro_input!(item as Item);
+    macro_args(args)
+        .and_then(|args| match &service {
+            Item::Impl(service) => create_service(
+                ServiceType::VirtualObject,
+                format_ident!("ObjectContext"),
+                &args,
+                service,
+            ),
+            item => service_impl_error("object", item),
+        })
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_attribute]
+#[cfg(not(test))]
+pub fn workflow(args: TokenStream, item: TokenStream) -> TokenStream {
+    let service = syn::parse_macro_input!(item as Item);
+    macro_args(args)
+        .and_then(|args| match &service {
+            Item::Impl(service) => create_service(
+                ServiceType::Workflow,
+                format_ident!("WorkflowContext"),
+                &args,
+                service,
+            ),
+            item => service_impl_error("workflow", item),
+        })
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Rejects the items `#[restate::object]` and `#[restate::workflow]` do not apply to, service traits
+/// only declaring services, whose handlers are called without a key.
+fn service_impl_error(attribute: &str, item: &Item) -> syn::Result<proc_macro2::TokenStream> {
+    match item {
+        Item::Trait(service) => Err(Error::new_spanned(
+            &service.ident,
+            format!(
+                "only services are declared as traits, `#[restate::{}]` applies to the implementation of the {}, `impl {} {{ ... }}`",
+                attribute, attribute, service.ident
+            ),
+        )),
+        item => Err(Error::new_spanned(
+            item,
+            format!(
+                "`#[restate::{}]` applies to the implementation of the {}, `impl {} {{ ... }}`",
+                attribute,
+                attribute,
+                attribute.to_case(Case::Pascal)
+            ),
+        )),
     }
+}
+
+/// Declares the struct of the service, its client calling the handlers from the given context and its
+/// definition.
+fn create_service(
+    service_type: ServiceType,
+    context: Ident,
+    args: &ServiceArgs,
+    service: &ItemImpl,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let manifest = create_service_manifest(service_type, args, service)?;
+    let service_name = service_ident(service)?.clone();
+    let methods = manifest
+        .handlers
+        .iter()
+        .zip(handler_fns(service))
+        .map(|(handler, handler_fn)| {
+            debug!("Handler {}", handler.name.to_string());
+            create_service_client_fn(
+                service_type,
+                &manifest.name.to_string(),
+                handler_fn,
+                &handler.name.to_string(),
+            )
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+    let definition = create_service_definition(&manifest, service)?;
     let service_client = format_ident!("{}ClientImpl", service_name.to_string());
     let service_client_ext = format_ident!("{}ClientExt", service_name.to_string());
     let service_client_indent = service_name.to_string().to_case(Case::Snake);
     let service_client_indent = format_ident!("{}_client", service_client_indent);
-    quote!(
+    // The clients of objects and workflows call the handlers on the key they are created with
+    let client = match service_type {
+        ServiceType::Service => quote!(
+            struct #service_client<'a> {
+                ctx: &'a #context,
+            }
+            impl<'a> #service_client<'a> {
+               #(#methods)*
+            }
+            trait #service_client_ext {
+                fn #service_client_indent(&self) -> #service_client;
+            }
+
+            impl #service_client_ext for #context {
+                fn #service_client_indent(&self) -> #service_client {
+                    #service_client { ctx: &self }
+                }
+            }
+        ),
+        ServiceType::VirtualObject | ServiceType::Workflow => quote!(
+            struct #service_client<'a> {
+                ctx: &'a #context,
+                key: String,
+            }
+            impl<'a> #service_client<'a> {
+               #(#methods)*
+            }
+            trait #service_client_ext {
+                fn #service_client_indent(&self, key: impl Into<String>) -> #service_client;
+            }
+
+            impl #service_client_ext for #context {
+                fn #service_client_indent(&self, key: impl Into<String>) -> #service_client {
+                    #service_client {
+                        ctx: &self,
+                        key: key.into(),
+                    }
+                }
+            }
+        ),
+    };
+    Ok(quote!(
         pub struct #service_name;
         #service
-        struct #service_client<'a> {
-            ctx: &'a ObjectContext,
-        }
-        impl<'a> #service_client<'a> {
-           #(#methods)*
+        #client
+        #definition
+    ))
+}
+
+/// Declares the trait implementing the service, the definition binding an implementation to an
+/// endpoint and the client calling the handlers from the context of any handler.
+fn create_service_trait(args: &ServiceArgs, service: &ItemTrait) -> syn::Result<proc_macro2::TokenStream> {
+    let handlers = trait_handler_fns(service)?;
+    let manifest = create_trait_manifest(args, service)?;
+
+    let service_literal = manifest.name.to_string();
+    let vis = &service.vis;
+    let attrs = &service.attrs;
+    let service_name = &service.ident;
+    let supertraits = service.supertraits.iter();
+    let serve = format_ident!("Serve{}", service_name);
+    let client = format_ident!("{}Client", service_name);
+    let client_ext = format_ident!("{}ClientExt", service_name);
+    let client_method = format_ident!("{}_client", service_name.to_string().to_case(Case::Snake));
+    let mut methods = vec![];
+    let mut definitions = vec![];
+    let mut client_methods = vec![];
+    for (handler, handler_fn) in manifest.handlers.iter().zip(&handlers) {
+        let signature = HandlerSignature::parse(&handler_fn.sig, false)?;
+        let input = signature.input.map(|(input, ty)| quote!(#input: #ty));
+        let result = signature.result;
+        let attrs = handler_fn
+            .attrs
+            .iter()
+            .filter(|attribute| {
+                find_attribute(&[HANDLER_ATTRIBUTE], std::slice::from_ref(*attribute)).is_none()
+            })
+            .collect::<Vec<_>>();
+        let method = &handler_fn.sig.ident;
+        let handler_literal = handler.name.to_string();
+        methods.push(quote!(
+            #(#attrs)*
+            fn #method(
+                &self,
+                ctx: restate_sdk_api::Context,
+                #input
+            ) -> impl std::future::Future<Output = #result> + Send;
+        ));
+        let options = handler_options(handler);
+        let handler = trait_handler(quote!(<S as #service_name>::#method), &signature);
+        definitions.push(quote!(
+            .handler_with_options(
+                #handler_literal,
+                {
+                    let service = self.service.clone();
+                    #handler
+                },
+                #options,
+            )
+        ));
+        let call = client_call(&service_literal, &handler_literal, &signature, quote!(None));
+        client_methods.push(quote!(
+            #(#attrs)*
+            pub fn #method(
+                self,
+                #input
+            ) -> impl restate_sdk_api::DurableFuture<Output = #result> + restate_sdk_api::JournalIndex + 'a {
+                #call
+            }
+        ));
+    }
+    let options = service_options(&manifest);
+    Ok(quote!(
+        #(#attrs)*
+        #vis trait #service_name: #(#supertraits +)* Sized + Send + Sync + 'static {
+            #(#methods)*
+
+            /// Binds the implementation to an endpoint.
+            fn serve(self) -> #serve<Self> {
+                #serve {
+                    service: std::sync::Arc::new(self),
+                }
+            }
         }
-        trait #service_client_ext {
-            fn #service_client_indent(&self) -> #service_client;
+
+        /// Definition of the service served by an implementation of its trait.
+        #vis struct #serve<S> {
+            service: std::sync::Arc<S>,
         }
 
-        impl #service_client_ext for ObjectContext {
-            fn #service_client_indent(&self) -> #service_client {
-                #service_client { ctx: &self }
+        impl<S: #service_name> restate_sdk_api::endpoint::IntoServiceDefinition for #serve<S> {
+            fn into_definition(self) -> restate_sdk_api::endpoint::ServiceDefinition {
+                restate_sdk_api::endpoint::ServiceDefinition::new(
+                    #service_literal,
+                    restate_sdk_api::endpoint::ServiceType::Service,
+                )
+                .options(#options)
+                #(#definitions)*
             }
         }
-    )
-    .into()
 
-    /*
-    impl ServiceHandler for #service_name {
-            fn name(&self) -> &'static str {
-                Self::NAME
-            }
+        /// Client calling the handlers of the service from the context of a handler.
+        #vis struct #client<'a, C> {
+            ctx: &'a C,
+        }
 
-            fn handlers(&self) -> &'static [&'static str] {
-                &["service", "greet"]
+        impl<'a, C: restate_sdk_api::ContextBase> #client<'a, C> {
+            #(#client_methods)*
+        }
+
+        #vis trait #client_ext: restate_sdk_api::ContextBase + Sized {
+            fn #client_method(&self) -> #client<'_, Self> {
+                #client { ctx: self }
             }
         }
-     */
+
+        impl<C: restate_sdk_api::ContextBase> #client_ext for C {}
+    ))
 }
 
-#[proc_macro_attribute]
-#[cfg(not(test))]
-pub fn workflow(args: TokenStream, item: TokenStream) -> TokenStream {
-    let service = syn::parse_macro_input!(item as ItemImpl);
-    let service_name = match service.self_ty.as_ref() {
-        Type::Path(path) => {
-            let service = path.path.segments[0].ident.to_string();
-            format_ident!("{}", service)
+/// Handlers declared by a service trait.
+fn trait_handler_fns(service: &ItemTrait) -> syn::Result<Vec<&TraitItemFn>> {
+    if !service.generics.params.is_empty() || service.generics.where_clause.is_some() {
+        return Err(Error::new_spanned(
+            &service.generics,
+            "a service trait is not generic",
+        ));
+    }
+    service
+        .items
+        .iter()
+        .map(|item| match item {
+            TraitItem::Fn(handler) if handler.default.is_none() => Ok(handler),
+            TraitItem::Fn(handler) => Err(Error::new_spanned(
+                &handler.default,
+                "the handlers of a service trait are implemented by the implementations of the trait",
+            )),
+            item => Err(Error::new_spanned(
+                item,
+                "a service trait only declares handlers, `async fn handler(input: Input) -> Result<Output, HandlerError>;`",
+            )),
+        })
+        .collect()
+}
+
+fn create_trait_manifest(args: &ServiceArgs, service: &ItemTrait) -> syn::Result<Service> {
+    service_manifest(
+        ServiceType::Service,
+        args,
+        &service.ident,
+        &service.attrs,
+        trait_handler_fns(service)?
+            .into_iter()
+            .map(|handler| (&handler.attrs[..], &handler.sig)),
+        false,
+    )
+}
+
+/// Handler registered for a handler of a service trait, calling the method at the given path on the
+/// implementation held in the `Arc` named `service`.
+fn trait_handler(method: proc_macro2::TokenStream, signature: &HandlerSignature) -> proc_macro2::TokenStream {
+    let (parameter, arguments) = payload_arguments(signature);
+    let output = payload_output(signature);
+    quote!(
+        move |ctx: restate_sdk_api::Context, #parameter| {
+            let service = service.clone();
+            async move { #method(&*service, ctx #arguments).await #output }
         }
-        _ => {
-            panic!("Only on impl struct");
+    )
+}
+
+/// Input and result of a handler, read from its signature.
+struct HandlerSignature<'a> {
+    /// Input the handler takes, if any
+    input: Option<(&'a Ident, &'a Type)>,
+    /// Result returned by the handler, `Result<Output, HandlerError>`
+    result: &'a Type,
+    /// `Output` of the result
+    output: &'a Type,
+}
+
+impl<'a> HandlerSignature<'a> {
+    /// Reads the signature of a handler taking its context before its input, or only its input for the
+    /// handlers of service traits, which receive the context from their implementation.
+    fn parse(handler: &'a Signature, takes_context: bool) -> syn::Result<Self> {
+        let inputs = &handler.inputs;
+        if let Some(FnArg::Receiver(receiver)) = inputs.first() {
+            return Err(Error::new_spanned(
+                receiver,
+                if takes_context {
+                    "a handler does not take `self`"
+                } else {
+                    "a handler of a service trait does not take `self`, its implementation does"
+                },
+            ));
         }
-    };
-    let mut methods = vec![];
-    for item in service.items.iter() {
-        match item {
-            ImplItem::Const(_) => {}
-            ImplItem::Fn(handler) => {
-                if handler.sig.asyncness.is_some() {
-                    if find_attribute(&["restate::handler"], &handler.attrs).is_some() {
-                        debug!("Handler {}", handler.sig.ident.to_string());
-                        let method = create_service_client_fn(service_name.clone(), handler);
-                        methods.push(method);
-                    }
+        let (message, max_inputs) = if takes_context {
+            (
+                "a handler takes the context and at most one input, `(ctx: Context, input: Input)`",
+                2,
+            )
+        } else {
+            (
+                "a handler of a service trait takes at most one input, `(input: Input)`",
+                1,
+            )
+        };
+        if takes_context && inputs.is_empty() {
+            return Err(Error::new(handler.paren_token.span.join(), message));
+        }
+        if inputs.len() > max_inputs {
+            return Err(Error::new_spanned(inputs, message));
+        }
+        let input = match inputs.iter().nth(max_inputs - 1) {
+            None => None,
+            Some(FnArg::Typed(typed)) => match typed.pat.as_ref() {
+                Pat::Ident(ident) => Some((&ident.ident, typed.ty.as_ref())),
+                pat => {
+                    return Err(Error::new_spanned(
+                        pat,
+                        "the input of a handler is bound to an identifier",
+                    ));
                 }
+            },
+            Some(FnArg::Receiver(receiver)) => {
+                return Err(Error::new_spanned(receiver, "a handler does not take `self`"));
             }
-            _ => {
-                panic!("Only consts and fns");
+        };
+
+        let message = "a handler returns its result, `-> Result<Output, HandlerError>`";
+        let result = match &handler.output {
+            ReturnType::Type(_, result) => result.as_ref(),
+            ReturnType::Default => return Err(Error::new_spanned(handler, message)),
+        };
+        let output = match result {
+            Type::Path(path) => path
+                .path
+                .segments
+                .last()
+                .filter(|segment| segment.ident == "Result")
+                .and_then(|segment| match &segment.arguments {
+                    PathArguments::AngleBracketed(arguments) => arguments.args.first(),
+                    _ => None,
+                })
+                .and_then(|argument| match argument {
+                    GenericArgument::Type(output) => Some(output),
+                    _ => None,
+                }),
+            _ => None,
+        };
+        let output = output.ok_or_else(|| Error::new_spanned(result, message))?;
+        Ok(Self {
+            input,
+            result,
+            output,
+        })
+    }
+
+    fn input_type(&self) -> PayloadType<'a> {
+        PayloadType::of(self.input.map(|(_, ty)| ty))
+    }
+
+    fn output_type(&self) -> PayloadType<'a> {
+        PayloadType::of(Some(self.output))
+    }
+}
+
+/// Payload the input or the output of a handler is passed as, following from its type.
+#[derive(Clone, Copy)]
+enum PayloadType<'a> {
+    /// Encoded as JSON, the type implements `Payload` as it is or wrapped in `Json`
+    Json(&'a Type),
+    /// `Bytes` taken and returned as they are, passed as `Raw`
+    Raw,
+    /// No input, or `()` output, passed as `Empty`
+    Empty,
+}
+
+impl<'a> PayloadType<'a> {
+    fn of(ty: Option<&'a Type>) -> Self {
+        match ty {
+            None => PayloadType::Empty,
+            Some(Type::Tuple(tuple)) if tuple.elems.is_empty() => PayloadType::Empty,
+            Some(Type::Path(path))
+                if path.qself.is_none()
+                    && path
+                        .path
+                        .segments
+                        .last()
+                        .is_some_and(|segment| segment.ident == "Bytes" && segment.arguments.is_none()) =>
+            {
+                PayloadType::Raw
             }
+            Some(ty) => PayloadType::Json(ty),
         }
     }
-    let service_client = format_ident!("{}ClientImpl", service_name.to_string());
-    let service_client_ext = format_ident!("{}ClientExt", service_name.to_string());
-    let service_client_indent = service_name.to_string().to_case(Case::Snake);
-    let service_client_indent = format_ident!("{}_client", service_client_indent);
-    quote!(
-        pub struct #service_name;
-        #service
-        struct #service_client<'a> {
-            ctx: &'a WorkflowContext,
-        }
-        impl<'a> #service_client<'a> {
-           #(#methods)*
+
+    /// Type implementing `Payload`.
+    fn payload(self) -> proc_macro2::TokenStream {
+        match self {
+            PayloadType::Json(ty) => quote!(#ty),
+            PayloadType::Raw => quote!(restate_sdk_api::Raw),
+            PayloadType::Empty => quote!(restate_sdk_api::Empty),
         }
-        trait #service_client_ext {
-            fn #service_client_indent(&self) -> #service_client;
+    }
+
+    /// Announced in the manifest as the `Payload` of the type announces it.
+    fn input_payload(self) -> InputPayload {
+        match self {
+            PayloadType::Json(_) => InputPayload {
+                content_type: Some(JSON_CONTENT_TYPE.to_string()),
+                json_schema: None,
+                required: Some(true),
+            },
+            PayloadType::Raw => InputPayload {
+                content_type: Some("*/*".to_string()),
+                json_schema: None,
+                required: Some(false),
+            },
+            PayloadType::Empty => InputPayload {
+                content_type: None,
+                json_schema: None,
+                required: None,
+            },
         }
+    }
 
-        impl #service_client_ext for WorkflowContext {
-            fn #service_client_indent(&self) -> #service_client {
-                #service_client { ctx: &self }
-            }
+    fn output_payload(self) -> OutputPayload {
+        match self {
+            PayloadType::Json(_) => OutputPayload {
+                content_type: Some(JSON_CONTENT_TYPE.to_string()),
+                json_schema: None,
+                set_content_type_if_empty: None,
+            },
+            PayloadType::Raw => OutputPayload {
+                content_type: Some("application/octet-stream".to_string()),
+                json_schema: None,
+                set_content_type_if_empty: Some(false),
+            },
+            PayloadType::Empty => OutputPayload {
+                content_type: None,
+                json_schema: None,
+                set_content_type_if_empty: None,
+            },
         }
-    )
-    .into()
+    }
+}
 
-    /*
-    impl ServiceHandler for #service_name {
-            fn name(&self) -> &'static str {
-                Self::NAME
-            }
+/// Parameter of the handler registered for a handler function, receiving its input as a payload, and
+/// the arguments following the context in the call of the function.
+fn payload_arguments(signature: &HandlerSignature) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    match (signature.input, signature.input_type()) {
+        (Some(_), PayloadType::Json(_)) => (quote!(input), quote!(, input)),
+        (Some(_), PayloadType::Raw) => (quote!(input: restate_sdk_api::Raw), quote!(, input.0)),
+        (Some(_), PayloadType::Empty) => (quote!(_: restate_sdk_api::Empty), quote!(, ())),
+        (None, _) => (quote!(_: restate_sdk_api::Empty), quote!()),
+    }
+}
 
-            fn handlers(&self) -> &'static [&'static str] {
-                &["service", "greet"]
-            }
-        }
-     */
+/// Conversion of the result of a handler function to the payload returned by its registered handler.
+fn payload_output(signature: &HandlerSignature) -> proc_macro2::TokenStream {
+    match signature.output_type() {
+        PayloadType::Json(_) => quote!(),
+        PayloadType::Raw => quote!(.map(restate_sdk_api::Raw)),
+        PayloadType::Empty => quote!(.map(|()| restate_sdk_api::Empty)),
+    }
+}
+
+/// Handler registered for the handler function at the given path, the function itself when its input
+/// and output are encoded as JSON.
+fn payload_handler(
+    handler: proc_macro2::TokenStream,
+    signature: &HandlerSignature,
+) -> proc_macro2::TokenStream {
+    if let (Some(_), PayloadType::Json(_), PayloadType::Json(_)) =
+        (signature.input, signature.input_type(), signature.output_type())
+    {
+        return handler;
+    }
+    let (parameter, arguments) = payload_arguments(signature);
+    let output = payload_output(signature);
+    quote!(|ctx, #parameter| async move { #handler(ctx #arguments).await #output })
+}
+
+/// Call of a handler from a client method taking the input of the handler, which is converted to its
+/// payload as the output is converted from its payload. The key is the one of the called object or
+/// workflow, `None` for services.
+fn client_call(
+    service_literal: &str,
+    handler_literal: &str,
+    signature: &HandlerSignature,
+    key: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let input = signature.input_type();
+    let output = signature.output_type();
+    let parameter = match (signature.input, input) {
+        (Some((input, _)), PayloadType::Json(_)) => quote!(#input),
+        (Some((input, _)), PayloadType::Raw) => quote!(restate_sdk_api::Raw(#input)),
+        (Some((input, _)), PayloadType::Empty) => quote!({
+            let _ = #input;
+            restate_sdk_api::Empty
+        }),
+        (None, _) => quote!(restate_sdk_api::Empty),
+    };
+    let (input, output_payload) = (input.payload(), output.payload());
+    let call = quote!(
+        restate_sdk_api::ContextBase::call::<#input, #output_payload>(
+            self.ctx,
+            #service_literal.to_string(),
+            #handler_literal.to_string(),
+            #parameter,
+            #key,
+            None,
+        )
+    );
+    match output {
+        PayloadType::Json(_) => call,
+        PayloadType::Raw => quote!(restate_sdk_api::MapOutput::new(
+            #call,
+            |result: Result<restate_sdk_api::Raw, restate_sdk_api::Error>| result.map(|output| output.0),
+        )),
+        PayloadType::Empty => quote!(restate_sdk_api::MapOutput::new(
+            #call,
+            |result: Result<restate_sdk_api::Empty, restate_sdk_api::Error>| result.map(|_| ()),
+        )),
+    }
 }
 
 #[proc_macro_attribute]
@@ -436,78 +1044,69 @@ pub fn handler(args: TokenStream, item: TokenStream) -> TokenStream {
     .into()
 }
 
-fn create_service_client_fn(service: proc_macro2::Ident, handler: &ImplItemFn) -> proc_macro2::TokenStream {
-    let mut client_fn = handler.clone();
-    client_fn.attrs.clear();
-    let mut signature = &mut client_fn.sig;
-    let return_type = match &signature.output {
-        ReturnType::Default => quote_spanned!(signature.paren_token.span => ()),
-        ReturnType::Type(_, ret) => quote!(#ret),
-    };
-    signature.output = parse_quote_spanned! {return_type.span() =>
-        -> impl ::std::future::Future<Output = #return_type> + JournalIndex + 'a
+/// Method of the client invoking the handler registered with the given names, on the key held by the
+/// client for objects and workflows.
+fn create_service_client_fn(
+    service_type: ServiceType,
+    service_literal: &str,
+    handler: &ImplItemFn,
+    handler_literal: &str,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let signature = HandlerSignature::parse(&handler.sig, true)?;
+    let vis = &handler.vis;
+    let method = &handler.sig.ident;
+    let input = signature.input.map(|(input, ty)| quote!(#input: #ty));
+    let result = signature.result;
+    let key = match service_type {
+        ServiceType::Service => quote!(None),
+        ServiceType::VirtualObject | ServiceType::Workflow => quote!(Some(self.key)),
     };
-    signature.asyncness = None;
-    let first = signature.inputs.first_mut().unwrap();
-    *first = FnArg::Receiver(Receiver {
-        attrs: vec![],
-        reference: None,
-        mutability: None,
-        self_token: Default::default(),
-        colon_token: None,
-        ty: Box::new(Type::Verbatim(quote!(Self))),
-    });
-    let method = &signature.ident;
-    let last = signature.inputs.last().unwrap();
-    let parameter = match last {
-        FnArg::Receiver(_) => {
-            panic!("There should be input");
+    let call = client_call(service_literal, handler_literal, &signature, key);
+    Ok(quote!(
+        #vis fn #method(
+            self,
+            #input
+        ) -> impl restate_sdk_api::DurableFuture<Output = #result> + restate_sdk_api::JournalIndex + 'a {
+            #call
         }
-        FnArg::Typed(typed) => match *typed.pat {
-            Pat::Ident(ref ident) => &ident.ident,
-            _ => {
-                panic!("There should be input");
-            }
-        },
-    };
-
-    let service_literal = service.to_string();
-    let method_literal = method.to_string();
-    let stmts: Vec<Stmt> = parse_quote!(
-        self.ctx
-            .invoke(
-                #service::#method,
-                #service_literal.to_string(),
-                #method_literal.to_string(),
-                #parameter,
-                None,
-                None,
-            )
-    );
-
-    client_fn.block = Block {
-        brace_token: Brace::default(),
-        stmts,
-    };
-
-    quote! (
-        #client_fn
-    )
-    .into()
+    ))
 }
 
-fn find_attribute(names: &[&'static str], attrs: &Vec<Attribute>) -> Option<String> {
-    attrs
+/// Doc comments of an item, announced as its documentation in the manifest.
+fn documentation(attrs: &[Attribute]) -> Option<String> {
+    let lines = attrs
         .iter()
-        .map(|attribute| {
-            attribute
-                .meta
-                .path()
-                .segments
-                .iter()
-                .map(|s| s.ident.to_string())
-                .collect::<Vec<_>>()
-                .join("::")
+        .filter_map(|attribute| match &attribute.meta {
+            Meta::NameValue(doc) if doc.path.is_ident("doc") => match &doc.value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(line), ..
+                }) => Some(line.value()),
+                _ => None,
+            },
+            _ => None,
         })
-        .find(|attribute| names.iter().any(|name| attribute.eq(name)))
+        .map(|line| line.strip_prefix(' ').map(str::to_string).unwrap_or(line))
+        .collect::<Vec<_>>();
+    let documentation = lines.join("\n").trim().to_string();
+    (!documentation.is_empty()).then_some(documentation)
+}
+
+fn find_attribute<'a>(
+    names: &[&'static str],
+    attrs: &'a [Attribute],
+) -> Option<(&'static str, &'a Attribute)> {
+    attrs.iter().find_map(|attribute| {
+        let path = attribute
+            .meta
+            .path()
+            .segments
+            .iter()
+            .map(|s| s.ident.to_string())
+            .collect::<Vec<_>>()
+            .join("::");
+        names
+            .iter()
+            .find(|name| path.eq(*name))
+            .map(|name| (*name, attribute))
+    })
 }
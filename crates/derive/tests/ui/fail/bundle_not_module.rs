@@ -0,0 +1,6 @@
+use restate_sdk_api as restate;
+
+#[restate::bundle]
+fn bundle() {}
+
+fn main() {}
@@ -0,0 +1,13 @@
+use restate_sdk_api as restate;
+
+#[restate::bundle]
+mod bundle {
+    use restate_sdk_api::{self as restate, HandlerError};
+
+    #[restate::service]
+    pub trait Greeter {
+        async fn greet(name: String) -> Result<String, HandlerError>;
+    }
+}
+
+fn main() {}
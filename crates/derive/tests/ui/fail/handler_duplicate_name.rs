@@ -0,0 +1,16 @@
+use restate_sdk_api as restate;
+
+#[restate::service]
+impl Greeter {
+    #[restate::handler]
+    async fn greet(ctx: Context, name: String) -> Result<String, anyhow::Error> {
+        Ok(name)
+    }
+
+    #[restate::handler(name = "greet")]
+    async fn greet_politely(ctx: Context, name: String) -> Result<String, anyhow::Error> {
+        Ok(name)
+    }
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use restate_sdk_api as restate;
+
+#[restate::service]
+impl Greeter {
+    #[restate::handler]
+    fn greet(ctx: Context, name: String) -> Result<String, anyhow::Error> {
+        Ok(name)
+    }
+}
+
+fn main() {}
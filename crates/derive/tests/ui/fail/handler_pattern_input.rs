@@ -0,0 +1,11 @@
+use restate_sdk_api as restate;
+
+#[restate::service]
+impl Greeter {
+    #[restate::handler]
+    async fn greet(ctx: Context, (first, last): (String, String)) -> Result<String, anyhow::Error> {
+        Ok(first + &last)
+    }
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use restate_sdk_api as restate;
+
+#[restate::object]
+impl Counter {
+    #[restate::handler(exclusive)]
+    async fn add(ctx: ObjectContext, value: u64) -> Result<u64, anyhow::Error> {
+        Ok(value)
+    }
+}
+
+fn main() {}
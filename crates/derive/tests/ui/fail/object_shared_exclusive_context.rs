@@ -0,0 +1,11 @@
+use restate_sdk_api as restate;
+
+#[restate::object]
+impl Counter {
+    #[restate::handler(shared)]
+    async fn get(ctx: ObjectContext, default: u64) -> Result<u64, anyhow::Error> {
+        Ok(default)
+    }
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use restate_sdk_api::{self as restate, KeyValueStore, ObjectContext, ObjectSharedContext};
+
+#[restate::object]
+impl Counter {
+    #[restate::handler(shared)]
+    async fn reset(ctx: ObjectSharedContext, value: u64) -> Result<u64, anyhow::Error> {
+        ctx.clear_all().await;
+        Ok(value)
+    }
+}
+
+fn main() {}
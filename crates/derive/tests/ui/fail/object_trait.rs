@@ -0,0 +1,8 @@
+use restate_sdk_api as restate;
+
+#[restate::object]
+pub trait Counter {
+    async fn add(value: u64) -> Result<u64, anyhow::Error>;
+}
+
+fn main() {}
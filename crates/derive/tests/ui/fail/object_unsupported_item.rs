@@ -0,0 +1,13 @@
+use restate_sdk_api as restate;
+
+#[restate::object]
+impl Counter {
+    type State = u64;
+
+    #[restate::handler]
+    async fn add(ctx: ObjectContext, value: u64) -> Result<u64, anyhow::Error> {
+        Ok(value)
+    }
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use restate_sdk_api as restate;
+
+#[restate::service]
+impl __Greeter {
+    #[restate::handler]
+    async fn greet(ctx: Context, name: String) -> Result<String, anyhow::Error> {
+        Ok(name)
+    }
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use restate_sdk_api as restate;
+
+#[restate::service(name = "greeter/v2")]
+impl Greeter {
+    #[restate::handler]
+    async fn greet(ctx: Context, name: String) -> Result<String, anyhow::Error> {
+        Ok(name)
+    }
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use restate_sdk_api as restate;
+
+#[restate::service]
+trait Greeter {
+    async fn greet(name: String) -> Result<String, restate::HandlerError> {
+        Ok(name)
+    }
+}
+
+fn main() {}
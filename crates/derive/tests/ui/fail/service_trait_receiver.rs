@@ -0,0 +1,8 @@
+use restate_sdk_api as restate;
+
+#[restate::service]
+trait Greeter {
+    async fn greet(&self, name: String) -> Result<String, restate::HandlerError>;
+}
+
+fn main() {}
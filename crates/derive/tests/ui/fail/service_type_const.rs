@@ -0,0 +1,13 @@
+use restate_sdk_api as restate;
+
+#[restate::workflow]
+impl Signup {
+    const TYPE: &'static str = "WORKFLOW";
+
+    #[restate::handler(workflow)]
+    async fn run(ctx: WorkflowContext, email: String) -> Result<bool, anyhow::Error> {
+        Ok(true)
+    }
+}
+
+fn main() {}
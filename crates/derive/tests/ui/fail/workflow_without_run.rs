@@ -0,0 +1,11 @@
+use restate_sdk_api as restate;
+
+#[restate::workflow]
+impl Signup {
+    #[restate::handler]
+    async fn run(ctx: WorkflowContext, email: String) -> Result<bool, anyhow::Error> {
+        Ok(true)
+    }
+}
+
+fn main() {}
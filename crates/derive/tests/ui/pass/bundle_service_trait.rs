@@ -0,0 +1,49 @@
+use restate_sdk_api::{self as restate, endpoint, BodyExt, Request, RestateEndpointOptions, ServiceRegistry};
+
+#[restate::bundle]
+mod bundle {
+    use restate_sdk_api::{self as restate, Context, HandlerError};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[restate::service]
+    pub trait Greeter {
+        async fn greet(name: String) -> Result<String, HandlerError>;
+
+        async fn greeted() -> Result<u64, HandlerError>;
+    }
+
+    #[derive(Default)]
+    pub struct CountingGreeter {
+        greeted: AtomicU64,
+    }
+
+    impl Greeter for CountingGreeter {
+        async fn greet(&self, _ctx: Context, name: String) -> Result<String, HandlerError> {
+            self.greeted.fetch_add(1, Ordering::Relaxed);
+            Ok(format!("Greetings {}", name))
+        }
+
+        async fn greeted(&self, _ctx: Context) -> Result<u64, HandlerError> {
+            Ok(self.greeted.load(Ordering::Relaxed))
+        }
+    }
+
+    #[restate::service]
+    impl Concierge {
+        #[restate::handler]
+        pub async fn welcome(ctx: Context, name: String) -> Result<String, anyhow::Error> {
+            Ok(ctx.greeter_client().greet(name).await?)
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let _ = endpoint(RestateEndpointOptions::default(), services());
+
+    let registry = ServiceRegistry::new(services()).unwrap();
+    let request = Request::get("/discover").body(String::new()).unwrap();
+    let body = registry.handle(request).into_body().collect().await.unwrap().to_bytes();
+    let manifest = String::from_utf8(body.to_vec()).unwrap();
+    assert!(manifest.contains("\"Greeter\"") && manifest.contains("\"Concierge\""));
+}
@@ -0,0 +1,86 @@
+use restate_sdk_api::{
+    self as restate, Bytes, Context, HandlerError, Json, KeyValueStore, KeyValueStoreReadOnly,
+    ObjectContext, ObjectSharedContext, RestateEndpoint,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct Delivery {
+    pub id: String,
+}
+
+#[restate::service]
+impl Webhook {
+    /// Receives the body of the webhook byte for byte
+    #[restate::handler]
+    pub async fn receive(ctx: Context, body: Bytes) -> Result<Bytes, anyhow::Error> {
+        let delivery = ctx.webhook_client().parse(body.clone()).await?;
+        ctx.webhook_client().record(Json(delivery)).await?;
+        ctx.webhook_client().ping().await?;
+        Ok(body)
+    }
+
+    #[restate::handler]
+    pub async fn parse(ctx: Context, body: Bytes) -> Result<Delivery, anyhow::Error> {
+        Ok(Delivery {
+            id: String::from_utf8(body.to_vec())?,
+        })
+    }
+
+    #[restate::handler]
+    pub async fn record(ctx: Context, delivery: Json<Delivery>) -> Result<Json<String>, anyhow::Error> {
+        Ok(Json(delivery.0.id))
+    }
+
+    #[restate::handler]
+    pub async fn ping(ctx: Context) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+#[restate::object]
+impl Inbox {
+    #[restate::handler]
+    pub async fn clear(ctx: ObjectContext) -> Result<(), anyhow::Error> {
+        ctx.clear("body").await;
+        Ok(())
+    }
+
+    #[restate::handler(shared)]
+    pub async fn body(ctx: ObjectSharedContext) -> Result<Bytes, anyhow::Error> {
+        let body = ctx.get::<String, _>("body").await.unwrap_or_default();
+        Ok(Bytes::from(body))
+    }
+}
+
+#[restate::service]
+pub trait Archive {
+    async fn store(body: Bytes) -> Result<(), HandlerError>;
+
+    async fn size() -> Result<u64, HandlerError>;
+}
+
+struct MemoryArchive;
+
+impl Archive for MemoryArchive {
+    async fn store(&self, ctx: Context, body: Bytes) -> Result<(), HandlerError> {
+        if body.is_empty() {
+            ctx.archive_client().size().await?;
+        }
+        Ok(())
+    }
+
+    async fn size(&self, ctx: Context) -> Result<u64, HandlerError> {
+        ctx.archive_client().store(Bytes::new()).await?;
+        Ok(0)
+    }
+}
+
+fn main() {
+    RestateEndpoint::builder()
+        .bind(Webhook)
+        .bind(Inbox)
+        .bind(MemoryArchive.serve())
+        .build()
+        .unwrap();
+}
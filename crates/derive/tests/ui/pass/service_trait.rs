@@ -0,0 +1,56 @@
+use restate_sdk_api::{self as restate, Context, HandlerError, RestateEndpoint};
+
+/// Greets people
+#[restate::service]
+pub trait Greeter {
+    /// Greets a person by name
+    async fn greet(name: String) -> Result<String, HandlerError>;
+
+    #[restate::handler(name = "greetAll", ingress_private)]
+    async fn greet_all(names: Vec<String>) -> Result<Vec<String>, HandlerError>;
+}
+
+#[restate::service(name = "Concierge")]
+pub trait Welcome {
+    async fn welcome(name: String) -> Result<String, HandlerError>;
+}
+
+struct GreeterImpl;
+
+impl Greeter for GreeterImpl {
+    async fn greet(&self, _ctx: Context, name: String) -> Result<String, HandlerError> {
+        Ok(format!("Greetings {}", name))
+    }
+
+    async fn greet_all(&self, ctx: Context, names: Vec<String>) -> Result<Vec<String>, HandlerError> {
+        let mut greetings = vec![];
+        for name in names {
+            greetings.push(ctx.greeter_client().greet(name).await?);
+        }
+        Ok(greetings)
+    }
+}
+
+struct WelcomeImpl {
+    prefix: String,
+}
+
+impl Welcome for WelcomeImpl {
+    async fn welcome(&self, ctx: Context, name: String) -> Result<String, HandlerError> {
+        let greeting = ctx.greeter_client().greet(name).await?;
+        Ok(format!("{} {}", self.prefix, greeting))
+    }
+}
+
+fn main() {
+    RestateEndpoint::builder()
+        .bind(GreeterImpl.serve())
+        .bind(
+            WelcomeImpl {
+                prefix: "Welcome!".to_string(),
+            }
+            .serve(),
+        )
+        .build()
+        .unwrap();
+}
@@ -0,0 +1,55 @@
+use restate_sdk_api::{
+    self as restate, Context, ContextBase, JournalIndex, KeyValueStore, KeyValueStoreReadOnly, ObjectContext,
+    ObjectSharedContext, WorkflowContext,
+};
+
+#[restate::service]
+/// Greets people
+impl Greeter {
+    /// Greets a person by name
+    #[restate::handler]
+    pub async fn greet(ctx: Context, name: String) -> Result<String, anyhow::Error> {
+        Ok(format!("Greetings {}", name))
+    }
+
+    fn helper() {}
+}
+
+#[restate::object(name = "counter")]
+impl Counter {
+    #[restate::handler(name = "increment")]
+    pub async fn add(ctx: ObjectContext, value: u64) -> Result<u64, anyhow::Error> {
+        let count = ctx.get::<u64, _>("count").await.unwrap_or_default() + value;
+        ctx.set("count", count).await;
+        Ok(count)
+    }
+
+    /// Moves the count to the counter of another key
+    #[restate::handler]
+    pub async fn transfer(ctx: ObjectContext, to: String) -> Result<u64, anyhow::Error> {
+        let count = ctx.get::<u64, _>("count").await.unwrap_or_default();
+        ctx.clear("count").await;
+        ctx.counter_client(to).add(count).await
+    }
+
+    #[restate::handler(shared)]
+    pub async fn get(ctx: ObjectSharedContext, default: u64) -> Result<u64, anyhow::Error> {
+        Ok(ctx.get::<u64, _>("count").await.unwrap_or(default))
+    }
+}
+
+#[restate::workflow(ingress_private)]
+impl Signup {
+    #[restate::handler(workflow)]
+    pub async fn register(ctx: WorkflowContext, email: String) -> Result<bool, anyhow::Error> {
+        Ok(!email.is_empty())
+    }
+
+    #[restate::handler(shared, ingress_private)]
+    pub async fn confirm(ctx: WorkflowContext, code: String) -> Result<(), anyhow::Error> {
+        ctx.signup_client(code).register("signup@example.com".to_string()).await?;
+        Ok(())
+    }
+}
+
+fn main() {}
@@ -1,9 +1,10 @@
-use crate::{context::JournalIndex, machine::StateMachine, syscall::SleepFuture};
+use crate::{
+    context::JournalIndex, machine::StateMachine, protocol::COMBINATOR_ENTRY_CODE, syscall::SleepFuture,
+};
 use anyhow::anyhow;
-use parking_lot::{Mutex, MutexGuard};
 use prost::Message;
 use restate_sdk_types::{
-    journal::{Entry, SleepEntry},
+    journal::{CustomEntry, Entry, SleepEntry},
     service_protocol::CombinatorEntryMessage,
 };
 use std::{
@@ -23,7 +24,6 @@ pub struct Timeout<F>
 where
     F: Future + JournalIndex,
 {
-    state_machine: Arc<Mutex<StateMachine>>,
     entry_index: Arc<AtomicU32>,
     polled: Arc<AtomicBool>,
     #[pin]
@@ -36,32 +36,23 @@ impl<F> Timeout<F>
 where
     F: Future + JournalIndex,
 {
-    pub fn new(state_machine: Arc<Mutex<StateMachine>>, timeout_millis: u64, future: F) -> Self {
+    pub fn new(timeout_millis: u64, future: F) -> Self {
         let wake_up_time = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .expect("Time went backwards");
         let wake_up_time = wake_up_time.as_millis() as u64 + timeout_millis;
         info!("Context sleep: Wake up time {}", wake_up_time);
-        let timer = SleepFuture::new(
-            None,
-            SleepEntry {
-                wake_up_time,
-                result: None,
-            },
-            state_machine.clone(),
-        );
+        let timer = SleepFuture::new(None, SleepEntry {
+            wake_up_time,
+            result: None,
+        });
         Self {
-            state_machine,
             entry_index: Arc::new(AtomicU32::new(0)),
             polled: Arc::new(AtomicBool::new(false)),
             future,
             timer,
         }
     }
-
-    fn set_span(&self, mut state_machine: MutexGuard<'_, StateMachine>) {
-        state_machine.set_span()
-    }
 }
 
 impl<F> Future for Timeout<F>
@@ -78,49 +69,51 @@ where
         if result.is_pending() && timeout.is_pending() {
             Poll::Pending
         } else {
-            let mut state_machine = selfp.state_machine.lock();
-            let future_index = selfp.future.entry_index();
-            let timer_index = selfp.timer.entry_index();
+            StateMachine::with(|state_machine| {
+                let future_index = selfp.future.entry_index();
+                let timer_index = selfp.timer.entry_index();
 
-            let entry_index = if selfp.polled.fetch_or(true, Ordering::Relaxed) {
-                Some(selfp.entry_index.load(Ordering::Relaxed))
-            } else {
-                None
-            };
+                let entry_index = if selfp.polled.fetch_or(true, Ordering::Relaxed) {
+                    Some(selfp.entry_index.load(Ordering::Relaxed))
+                } else {
+                    None
+                };
 
-            let journal_entries_order = if result.is_pending() && timeout.is_ready() {
-                debug!("Timeout for timer entry: {:?}", timer_index);
-                //vec![timer_index as i32, future_index as i32]
-                vec![timer_index as i32]
-            } else {
-                vec![future_index as i32, timer_index as i32]
-            };
+                let journal_entries_order = if result.is_pending() && timeout.is_ready() {
+                    debug!("Timeout for timer entry: {:?}", timer_index);
+                    //vec![timer_index as i32, future_index as i32]
+                    vec![timer_index as i32]
+                } else {
+                    vec![future_index as i32, timer_index as i32]
+                };
 
-            let entry = Entry::Custom(
-                CombinatorEntryMessage {
-                    combinator_id: 0,
-                    journal_entries_order,
-                }
-                .encode_to_vec()
-                .into(),
-            );
+                let entry = Entry::Custom(CustomEntry {
+                    code: COMBINATOR_ENTRY_CODE,
+                    value: CombinatorEntryMessage {
+                        combinator_id: 0,
+                        journal_entries_order,
+                    }
+                    .encode_to_vec()
+                    .into(),
+                });
 
-            let (entry_index, done) =
-                state_machine.write_combinator_order(entry_index, entry, cx.waker().clone());
-            if let Some(_) = done {
-                debug!("Timeout Result ready for entry: {}", entry_index);
-                //selfp.set_span(state_machine);
-                if timeout.is_ready() {
-                    Poll::Ready(Err(anyhow!("Timeout")))
+                let (entry_index, done) =
+                    state_machine.write_combinator_order(entry_index, entry, cx.waker().clone());
+                if let Some(_) = done {
+                    debug!("Timeout Result ready for entry: {}", entry_index);
+                    //selfp.set_span(state_machine);
+                    if timeout.is_ready() {
+                        Poll::Ready(Err(anyhow!("Timeout")))
+                    } else {
+                        result.map(|result| Ok(result))
+                    }
                 } else {
-                    result.map(|result| Ok(result))
+                    debug!("Timeout Result pending for entry: {}", entry_index);
+                    selfp.entry_index.store(entry_index, Ordering::Relaxed);
+                    state_machine.abort_on_replay();
+                    Poll::Pending
                 }
-            } else {
-                debug!("Timeout Result pending for entry: {}", entry_index);
-                selfp.entry_index.store(entry_index, Ordering::Relaxed);
-                state_machine.abort_on_replay();
-                Poll::Pending
-            }
+            })
         }
     }
 }
@@ -1,34 +1,142 @@
-use anyhow::anyhow;
+use crate::metrics::{InvocationTarget, Metrics, METRICS};
 use bytes::Bytes;
-use futures::{pin_mut, Stream};
+use futures::{pin_mut, task::AtomicWaker, Stream};
 use futures_util::{StreamExt, TryStreamExt};
-use http::Request;
-use http_body::Frame;
+use http::{Request, Version};
+use http_body::{Body, Frame};
 use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full, StreamBody};
 use prost::Message;
-use restate_sdk_types::service_protocol::ServiceProtocolVersion;
-use restate_service_protocol::message::{Decoder, Encoder, MessageType, ProtocolMessage};
-use std::{collections::VecDeque, future::Future};
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use restate_sdk_types::{
+    endpoint_manifest::ProtocolMode,
+    errors::{codes, InvocationError},
+    service_protocol::ServiceProtocolVersion,
+};
+use restate_service_protocol::message::{
+    Encoder, EncodingError, MessageType, ProtocolCodec, ProtocolMessage,
+};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+use tokio::sync::mpsc::{Receiver, UnboundedReceiver, UnboundedSender};
 use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_util::{codec::FramedRead, io::StreamReader, sync::CancellationToken};
 use tracing::debug;
 
 pub(crate) trait Sealed {}
 
+/// Limits applied to a single invocation connection.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionOptions {
+    /// Capacity of the inbound and outbound message buffers. Reading from the request body pauses when
+    /// the inbound buffer is full, and the handler pauses when the response is not read fast enough.
+    pub channel_capacity: usize,
+    /// Messages larger than this size are logged.
+    pub message_size_warning: usize,
+    /// Messages larger than this size fail the invocation before they are buffered, 32 MiB by default in
+    /// line with the runtime. `None` lifts the limit.
+    pub message_size_limit: Option<usize>,
+    /// Maximum number of journal entries, including the replayed ones, an invocation can have.
+    pub max_journal_length: Option<u32>,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        Self {
+            channel_capacity: 64,
+            message_size_warning: 10 * 1024 * 1024,
+            message_size_limit: Some(32 * 1024 * 1024),
+            max_journal_length: None,
+        }
+    }
+}
+
+/// Shutdown of the endpoint serving an invocation.
+#[derive(Debug, Clone, Default)]
+pub struct Draining {
+    /// Cancelled once the endpoint stops accepting connections, the invocation suspends as soon as it
+    /// awaits a completion.
+    pub started: CancellationToken,
+    /// Cancelled once the drain timeout elapsed, the invocation suspends right away.
+    pub expired: CancellationToken,
+}
+
 pub trait MessageReceiver: Sealed + Send {
-    fn recv(&mut self) -> impl Future<Output = Option<(MessageType, ProtocolMessage)>> + Send;
+    fn recv(
+        &mut self,
+    ) -> impl Future<Output = Option<Result<(MessageType, ProtocolMessage), InvocationError>>> + Send;
+
+    fn options(&self) -> ConnectionOptions;
+
+    /// With [`ProtocolMode::RequestResponse`] the request carries all the messages of the runtime, the
+    /// invocation suspends once it awaits a completion.
+    fn protocol_mode(&self) -> ProtocolMode;
+
+    fn draining(&self) -> Draining;
+
+    /// Service and handler of the invocation, labelling its metrics.
+    fn target(&self) -> InvocationTarget;
+
+    /// Metrics fed by the invocation.
+    fn metrics(&self) -> &'static Metrics {
+        &METRICS
+    }
 }
 
 pub trait MessageSender: Sealed + Send {
     fn send(&self, message: ProtocolMessage);
+
+    /// Ready once the outbound buffer has room for more messages.
+    fn poll_ready(&self, _cx: &mut Context<'_>) -> Poll<()> {
+        Poll::Ready(())
+    }
 }
 
 pub trait RestateStreamConsumer {
-    fn handle_message(&mut self, message: (MessageType, ProtocolMessage)) -> bool;
+    /// Handles the next inbound message, returns `true` once the consumer expects no further messages.
+    fn handle_message(&mut self, message: (MessageType, ProtocolMessage)) -> Result<bool, InvocationError>;
 }
 
 pub struct MockHttp2Receiver {
     inbound_rx: VecDeque<(Option<String>, MessageType, ProtocolMessage)>,
+    options: ConnectionOptions,
+    protocol_mode: ProtocolMode,
+    draining: Draining,
+    target: InvocationTarget,
+    metrics: &'static Metrics,
+}
+
+impl MockHttp2Receiver {
+    pub fn with_options(mut self, options: ConnectionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_protocol_mode(mut self, protocol_mode: ProtocolMode) -> Self {
+        self.protocol_mode = protocol_mode;
+        self
+    }
+
+    pub fn with_draining(mut self, draining: Draining) -> Self {
+        self.draining = draining;
+        self
+    }
+
+    pub fn with_target(mut self, target: InvocationTarget) -> Self {
+        self.target = target;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: &'static Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
 }
 
 pub struct MockHttp2Sender {
@@ -38,8 +146,30 @@ pub struct MockHttp2Sender {
 impl Sealed for MockHttp2Receiver {}
 
 impl MessageReceiver for MockHttp2Receiver {
-    async fn recv(&mut self) -> Option<(MessageType, ProtocolMessage)> {
-        self.inbound_rx.pop_front().map(|message| (message.1, message.2))
+    async fn recv(&mut self) -> Option<Result<(MessageType, ProtocolMessage), InvocationError>> {
+        self.inbound_rx
+            .pop_front()
+            .map(|message| Ok((message.1, message.2)))
+    }
+
+    fn options(&self) -> ConnectionOptions {
+        self.options
+    }
+
+    fn protocol_mode(&self) -> ProtocolMode {
+        self.protocol_mode
+    }
+
+    fn draining(&self) -> Draining {
+        self.draining.clone()
+    }
+
+    fn target(&self) -> InvocationTarget {
+        self.target.clone()
+    }
+
+    fn metrics(&self) -> &'static Metrics {
+        self.metrics
     }
 }
 
@@ -61,80 +191,175 @@ pub fn setup_mock_connection(
 ) {
     let (outbound_tx, outbound_rx) = tokio::sync::mpsc::unbounded_channel();
     (
-        MockHttp2Receiver { inbound_rx },
+        MockHttp2Receiver {
+            inbound_rx,
+            options: ConnectionOptions::default(),
+            protocol_mode: ProtocolMode::BidiStream,
+            draining: Draining::default(),
+            target: InvocationTarget::default(),
+            metrics: &METRICS,
+        },
         MockHttp2Sender { outbound_tx },
         outbound_rx,
     )
 }
 
 pub struct Http2Receiver {
-    inbound_rx: UnboundedReceiver<(MessageType, ProtocolMessage)>,
+    inbound_rx: Receiver<Result<(MessageType, ProtocolMessage), InvocationError>>,
+    options: ConnectionOptions,
+    protocol_mode: ProtocolMode,
+    draining: Draining,
+    target: InvocationTarget,
 }
 
 pub struct Http2Sender {
     outbound_tx: UnboundedSender<ProtocolMessage>,
+    buffer: Arc<OutboundBuffer>,
+    capacity: usize,
+}
+
+/// Messages sent on the response and not read yet.
+#[derive(Default)]
+struct OutboundBuffer {
+    pending: AtomicUsize,
+    waker: AtomicWaker,
+}
+
+/// Held by the response body, wakes up the sender once the body is dropped.
+struct OutboundReader(Arc<OutboundBuffer>);
+
+impl OutboundReader {
+    fn read(&self) {
+        self.0.pending.fetch_sub(1, Ordering::AcqRel);
+        self.0.waker.wake();
+    }
+}
+
+impl Drop for OutboundReader {
+    fn drop(&mut self) {
+        self.0.waker.wake();
+    }
 }
 
 impl Sealed for Http2Receiver {}
 
 impl MessageReceiver for Http2Receiver {
-    async fn recv(&mut self) -> Option<(MessageType, ProtocolMessage)> {
+    async fn recv(&mut self) -> Option<Result<(MessageType, ProtocolMessage), InvocationError>> {
         self.inbound_rx.recv().await
     }
+
+    fn options(&self) -> ConnectionOptions {
+        self.options
+    }
+
+    fn protocol_mode(&self) -> ProtocolMode {
+        self.protocol_mode
+    }
+
+    fn draining(&self) -> Draining {
+        self.draining.clone()
+    }
+
+    fn target(&self) -> InvocationTarget {
+        self.target.clone()
+    }
 }
 
 impl Sealed for Http2Sender {}
 
 impl MessageSender for Http2Sender {
     fn send(&self, message: ProtocolMessage) {
+        // Counted before it can be read
+        self.buffer.pending.fetch_add(1, Ordering::AcqRel);
         if let Err(err) = self.outbound_tx.send(message) {
+            self.buffer.pending.fetch_sub(1, Ordering::AcqRel);
             debug!("Outbound send error: {}", err);
         }
     }
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.buffer.waker.register(cx.waker());
+        // Nothing is read anymore once the response is dropped, the messages sent are discarded
+        if self.buffer.pending.load(Ordering::Acquire) < self.capacity || self.outbound_tx.is_closed() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
 }
 
-pub fn setup_connection(
-    request: Request<hyper::body::Incoming>,
-) -> (Http2Receiver, Http2Sender, BoxBody<Bytes, anyhow::Error>) {
-    // Setup inbound message buffer
-    let frame_stream = http_body_util::BodyStream::new(
-        request
-            .into_body()
-            .map_frame(move |frame| frame)
-            .map_err(|_e| anyhow!("error"))
-            .boxed(),
+/// Sets up the message streams of an invocation request, using the [`ConnectionOptions`] and the
+/// [`Draining`] signal found in the request extensions.
+///
+/// Requests prior to HTTP/2 cannot stream in both directions, they are served in request-response mode.
+pub fn setup_connection<B>(request: Request<B>) -> (Http2Receiver, Http2Sender, BoxBody<Bytes, anyhow::Error>)
+where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let options = request
+        .extensions()
+        .get::<ConnectionOptions>()
+        .copied()
+        .unwrap_or_default();
+    let draining = request
+        .extensions()
+        .get::<Draining>()
+        .cloned()
+        .unwrap_or_default();
+    let protocol_mode = protocol_mode(request.version());
+    let target = InvocationTarget::from_path(request.uri().path());
+
+    // Setup inbound message stream
+    let data_stream = http_body_util::BodyStream::new(request.into_body().map_err(io::Error::other))
+        .try_filter_map(|frame| futures::future::ready(Ok(frame.into_data().ok())));
+    let message_stream = FramedRead::new(
+        StreamReader::new(data_stream),
+        ProtocolCodec::new(
+            ServiceProtocolVersion::V1,
+            options.message_size_warning,
+            options.message_size_limit,
+        ),
     );
 
-    let (inbound_tx, mut inbound_rx) = tokio::sync::mpsc::unbounded_channel();
+    // The inbound buffer is bounded, so that a slow invocation applies backpressure on the request body
+    let (inbound_tx, inbound_rx) = tokio::sync::mpsc::channel(options.channel_capacity);
     tokio::spawn(async move {
-        let mut decoder = Decoder::new(ServiceProtocolVersion::V1, usize::MAX, None);
-        pin_mut!(frame_stream);
-        while let Some(Ok(frame)) = frame_stream.next().await {
-            if let Ok(data) = frame.into_data() {
-                decoder.push(data);
-                match decoder.consume_next() {
-                    Ok(result) => {
-                        if let Some((header, message)) = result {
-                            //info!("Header: {:?}, Message: {:?}", header, message);
-                            if let Err(err) = inbound_tx.send((header.message_type(), message)) {
-                                debug!("Send failed {}", err);
-                            }
-                        }
-                    }
-                    Err(err) => {
-                        debug!("decode error: {:?}", err);
-                    }
+        pin_mut!(message_stream);
+        while let Some(result) = message_stream.next().await {
+            let message = match result {
+                Ok((header, message)) => Ok((header.message_type(), message)),
+                Err(EncodingError::Io(err)) => {
+                    debug!("HTTP request stream error: {}", err);
+                    break;
+                }
+                Err(err) => {
+                    debug!("Decode error: {:?}", err);
+                    Err(match err {
+                        EncodingError::MessageSizeLimit(..) => InvocationError::internal(err),
+                        err => InvocationError::new(codes::PROTOCOL_VIOLATION, err),
+                    })
                 }
             };
+            if let Err(err) = inbound_tx.send(message).await {
+                debug!("Send failed {}", err);
+                break;
+            }
         }
         debug!("HTTP request stream closed");
     });
 
     // Setup outbound message buffer
+    // Outbound messages are produced synchronously by the state machine, the handler is only polled while
+    // fewer than `channel_capacity` of them are waiting to be read, so that a slow reader of the response
+    // applies backpressure on the handler
     let (outbound_tx, outbound_rx) = tokio::sync::mpsc::unbounded_channel();
+    let buffer = Arc::new(OutboundBuffer::default());
+    let reader = OutboundReader(buffer.clone());
     let encoder = Encoder::new(ServiceProtocolVersion::V1);
     let boxed_body = BodyExt::boxed(StreamBody::new(UnboundedReceiverStream::new(outbound_rx).map(
         move |message| {
+            reader.read();
             debug!("Sending response message: {:?}", message);
             let result = encoder.encode(message);
             Ok(Frame::data(result))
@@ -142,12 +367,31 @@ pub fn setup_connection(
     )));
 
     (
-        Http2Receiver { inbound_rx },
-        Http2Sender { outbound_tx },
+        Http2Receiver {
+            inbound_rx,
+            options,
+            protocol_mode,
+            draining,
+            target,
+        },
+        Http2Sender {
+            outbound_tx,
+            buffer,
+            capacity: options.channel_capacity,
+        },
         boxed_body,
     )
 }
 
+/// Protocol mode of the invocations and discovery requests received with the given HTTP version.
+pub fn protocol_mode(version: Version) -> ProtocolMode {
+    if version >= Version::HTTP_2 {
+        ProtocolMode::BidiStream
+    } else {
+        ProtocolMode::RequestResponse
+    }
+}
+
 pub fn empty() -> BoxBody<Bytes, hyper::Error> {
     Empty::<Bytes>::new().map_err(|never| match never {}).boxed()
 }
@@ -158,6 +402,93 @@ pub fn full<T: Into<Bytes>>(chunk: T) -> BoxBody<Bytes, hyper::Error> {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use restate_sdk_types::service_protocol;
+
+    fn request(body: Bytes) -> Request<Full<Bytes>> {
+        Request::new(Full::new(body))
+    }
+
+    fn encoded_messages() -> Bytes {
+        let encoder = Encoder::new(ServiceProtocolVersion::V1);
+        let mut bytes = Vec::new();
+        for message in [
+            ProtocolMessage::new_entry_ack(1),
+            ProtocolMessage::new_entry_ack(2),
+            ProtocolMessage::End(service_protocol::EndMessage {}),
+        ] {
+            bytes.extend_from_slice(&encoder.encode(message));
+        }
+        bytes.into()
+    }
+
     #[test]
     fn test_connection() {}
+
+    #[tokio::test]
+    async fn test_receive_all_messages_of_a_frame() {
+        let (mut receiver, _sender, _body) = setup_connection(request(encoded_messages()));
+
+        for expected in [MessageType::EntryAck, MessageType::EntryAck, MessageType::End] {
+            let (message_type, _) = receiver.recv().await.unwrap().unwrap();
+            assert_eq!(message_type, expected);
+        }
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pause_on_slow_reader() {
+        let mut request = request(Bytes::new());
+        request.extensions_mut().insert(ConnectionOptions {
+            channel_capacity: 2,
+            ..ConnectionOptions::default()
+        });
+        let (_receiver, sender, mut body) = setup_connection(request);
+
+        let mut ready = tokio_test::task::spawn(futures::future::poll_fn(|cx| sender.poll_ready(cx)));
+        sender.send(ProtocolMessage::new_entry_ack(1));
+        tokio_test::assert_ready!(ready.poll());
+        sender.send(ProtocolMessage::new_entry_ack(2));
+        tokio_test::assert_pending!(ready.poll());
+
+        // Reading a message from the response makes room for the next one
+        assert!(body.frame().await.unwrap().unwrap().is_data());
+        assert!(ready.is_woken());
+        tokio_test::assert_ready!(ready.poll());
+
+        // The sender does not wait anymore once the response is dropped
+        sender.send(ProtocolMessage::new_entry_ack(3));
+        tokio_test::assert_pending!(ready.poll());
+        drop(body);
+        assert!(ready.is_woken());
+        tokio_test::assert_ready!(ready.poll());
+    }
+
+    #[tokio::test]
+    async fn test_limit_message_size_by_default() {
+        // A header announcing a message larger than the default limit, without its payload
+        let mut bytes = Encoder::new(ServiceProtocolVersion::V1)
+            .encode(ProtocolMessage::new_entry_ack(1))
+            .to_vec();
+        bytes.truncate(8);
+        bytes[4..8].copy_from_slice(&(64 * 1024 * 1024u32).to_be_bytes());
+        let (mut receiver, _sender, _body) = setup_connection(request(bytes.into()));
+
+        let error = receiver.recv().await.unwrap().unwrap_err();
+        assert!(error.message().contains("message size limit"));
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reject_corrupted_stream() {
+        let mut bytes = encoded_messages().to_vec();
+        bytes.truncate(bytes.len() - 1);
+        let (mut receiver, _sender, _body) = setup_connection(request(bytes.into()));
+
+        assert!(receiver.recv().await.unwrap().is_ok());
+        assert!(receiver.recv().await.unwrap().is_ok());
+        let error = receiver.recv().await.unwrap().unwrap_err();
+        assert_eq!(error.code(), codes::PROTOCOL_VIOLATION);
+        assert!(receiver.recv().await.is_none());
+    }
 }
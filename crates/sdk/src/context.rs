@@ -1,12 +1,13 @@
-pub use crate::syscall::JournalIndex;
+pub use crate::syscall::{CustomJournalEntry, DurableFuture, JournalIndex, MapOutput};
 use crate::{
     combinators::Timeout,
     machine::StateMachine,
+    payload::Payload,
     protocol::AWAKEABLE_IDENTIFIER_PREFIX,
     syscall::{
         AwakeableFuture, CallServiceFuture, ClearAllStateFuture, ClearStateFuture, CompletePromiseFuture,
-        GetPromiseFuture, GetStateFuture, GetStateKeysFuture, PeekPromiseFuture, RunFuture, SetStateFuture,
-        SleepFuture,
+        CustomEntryFuture, GetPromiseFuture, GetStateFuture, GetStateKeysFuture, PeekPromiseFuture,
+        RunFuture, SetStateFuture, SleepFuture,
     },
     utils,
 };
@@ -14,11 +15,10 @@ use anyhow::Error;
 use base64::Engine;
 use bytes::{BufMut, Bytes, BytesMut};
 use futures_util::FutureExt;
-use parking_lot::Mutex;
 use restate_sdk_core::{RunAction, ServiceHandler};
 use restate_sdk_types::journal::{
-    AwakeableEntry, CompletePromiseEntry, EntryResult, GetPromiseEntry, GetStateEntry, GetStateKeysEntry,
-    InvokeEntry, InvokeRequest, PeekPromiseEntry, RunEntry, SleepEntry,
+    AwakeableEntry, ClearStateEntry, CompletePromiseEntry, EntryResult, GetPromiseEntry, GetStateEntry,
+    GetStateKeysEntry, InvokeEntry, InvokeRequest, PeekPromiseEntry, SetStateEntry, SleepEntry,
 };
 use serde::{Deserialize, Serialize};
 use std::{
@@ -28,14 +28,31 @@ use std::{
     ops::Add,
     pin::Pin,
     sync::Arc,
-    task::{Poll, Waker},
+    task::Poll,
     time::{Duration, SystemTime},
 };
+use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, Instrument};
 
 #[derive(Clone)]
 pub struct Request {
     pub id: Bytes,
+    /// Cancelled when the context is used outside of the task running the invocation, failing it
+    misused: CancellationToken,
+}
+
+impl Request {
+    pub(crate) fn new(id: Bytes, misused: CancellationToken) -> Self {
+        Self { id, misused }
+    }
+
+    /// Fails the invocation when its context is used from another task, e.g. one spawned by the
+    /// handler, which cannot reach the state machine owned by the invocation task.
+    pub(crate) fn enter(&self) {
+        if !StateMachine::is_current() {
+            self.misused.cancel();
+        }
+    }
 }
 
 pub enum CallContextType {
@@ -52,13 +69,22 @@ pub trait ContextData {
     fn request(&self) -> &Request;
 }
 
-pub(crate) trait ContextInstance: ContextData {
-    fn new(request: Request, state_machine: Arc<Mutex<StateMachine>>) -> Self;
-    fn state_machine(&self) -> Arc<Mutex<StateMachine>>;
+/// Context built by the SDK for each invocation, implemented by the context types of the handlers.
+///
+/// The trait is sealed, the contexts cannot be built outside of the SDK.
+pub trait ContextInstance: ContextData + sealed::Sealed {
+    #[doc(hidden)]
+    fn new(request: Request) -> Self;
+}
+
+mod sealed {
+    pub trait Sealed {}
 }
 
 macro_rules! context_data_impl {
     ($test:ident) => {
+        impl sealed::Sealed for $test {}
+
         impl ContextData for $test {
             fn request(&self) -> &Request {
                 &self.request
@@ -66,15 +92,8 @@ macro_rules! context_data_impl {
         }
 
         impl ContextInstance for $test {
-            fn new(request: Request, state_machine: Arc<Mutex<StateMachine>>) -> Self {
-                $test {
-                    request,
-                    state_machine,
-                }
-            }
-
-            fn state_machine(&self) -> Arc<Mutex<StateMachine>> {
-                self.state_machine.clone()
+            fn new(request: Request) -> Self {
+                $test { request }
             }
         }
     };
@@ -85,88 +104,69 @@ pub trait CombinableFuture<T: Send>: Future<Output = T> + Send {
 }
 
 pub trait ContextBase: ContextInstance {
-    fn awakeable<R>(&self) -> (String, impl Future<Output = Result<R, Error>> + '_)
+    fn awakeable<R>(&self) -> (String, impl DurableFuture<Output = Result<R, Error>> + '_)
     where
         for<'a> R: Serialize + Deserialize<'a>,
     {
-        let awakeable = AwakeableFuture::new(
-            None,
-            AwakeableEntry { result: None },
-            self.state_machine().clone(),
-        );
+        self.request().enter();
+        let awakeable = AwakeableFuture::new(None, AwakeableEntry { result: None });
         let mut input_buf = BytesMut::new();
         input_buf.put_slice(&self.request().id);
         input_buf.put_u32(awakeable.entry());
         let encoded_base64 = utils::base64::URL_SAFE.encode(input_buf.freeze());
         let id = format!("{}{}", AWAKEABLE_IDENTIFIER_PREFIX, encoded_base64);
-        (id, async move {
-            let bytes = awakeable.await;
-            // If the awakeable is completed, deserialize the result and return
-            let bytes = bytes.to_vec();
-            let result: R = serde_json::from_slice(&bytes).unwrap();
-            Ok(result)
-        })
+        (
+            id,
+            MapOutput::new(awakeable, |bytes: Bytes| {
+                // If the awakeable is completed, deserialize the result and return
+                let bytes = bytes.to_vec();
+                let result: R = serde_json::from_slice(&bytes).unwrap();
+                Ok(result)
+            }),
+        )
     }
 
-    fn sleep(&self, timeout_millis: u64) -> impl Future<Output = Result<(), anyhow::Error>> + '_ {
+    fn sleep(&self, timeout_millis: u64) -> impl DurableFuture<Output = Result<(), anyhow::Error>> + '_ {
+        self.request().enter();
         let wake_up_time = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .expect("Time went backwards");
         let wake_up_time = wake_up_time.as_millis() as u64 + timeout_millis;
         info!("Context sleep: Wake up time {}", wake_up_time);
-        async move {
-            let _ = SleepFuture::new(
-                None,
-                SleepEntry {
-                    wake_up_time,
-                    result: None,
-                },
-                self.state_machine().clone(),
-            )
-            .await;
-            Ok(())
-        }
+        let sleep = SleepFuture::new(None, SleepEntry {
+            wake_up_time,
+            result: None,
+        });
+        MapOutput::new(sleep, |_| Ok(()))
     }
 
-    fn run<'c, Name: Into<String> + 'c, Func, Output>(
-        &'c self,
+    /// Runs the closure once and journals its result, replayed in the next attempts. A closure failing
+    /// with an `InvocationError` journals the failure too, the other failures are retried.
+    fn run<Name, Func, Output>(
+        &self,
         name: Name,
         func: Func,
-    ) -> impl Future<Output = Result<(), anyhow::Error>> + 'c
+    ) -> impl DurableFuture<Output = Result<Output, anyhow::Error>> + JournalIndex
     where
+        Name: Into<String>,
         for<'a> Output: Serialize + Deserialize<'a>,
-        Func: RunAction<Output = Result<Output, anyhow::Error>> + Send + Sync + 'static,
+        Func: RunAction<Output = Result<Output, anyhow::Error>> + Send,
     {
-        async move {
-            // TODO: Fix Running function name
-            //let name = std::any::type_name::<Func>().to_string();
-            let name = name.into();
-            let (run_tx, run_rx) = std::sync::mpsc::sync_channel(1);
-            let (result_tx, result_rx) = std::sync::mpsc::sync_channel(1);
-
-            // TODO: Move this into the future and only create if not replaying
-            // TODO: Abortable and unwinds
-            let side_effect = name.clone();
-            let _handle = tokio::spawn(async move {
-                match run_rx.recv() {
-                    Ok(waker) => {
-                        let waker: Waker = waker;
-                        debug!("Running action: {}", side_effect);
-                        let _result = func().await;
-                        //let result = serde_json::to_vec(&result).unwrap_or_default();
-                        let result = RunEntry {
-                            result: EntryResult::Success(Bytes::new()),
-                        };
-                        let _ = result_tx.send(result);
-                        waker.wake();
-                    }
-                    Err(_) => {}
-                }
-            });
-
-            let _result = RunFuture::new(Some(name), self.state_machine().clone(), run_tx, result_rx).await;
-            Ok(())
-        }
+        self.request().enter();
+        RunFuture::new(Some(name.into()), func)
+    }
+
+    /// Records a custom journal entry built by `entry`, or returns the recorded entry on replay.
+    fn record<Entry, Func>(
+        &self,
+        entry: Func,
+    ) -> impl Future<Output = Result<Entry, anyhow::Error>> + JournalIndex
+    where
+        Entry: CustomJournalEntry,
+        Func: FnOnce() -> Entry + Send,
+    {
+        self.request().enter();
+        CustomEntryFuture::new(entry)
     }
 
     fn invoke<Context, Func, Input, Output>(
@@ -177,36 +177,50 @@ pub trait ContextBase: ContextInstance {
         parameter: Input,
         key: Option<String>,
         idempotency_key: Option<String>,
-    ) -> impl Future<Output = Result<Output, anyhow::Error>> + JournalIndex + '_
+    ) -> impl DurableFuture<Output = Result<Output, anyhow::Error>> + JournalIndex + '_
     where
-        for<'a> Input: Serialize + Deserialize<'a>,
-        for<'a> Output: Serialize + Deserialize<'a> + 'static,
+        Input: Payload,
+        Output: Payload + 'static,
         Func: ServiceHandler<Context, Input, Output = Result<Output, anyhow::Error>> + Send + Sync + 'static,
         Context: ContextInstance,
     {
-        let parameter = serde_json::to_string(&parameter).unwrap();
-        CallServiceFuture::<Output>::new(
-            None,
-            InvokeEntry {
-                request: InvokeRequest {
-                    service_name: service_name.into(),
-                    handler_name: handler_name.into(),
-                    parameter: parameter.into(),
-                    headers: vec![],
-                    key: Default::default(),
-                    idempotency_key: idempotency_key.map(|key| key.into()),
-                },
-                result: None,
+        self.call(service_name, handler_name, parameter, key, idempotency_key)
+    }
+
+    /// Same as [`ContextBase::invoke`], with the types of the handler given by its input and output rather
+    /// than by the handler itself.
+    fn call<Input, Output>(
+        &self,
+        service_name: String,
+        handler_name: String,
+        parameter: Input,
+        key: Option<String>,
+        idempotency_key: Option<String>,
+    ) -> impl DurableFuture<Output = Result<Output, anyhow::Error>> + JournalIndex + '_
+    where
+        Input: Payload,
+        Output: Payload + 'static,
+    {
+        self.request().enter();
+        let invoke_entry = parameter.encode().map(|parameter| InvokeEntry {
+            request: InvokeRequest {
+                service_name: service_name.into(),
+                handler_name: handler_name.into(),
+                parameter,
+                headers: vec![],
+                key: key.unwrap_or_default().into(),
+                idempotency_key: idempotency_key.map(|key| key.into()),
             },
-            self.state_machine().clone(),
-        )
+            result: None,
+        });
+        CallServiceFuture::<Output>::new(None, invoke_entry)
     }
 
     fn timeout<F>(&self, f: F, timeout_millis: u64) -> Timeout<F>
     where
         F: Future + JournalIndex,
     {
-        Timeout::new(self.state_machine().clone(), timeout_millis, f)
+        Timeout::new(timeout_millis, f)
     }
 }
 
@@ -217,88 +231,97 @@ pub trait ContextKeyed: ContextData {
 }
 
 pub trait KeyValueStoreReadOnly: ContextInstance {
-    fn get<V, N>(&self, name: N) -> impl Future<Output = Option<V>>
+    fn get<V, N>(&self, name: N) -> impl DurableFuture<Output = Option<V>>
     where
         for<'a> V: Serialize + Deserialize<'a>,
         N: AsRef<str>,
     {
+        self.request().enter();
         let mut get_state_entry = GetStateEntry {
             key: name.as_ref().to_string().into(),
             value: None,
         };
-        let completed = self
-            .state_machine()
-            .lock()
-            .local_state_store()
-            .try_complete_get(name.as_ref(), &mut get_state_entry);
-        let get_state = GetStateFuture::new(None, get_state_entry, self.state_machine().clone());
-        let state_machine = self.state_machine().clone();
-        async move {
-            let bytes = get_state.await;
+        let completed = StateMachine::try_with(|state_machine| {
+            state_machine
+                .local_state_store()
+                .try_complete_get(name.as_ref(), &mut get_state_entry)
+        })
+        .unwrap_or_default();
+        let get_state = GetStateFuture::new(None, get_state_entry);
+        MapOutput::new(get_state, move |bytes: Bytes| {
             if !completed {
-                state_machine
-                    .lock()
-                    .local_state_store()
-                    .add(name.as_ref().to_string(), bytes.clone());
+                StateMachine::with(|state_machine| {
+                    state_machine
+                        .local_state_store()
+                        .add(name.as_ref().to_string(), bytes.clone())
+                });
             }
             let bytes = bytes.to_vec();
             let result: V = serde_json::from_slice(&bytes).unwrap();
             Some(result)
-        }
+        })
     }
 
-    fn state_keys<V>(&self) -> impl Future<Output = Vec<V>>
+    fn state_keys<V>(&self) -> impl DurableFuture<Output = Vec<V>>
     where
         for<'a> V: Serialize + Deserialize<'a>,
     {
+        self.request().enter();
         let mut get_state_keys_entry = GetStateKeysEntry { value: None };
-        let completed = self
-            .state_machine()
-            .lock()
-            .local_state_store()
-            .try_complete_get_keys(&mut get_state_keys_entry);
-        let get_state_keys =
-            GetStateKeysFuture::new(None, get_state_keys_entry, self.state_machine().clone());
-        async move {
-            let bytes = get_state_keys.await;
+        let completed = StateMachine::try_with(|state_machine| {
+            state_machine
+                .local_state_store()
+                .try_complete_get_keys(&mut get_state_keys_entry)
+        })
+        .unwrap_or_default();
+        let get_state_keys = GetStateKeysFuture::new(None, get_state_keys_entry);
+        MapOutput::new(get_state_keys, |bytes: Vec<Bytes>| {
             bytes.iter().map(|v| serde_json::from_slice(v).unwrap()).collect()
-        }
+        })
     }
 }
 
 pub trait KeyValueStore: KeyValueStoreReadOnly {
-    fn set<V, N>(&self, name: N, value: V) -> impl Future<Output = ()>
+    fn set<V, N>(&self, name: N, value: V) -> impl DurableFuture<Output = ()>
     where
         for<'a> V: Serialize + Deserialize<'a>,
         N: AsRef<str>,
     {
-        let set_state_entry = self
-            .state_machine()
-            .lock()
-            .local_state_store()
-            .set(name.as_ref().to_string(), value);
-        SetStateFuture::new(None, set_state_entry, self.state_machine().clone())
+        self.request().enter();
+        // Outside of the invocation task the entry is never journaled, the invocation failed
+        let set_state_entry = StateMachine::try_with(|state_machine| {
+            state_machine
+                .local_state_store()
+                .set(name.as_ref().to_string(), value)
+        })
+        .unwrap_or_else(|| SetStateEntry {
+            key: name.as_ref().to_string().into(),
+            value: Bytes::new(),
+        });
+        SetStateFuture::new(None, set_state_entry)
     }
 
-    fn clear<N: AsRef<str>>(&self, name: N) -> impl Future<Output = ()> {
-        let clear_state_entry = self
-            .state_machine()
-            .lock()
-            .local_state_store()
-            .clear(name.as_ref().to_string());
-        ClearStateFuture::new(None, clear_state_entry, self.state_machine().clone())
+    fn clear<N: AsRef<str>>(&self, name: N) -> impl DurableFuture<Output = ()> {
+        self.request().enter();
+        let clear_state_entry = StateMachine::try_with(|state_machine| {
+            state_machine.local_state_store().clear(name.as_ref().to_string())
+        })
+        .unwrap_or_else(|| ClearStateEntry {
+            key: name.as_ref().to_string().into(),
+        });
+        ClearStateFuture::new(None, clear_state_entry)
     }
 
-    fn clear_all(&self) -> impl Future<Output = ()> {
-        self.state_machine().lock().local_state_store().clear_all();
-        ClearAllStateFuture::new(None, self.state_machine().clone())
+    fn clear_all(&self) -> impl DurableFuture<Output = ()> {
+        self.request().enter();
+        StateMachine::try_with(|state_machine| state_machine.local_state_store().clear_all());
+        ClearAllStateFuture::new(None)
     }
 }
 
 #[derive(Clone)]
 pub struct Context {
     request: Request,
-    state_machine: Arc<Mutex<StateMachine>>,
 }
 
 context_data_impl!(Context);
@@ -308,7 +331,6 @@ impl ContextBase for Context {}
 #[derive(Clone)]
 pub struct ObjectSharedContext {
     request: Request,
-    state_machine: Arc<Mutex<StateMachine>>,
 }
 
 context_data_impl!(ObjectSharedContext);
@@ -322,7 +344,6 @@ impl KeyValueStoreReadOnly for ObjectSharedContext {}
 #[derive(Clone)]
 pub struct ObjectContext {
     request: Request,
-    state_machine: Arc<Mutex<StateMachine>>,
 }
 
 context_data_impl!(ObjectContext);
@@ -336,31 +357,31 @@ impl KeyValueStoreReadOnly for ObjectContext {}
 impl KeyValueStore for ObjectContext {}
 
 pub trait DurablePromise {
-    fn peek<T: Send>(&self) -> impl Future<Output = Option<T>> + Send
+    fn peek<T>(&self) -> impl DurableFuture<Output = Option<T>> + Send
     where
-        for<'a> T: Serialize + Deserialize<'a>;
-    fn resolve<T: Send>(&self, value: T) -> impl Future<Output = ()> + Send
+        for<'a> T: Serialize + Deserialize<'a> + Send;
+    fn resolve<T>(&self, value: T) -> impl DurableFuture<Output = ()> + Send
     where
-        for<'a> T: Serialize + Deserialize<'a>;
-    fn reject(&self, message: String) -> impl Future<Output = ()> + Send;
-    fn get<T: Send>(&self) -> impl CombinableFuture<T>
+        for<'a> T: Serialize + Deserialize<'a> + Send;
+    fn reject(&self, message: String) -> impl DurableFuture<Output = ()> + Send;
+    fn get<T>(&self) -> CombinableFutureImpl<T>
     where
-        for<'a> T: Serialize + Deserialize<'a>;
-    fn awaitable<T: Send>(&self) -> impl Future<Output = T> + Send
+        for<'a> T: Serialize + Deserialize<'a> + Send;
+    fn awaitable<T>(&self) -> impl DurableFuture<Output = T> + Send
     where
-        for<'a> T: Serialize + Deserialize<'a>;
+        for<'a> T: Serialize + Deserialize<'a> + Send;
 }
 
 pub trait ContextWorkflowShared: ContextInstance {
     fn promise<N: AsRef<str>>(&self, name: N) -> impl DurablePromise {
-        DurablePromiseImpl::new(name.as_ref().to_string(), self.state_machine().clone())
+        self.request().enter();
+        DurablePromiseImpl::new(self.request().clone(), name.as_ref().to_string())
     }
 }
 
 #[derive(Clone)]
 pub struct WorkflowSharedContext {
     request: Request,
-    state_machine: Arc<Mutex<StateMachine>>,
 }
 
 context_data_impl!(WorkflowSharedContext);
@@ -370,7 +391,6 @@ impl ContextWorkflowShared for WorkflowSharedContext {}
 #[derive(Clone)]
 pub struct WorkflowContext {
     request: Request,
-    state_machine: Arc<Mutex<StateMachine>>,
 }
 
 context_data_impl!(WorkflowContext);
@@ -385,18 +405,19 @@ impl KeyValueStore for WorkflowContext {}
 
 impl ContextWorkflowShared for WorkflowContext {}
 
+/// Future returned by [`DurablePromise::get`], resolved with the value of the promise.
 pub struct CombinableFutureImpl<T> {
     entry_index: u32,
-    state_machine: Arc<Mutex<StateMachine>>,
     _ret: PhantomData<T>,
 }
 
 impl<T: Send> CombinableFutureImpl<T> {
-    pub fn new(state_machine: Arc<Mutex<StateMachine>>) -> Self {
-        let entry_index = state_machine.lock().get_next_user_code_journal_index();
+    fn new() -> Self {
+        let entry_index =
+            StateMachine::try_with(|state_machine| state_machine.get_next_user_code_journal_index())
+                .unwrap_or_default();
         Self {
             entry_index,
-            state_machine,
             _ret: PhantomData,
         }
     }
@@ -417,95 +438,82 @@ impl<T: Send> CombinableFuture<T> for CombinableFutureImpl<T> {
 }
 
 pub struct DurablePromiseImpl {
+    request: Request,
     name: String,
-    state_machine: Arc<Mutex<StateMachine>>,
 }
 
 impl DurablePromiseImpl {
-    pub fn new(name: String, state_machine: Arc<Mutex<StateMachine>>) -> Self {
-        Self { name, state_machine }
+    pub fn new(request: Request, name: String) -> Self {
+        Self { request, name }
     }
 }
 
 impl DurablePromise for DurablePromiseImpl {
-    fn peek<T: Send>(&self) -> impl Future<Output = Option<T>>
+    fn peek<T>(&self) -> impl DurableFuture<Output = Option<T>>
     where
-        for<'a> T: Serialize + Deserialize<'a>,
+        for<'a> T: Serialize + Deserialize<'a> + Send,
     {
-        let peek_promise = PeekPromiseFuture::new(
-            None,
-            PeekPromiseEntry {
-                key: self.name.clone().into(),
-                value: None,
-            },
-            self.state_machine.clone(),
-        );
+        self.request.enter();
+        let peek_promise = PeekPromiseFuture::new(None, PeekPromiseEntry {
+            key: self.name.clone().into(),
+            value: None,
+        });
 
-        async move {
-            let bytes = peek_promise.in_current_span().await;
+        MapOutput::new(peek_promise, |bytes: Option<Bytes>| {
             bytes.map(|bytes| {
                 // If the system call is completed, deserialize the result and return
                 let bytes = bytes.to_vec();
                 let result: T = serde_json::from_slice(&bytes).unwrap();
                 result
             })
-        }
+        })
     }
 
-    fn resolve<T: Send>(&self, value: T) -> impl Future<Output = ()>
+    fn resolve<T>(&self, value: T) -> impl DurableFuture<Output = ()>
     where
-        for<'a> T: Serialize + Deserialize<'a>,
+        for<'a> T: Serialize + Deserialize<'a> + Send,
     {
+        self.request.enter();
         let value = serde_json::to_string(&value).unwrap();
-        CompletePromiseFuture::new(
-            None,
-            CompletePromiseEntry {
-                key: self.name.clone().into(),
-                completion: EntryResult::Success(value.into()),
-                value: None,
-            },
-            self.state_machine.clone(),
-        )
+        CompletePromiseFuture::new(None, CompletePromiseEntry {
+            key: self.name.clone().into(),
+            completion: EntryResult::Success(value.into()),
+            value: None,
+        })
     }
 
-    fn reject(&self, message: String) -> impl Future<Output = ()> {
-        CompletePromiseFuture::new(
-            None,
-            CompletePromiseEntry {
-                key: self.name.clone().into(),
-                completion: EntryResult::Failure(0u32.into(), message.into()),
-                value: None,
-            },
-            self.state_machine.clone(),
-        )
+    fn reject(&self, message: String) -> impl DurableFuture<Output = ()> {
+        self.request.enter();
+        CompletePromiseFuture::new(None, CompletePromiseEntry {
+            key: self.name.clone().into(),
+            completion: EntryResult::Failure(0u32.into(), message.into()),
+            value: None,
+        })
     }
 
-    fn get<T: Send>(&self) -> impl CombinableFuture<T>
+    fn get<T>(&self) -> CombinableFutureImpl<T>
     where
-        for<'a> T: Serialize + Deserialize<'a>,
+        for<'a> T: Serialize + Deserialize<'a> + Send,
     {
-        CombinableFutureImpl::new(self.state_machine.clone())
+        self.request.enter();
+        CombinableFutureImpl::new()
     }
 
-    fn awaitable<T: Send>(&self) -> impl Future<Output = T> + Send
+    fn awaitable<T>(&self) -> impl DurableFuture<Output = T> + Send
     where
-        for<'a> T: Serialize + Deserialize<'a>,
+        for<'a> T: Serialize + Deserialize<'a> + Send,
     {
-        let get_promise = GetPromiseFuture::new(
-            None,
-            GetPromiseEntry {
-                key: self.name.clone().into(),
-                value: None,
-            },
-            self.state_machine.clone(),
-        );
-        async move {
-            let bytes = get_promise.in_current_span().await;
+        self.request.enter();
+        let get_promise = GetPromiseFuture::new(None, GetPromiseEntry {
+            key: self.name.clone().into(),
+            value: None,
+        });
+        MapOutput::new(get_promise, |bytes: Bytes| {
             // If the system call is completed, deserialize the result and return
             let bytes = bytes.to_vec();
             let result: T = serde_json::from_slice(&bytes).unwrap();
             result
-        }
+        })
     }
 }
 
@@ -0,0 +1,283 @@
+use crate::connection::{full, protocol_mode};
+use bytes::Bytes;
+use http::{header::ACCEPT, HeaderMap, Request, Response, StatusCode};
+use http_body_util::{combinators::BoxBody, BodyExt};
+use restate_sdk_types::endpoint_manifest::{Endpoint, Handler, Service};
+use tracing::info;
+
+/// Versions of the discovery manifest, the runtime lists the ones it understands in the `Accept` header
+/// of the discovery request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ManifestVersion {
+    V1,
+    /// Adds the documentation and the metadata of the services and handlers.
+    V2,
+    /// Adds the timeouts, the retentions and the ingress visibility of the services and handlers.
+    V3,
+}
+
+impl ManifestVersion {
+    const ALL: [ManifestVersion; 3] = [ManifestVersion::V1, ManifestVersion::V2, ManifestVersion::V3];
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ManifestVersion::V1 => "application/vnd.restate.endpointmanifest.v1+json",
+            ManifestVersion::V2 => "application/vnd.restate.endpointmanifest.v2+json",
+            ManifestVersion::V3 => "application/vnd.restate.endpointmanifest.v3+json",
+        }
+    }
+
+    /// Lowest version describing the settings of the manifest the runtime has to apply. The documentation
+    /// and the metadata are left out of older versions instead.
+    pub fn required_by(manifest: &Endpoint) -> Self {
+        let applies_settings = manifest.services.iter().any(|service| {
+            service_has_settings(service) || service.handlers.iter().any(handler_has_settings)
+        });
+        if applies_settings {
+            ManifestVersion::V3
+        } else {
+            ManifestVersion::V1
+        }
+    }
+
+    /// Highest version accepted by the request that describes a manifest requiring the given version.
+    ///
+    /// Requests without `Accept` header are answered with v1, as expected by the runtimes predating the
+    /// negotiation.
+    pub fn negotiate(headers: &HeaderMap, required: Self) -> Option<Self> {
+        let accepted = match headers.get(ACCEPT).map(|accept| accept.to_str()) {
+            None => vec![ManifestVersion::V1],
+            Some(Ok(accept)) => accept
+                .split(',')
+                .map(|media_type| media_type.split(';').next().unwrap_or_default().trim())
+                .flat_map(|media_type| match media_type {
+                    "*/*" | "application/*" => ManifestVersion::ALL.to_vec(),
+                    media_type => ManifestVersion::ALL
+                        .into_iter()
+                        .filter(|version| version.content_type().eq_ignore_ascii_case(media_type))
+                        .collect(),
+                })
+                .collect(),
+            Some(Err(_)) => vec![],
+        };
+        accepted.into_iter().filter(|version| *version >= required).max()
+    }
+}
+
+fn service_has_settings(service: &Service) -> bool {
+    service.inactivity_timeout.is_some()
+        || service.abort_timeout.is_some()
+        || service.journal_retention.is_some()
+        || service.idempotency_retention.is_some()
+        || service.ingress_private.is_some()
+}
+
+fn handler_has_settings(handler: &Handler) -> bool {
+    handler.inactivity_timeout.is_some()
+        || handler.abort_timeout.is_some()
+        || handler.journal_retention.is_some()
+        || handler.idempotency_retention.is_some()
+        || handler.workflow_completion_retention.is_some()
+        || handler.ingress_private.is_some()
+}
+
+/// Leaves out the fields added after v1.
+fn without_documentation(manifest: &mut Endpoint) {
+    for service in &mut manifest.services {
+        service.documentation = None;
+        service.metadata.clear();
+        for handler in &mut service.handlers {
+            handler.documentation = None;
+            handler.metadata.clear();
+        }
+    }
+}
+
+/// Answers a discovery request with the manifest, in the version negotiated from its `Accept` header
+/// and announcing the protocol mode supported by its HTTP version. Fails with `415 Unsupported Media
+/// Type` when none of the accepted versions describes the manifest.
+pub fn response<B>(manifest: &Endpoint, request: &Request<B>) -> Response<BoxBody<Bytes, anyhow::Error>> {
+    let required = ManifestVersion::required_by(manifest);
+    let Some(version) = ManifestVersion::negotiate(request.headers(), required) else {
+        info!(
+            "Rejecting discovery accepting {:?}, the manifest requires {:?}",
+            request.headers().get(ACCEPT),
+            required
+        );
+        return Response::builder()
+            .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+            .body(
+                full(format!(
+                    "Unsupported manifest version, the endpoint serves {} or later",
+                    required.content_type()
+                ))
+                .map_err(|e| e.into())
+                .boxed(),
+            )
+            .unwrap();
+    };
+    let mut manifest = Endpoint {
+        protocol_mode: Some(protocol_mode(request.version())),
+        ..manifest.clone()
+    };
+    if version < ManifestVersion::V2 {
+        without_documentation(&mut manifest);
+    }
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", version.content_type())
+        .header("x-restate-server", "restate-sdk-rust/0.1.0")
+        .body(
+            full(serde_json::to_string(&manifest).expect("The manifest is serializable"))
+                .map_err(|e| e.into())
+                .boxed(),
+        )
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use restate_sdk_types::endpoint_manifest::{HandlerName, ServiceName, ServiceType};
+
+    async fn body(response: Response<BoxBody<Bytes, anyhow::Error>>) -> String {
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    fn manifest(handler: Handler) -> Endpoint {
+        Endpoint {
+            max_protocol_version: 1,
+            min_protocol_version: 1,
+            protocol_mode: None,
+            services: vec![Service {
+                abort_timeout: None,
+                documentation: None,
+                handlers: vec![handler],
+                idempotency_retention: None,
+                inactivity_timeout: None,
+                ingress_private: None,
+                journal_retention: None,
+                metadata: Default::default(),
+                name: ServiceName::try_from("Greeter").unwrap(),
+                ty: ServiceType::Service,
+            }],
+        }
+    }
+
+    fn handler() -> Handler {
+        Handler {
+            abort_timeout: None,
+            documentation: None,
+            idempotency_retention: None,
+            inactivity_timeout: None,
+            ingress_private: None,
+            input: None,
+            journal_retention: None,
+            metadata: Default::default(),
+            name: HandlerName::try_from("greet").unwrap(),
+            output: None,
+            ty: None,
+            workflow_completion_retention: None,
+        }
+    }
+
+    fn accept(value: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(ACCEPT, value.parse().unwrap())])
+    }
+
+    #[test]
+    fn test_required_version() {
+        let documented = Handler {
+            documentation: Some("Greets".to_string()),
+            ..handler()
+        };
+        assert_eq!(
+            ManifestVersion::required_by(&manifest(documented)),
+            ManifestVersion::V1
+        );
+        let timed_out = Handler {
+            inactivity_timeout: Some(60_000),
+            ..handler()
+        };
+        assert_eq!(
+            ManifestVersion::required_by(&manifest(timed_out)),
+            ManifestVersion::V3
+        );
+    }
+
+    #[test]
+    fn test_negotiate_version() {
+        let runtime = accept(
+            "application/vnd.restate.endpointmanifest.v1+json, application/vnd.restate.endpointmanifest.v2+json",
+        );
+        assert_eq!(
+            ManifestVersion::negotiate(&runtime, ManifestVersion::V1),
+            Some(ManifestVersion::V2)
+        );
+        assert_eq!(ManifestVersion::negotiate(&runtime, ManifestVersion::V3), None);
+        assert_eq!(
+            ManifestVersion::negotiate(&HeaderMap::new(), ManifestVersion::V1),
+            Some(ManifestVersion::V1)
+        );
+        assert_eq!(
+            ManifestVersion::negotiate(&accept("*/*"), ManifestVersion::V1),
+            Some(ManifestVersion::V3)
+        );
+        assert_eq!(
+            ManifestVersion::negotiate(&accept("application/json"), ManifestVersion::V1),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_leave_out_documentation_of_v1() {
+        let documented = manifest(Handler {
+            documentation: Some("Greets".to_string()),
+            ..handler()
+        });
+        let request = Request::get("/discover")
+            .header(ACCEPT, ManifestVersion::V1.content_type())
+            .body(())
+            .unwrap();
+        let v1 = response(&documented, &request);
+        assert_eq!(v1.headers()["content-type"], ManifestVersion::V1.content_type());
+        assert!(!body(v1).await.contains("\"documentation\""));
+
+        let request = Request::get("/discover")
+            .header(ACCEPT, ManifestVersion::V2.content_type())
+            .body(())
+            .unwrap();
+        assert!(body(response(&documented, &request))
+            .await
+            .contains("\"documentation\":\"Greets\""));
+    }
+
+    #[tokio::test]
+    async fn test_reject_unsupported_version() {
+        let timed_out = manifest(Handler {
+            inactivity_timeout: Some(60_000),
+            ..handler()
+        });
+        let request = Request::get("/discover")
+            .header(ACCEPT, ManifestVersion::V2.content_type())
+            .body(())
+            .unwrap();
+        assert_eq!(
+            response(&timed_out, &request).status(),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+
+        let request = Request::get("/discover")
+            .header(ACCEPT, ManifestVersion::V3.content_type())
+            .body(())
+            .unwrap();
+        let response = response(&timed_out, &request);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            ManifestVersion::V3.content_type()
+        );
+        assert!(body(response).await.contains("\"inactivityTimeout\":60000"));
+    }
+}
@@ -1,16 +1,20 @@
 use crate::{
     connection::{MessageReceiver, MessageSender, RestateStreamConsumer},
     context::ContextInstance,
+    errors,
     invocation::InvocationBuilder,
     machine::StateMachine,
+    payload::Payload,
 };
-use parking_lot::Mutex;
 use restate_sdk_core::ServiceHandler;
-use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use restate_sdk_types::{errors::InvocationError, service_protocol};
+use restate_service_protocol::message::ProtocolMessage;
 use tokio_util::sync::CancellationToken;
 use tracing::debug;
 
+/// Error failing the invocation of a handler.
+pub type HandlerError = anyhow::Error;
+
 pub async fn handle_invocation<Context, Func, Input, Output>(
     handler: Func,
     token: Option<CancellationToken>,
@@ -18,15 +22,18 @@ pub async fn handle_invocation<Context, Func, Input, Output>(
     sender: impl MessageSender + 'static,
     test: bool,
 ) where
-    for<'a> Input: Serialize + Deserialize<'a>,
-    for<'a> Output: Serialize + Deserialize<'a>,
+    Input: Payload,
+    Output: Payload,
     Func: ServiceHandler<Context, Input, Output = Result<Output, anyhow::Error>> + Send + Sync + 'static,
     Context: ContextInstance,
 {
-    let token = token.unwrap_or_else(|| CancellationToken::new());
+    // The invocation owns a child token, so that it can stop its own tasks without cancelling the caller
+    let token = token.map(|token| token.child_token()).unwrap_or_default();
+
+    let options = receiver.options();
 
     // step 1: collect all journal entries
-    let mut builder = InvocationBuilder::new();
+    let mut builder = InvocationBuilder::new().max_journal_length(options.max_journal_length);
     loop {
         tokio::select! {
             _ = token.cancelled() => {
@@ -34,69 +41,47 @@ pub async fn handle_invocation<Context, Func, Input, Output>(
                 return;
             }
             message = receiver.recv() => {
-                if let Some(message) = message {
-                    debug!("Messages received {:?}", message);
-                    if builder.handle_message(message) {
-                        break;
+                let Some(message) = message else {
+                    // The stream closed, let the builder report the truncated journal
+                    break;
+                };
+                debug!("Messages received {:?}", message);
+                match message.and_then(|message| builder.handle_message(message)) {
+                    Ok(true) => break,
+                    Ok(false) => {}
+                    Err(error) => {
+                        reject_invocation(&sender, error);
+                        return;
                     }
                 }
             }
         }
     }
-    let invocation = builder.build();
+    let invocation = match builder.build() {
+        Ok(invocation) => invocation,
+        Err(error) => {
+            reject_invocation(&sender, error);
+            return;
+        }
+    };
     debug!("Invocation build completed {:?}", invocation.debug_id);
     debug!("Invocation machine started {:?}", invocation.debug_id);
-    let invocation_id = invocation.id.clone();
     // step 2: create the state machine
-    let (state_machine, mut suspension_rx) = StateMachine::new(test, Some(Box::new(sender)), invocation);
-    let state_machine = Arc::new(Mutex::new(state_machine));
-    let message_consumer = state_machine.clone();
-    let suspension_consumer = state_machine.clone();
-
-    let token2 = token.clone();
-    let token3 = token.clone();
+    let (state_machine, suspension_rx) = StateMachine::new(
+        test,
+        Some(Box::new(sender)),
+        invocation,
+        options.max_journal_length,
+    );
 
-    // step 3: create connection stream consumer
-    tokio::spawn(async move {
-        // Connection handler
-        let message_consumer = message_consumer;
-        let token = token2;
-        loop {
-            tokio::select! {
-                _ = token.cancelled() => {
-                    break;
-                }
-                message = receiver.recv() => {
-                   if let Some(message) = message {
-                       let mut message_consumer = message_consumer.lock();
-                        message_consumer.handle_message(message);
-                    }
-                }
-            }
-        }
-        debug!("Stream consumption completed");
-    });
-
-    // step 4: create suspension stream consumer
-    tokio::spawn(async move {
-        let suspension_consumer = suspension_consumer;
-        let token = token3;
-        loop {
-            tokio::select! {
-                _ = token.cancelled() => {
-                    break;
-                }
-                message = suspension_rx.recv() => {
-                   if let Some(message) = message {
-                       debug!("scheduling suspension: {:?}", message);
-                        suspension_consumer.lock().suspend();
-                    }
-                }
-            }
-        }
-        debug!("Suspension task completed");
-    });
+    // step 3: invoke the function, the invocation task owns the state machine and consumes the connection
+    state_machine
+        .invoke(token, handler, receiver, suspension_rx)
+        .await;
+}
 
-    // step 5: invoke the function
-    StateMachine::invoke(token, handler, state_machine).await
+fn reject_invocation(sender: &impl MessageSender, error: InvocationError) {
+    debug!("Invocation rejected: {}", error);
+    sender.send(errors::error_message(&error, None));
+    sender.send(ProtocolMessage::End(service_protocol::EndMessage {}));
 }
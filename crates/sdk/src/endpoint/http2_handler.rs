@@ -2,9 +2,9 @@ use crate::{
     connection::{Http2Receiver, Http2Sender, MessageReceiver, MessageSender, RestateStreamConsumer},
     context::{Context, ContextData, ContextInstance},
     endpoint::handler::handle_invocation,
+    payload::Payload,
 };
 use restate_sdk_core::ServiceHandler;
-use serde::{Deserialize, Serialize};
 use tokio_util::sync::CancellationToken;
 use tracing::debug;
 
@@ -15,8 +15,8 @@ pub async fn handle<Context, Func, Input, Output>(
     sender: Http2Sender,
     test: bool,
 ) where
-    for<'a> Input: Serialize + Deserialize<'a>,
-    for<'a> Output: Serialize + Deserialize<'a>,
+    Input: Payload,
+    Output: Payload,
     Func: ServiceHandler<Context, Input, Output = Result<Output, anyhow::Error>> + Send + Sync + 'static,
     Context: ContextInstance,
 {
@@ -28,17 +28,34 @@ pub async fn handle<Context, Func, Input, Output>(
 mod tests {
     use super::*;
     use crate::{
-        connection::{setup_mock_connection, MessageSender, RestateStreamConsumer},
-        context::{Context, ContextBase},
+        connection::{
+            setup_mock_connection, ConnectionOptions, Draining, MessageSender, RestateStreamConsumer,
+        },
+        context::{Context, ContextBase, CustomJournalEntry, DurableFuture, JournalIndex},
+        metrics::{InvocationTarget, Metrics},
+        payload::{Empty, Raw},
     };
+    use bytes::Bytes;
     use prost::Message;
     use restate_sdk_types::{
-        journal::raw::{PlainEntryHeader, PlainRawEntry},
-        service_protocol::call_entry_message,
+        endpoint_manifest::ProtocolMode,
+        errors::InvocationError,
+        journal::{
+            raw::{PlainEntryHeader, PlainRawEntry},
+            EntryType,
+        },
+        service_protocol::{
+            call_entry_message, completion_message, output_entry_message, run_entry_message,
+            CallEntryMessage, CompletionMessage, Failure, OutputEntryMessage, RunEntryMessage,
+            SetStateEntryMessage,
+        },
     };
     use restate_service_protocol::message::{MessageType, ProtocolMessage};
     use serde::{Deserialize, Serialize};
-    use std::{collections::VecDeque, time::Duration};
+    use std::{
+        collections::{HashMap, VecDeque},
+        time::Duration,
+    };
     use tokio::sync::mpsc::{channel, UnboundedSender};
     use tokio_util::sync::CancellationToken;
     use tracing::info;
@@ -75,6 +92,183 @@ mod tests {
         Ok(ExecOutput { status: name.name })
     }
 
+    async fn named_fn(ctx: Context, input: ExecInput) -> Result<ExecOutput, anyhow::Error> {
+        ctx.invoke(
+            greet_fn,
+            "Greeter".to_string(),
+            "greet".to_string(),
+            input,
+            None,
+            None,
+        )
+        .named("greeting")
+        .await
+    }
+
+    async fn run_fn(ctx: Context, input: ExecInput) -> Result<ExecOutput, anyhow::Error> {
+        // The side effect borrows the input of the handler
+        let name = &input.name;
+        let status: String = ctx
+            .run("greeting", || async move { Ok(format!("Hello {}", name)) })
+            .await?;
+        Ok(ExecOutput { status })
+    }
+
+    async fn failing_run_fn(ctx: Context, input: ExecInput) -> Result<ExecOutput, anyhow::Error> {
+        let name = &input.name;
+        let result: Result<String, _> = ctx
+            .run("greeting", || async move {
+                Err(InvocationError::new(409u16, format!("Cannot greet {}", name)).into())
+            })
+            .await;
+        let failure = result.unwrap_err().downcast::<InvocationError>()?;
+        Ok(ExecOutput {
+            status: format!("[{}] {}", failure.code(), failure.message()),
+        })
+    }
+
+    async fn retried_run_fn(ctx: Context, input: ExecInput) -> Result<ExecOutput, anyhow::Error> {
+        let status: String = ctx
+            .run("greeting", || async move {
+                Err(anyhow::anyhow!("Greeter unavailable"))
+            })
+            .await?;
+        Ok(ExecOutput { status })
+    }
+
+    async fn panic_fn(ctx: Context, name: ExecInput) -> Result<ExecOutput, anyhow::Error> {
+        panic!("Cannot greet {}", name.name)
+    }
+
+    async fn unencodable_fn(ctx: Context, _input: ExecInput) -> Result<ExecOutput, anyhow::Error> {
+        // JSON objects only have string keys
+        let parameter = HashMap::from([((1, 2), 3)]);
+        let result = ctx
+            .call::<_, ExecOutput>("Greeter".to_string(), "greet".to_string(), parameter, None, None)
+            .await;
+        Ok(ExecOutput {
+            status: result.err().unwrap().to_string(),
+        })
+    }
+
+    async fn keyed_fn(ctx: Context, input: ExecInput) -> Result<ExecOutput, anyhow::Error> {
+        ctx.call::<_, ExecOutput>(
+            "Counter".to_string(),
+            "add".to_string(),
+            input,
+            Some("counter-1".to_string()),
+            None,
+        )
+        .await
+    }
+
+    async fn spawn_fn(ctx: Context, _input: ExecInput) -> Result<ExecOutput, anyhow::Error> {
+        // The spawned task does not run the invocation, it cannot reach its state machine
+        tokio::spawn(async move { ctx.sleep(1000).await }).await??;
+        Ok(ExecOutput {
+            status: "woke up".to_string(),
+        })
+    }
+
+    async fn raw_fn(_ctx: Context, body: Raw) -> Result<Raw, anyhow::Error> {
+        Ok(body)
+    }
+
+    async fn empty_fn(_ctx: Context, _input: Empty) -> Result<Empty, anyhow::Error> {
+        Ok(Empty)
+    }
+
+    struct VersionMarker(String);
+
+    impl CustomJournalEntry for VersionMarker {
+        const CODE: u16 = 0xFC01;
+
+        fn encode(&self) -> Result<Bytes, anyhow::Error> {
+            Ok(Bytes::from(self.0.clone()))
+        }
+
+        fn decode(value: Bytes) -> Result<Self, anyhow::Error> {
+            Ok(VersionMarker(String::from_utf8(value.to_vec())?))
+        }
+    }
+
+    async fn versioned_fn(ctx: Context, input: ExecInput) -> Result<ExecOutput, anyhow::Error> {
+        let marker = ctx.record(|| VersionMarker("v2".to_string())).await?;
+        Ok(ExecOutput { status: marker.0 })
+    }
+
+    async fn indexed_marker_fn(ctx: Context, _input: ExecInput) -> Result<ExecOutput, anyhow::Error> {
+        let mut marker = std::pin::pin!(ctx.record(|| VersionMarker("v2".to_string())));
+        (&mut marker).await?;
+        Ok(ExecOutput {
+            status: marker.entry_index().to_string(),
+        })
+    }
+
+    fn set_state_message() -> (Option<String>, MessageType, ProtocolMessage) {
+        (
+            None,
+            MessageType::SetStateEntry,
+            PlainRawEntry::new(
+                PlainEntryHeader::SetState {},
+                SetStateEntryMessage {
+                    key: Bytes::from("marker"),
+                    value: Bytes::new(),
+                    ..Default::default()
+                }
+                .encode_to_vec()
+                .into(),
+            )
+            .into(),
+        )
+    }
+
+    fn custom_entry_message(
+        code: u16,
+        value: &'static str,
+    ) -> (Option<String>, MessageType, ProtocolMessage) {
+        (
+            None,
+            MessageType::CustomEntry(code),
+            PlainRawEntry::new(PlainEntryHeader::Custom { code }, value.into()).into(),
+        )
+    }
+
+    fn start_message(known_entries: u32) -> (Option<String>, MessageType, ProtocolMessage) {
+        (
+            None,
+            MessageType::Start,
+            ProtocolMessage::Start(restate_sdk_types::service_protocol::StartMessage {
+                id: Default::default(),
+                debug_id: "".to_string(),
+                known_entries,
+                state_map: vec![],
+                partial_state: false,
+                key: "".to_string(),
+                retry_count_since_last_stored_entry: 0,
+                duration_since_last_stored_entry: 0,
+            }),
+        )
+    }
+
+    fn input_message(value: &'static str) -> (Option<String>, MessageType, ProtocolMessage) {
+        (
+            None,
+            MessageType::InputEntry,
+            PlainRawEntry::new(
+                PlainEntryHeader::Input,
+                restate_sdk_types::service_protocol::InputEntryMessage {
+                    headers: vec![],
+                    value: value.into(),
+                    name: "".to_string(),
+                }
+                .encode_to_vec()
+                .into(),
+            )
+            .into(),
+        )
+    }
+
     #[traced_test]
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn test_handle_connection() {
@@ -167,4 +361,609 @@ mod tests {
         token.cancel();
         handle.await.unwrap();
     }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_reject_truncated_stream() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([start_message(2)]));
+
+        handle_invocation(service_fn, None, receiver, sender, true).await;
+
+        let Some(ProtocolMessage::Error(error)) = output_rx.recv().await else {
+            panic!("Expected an error message");
+        };
+        assert_eq!(error.code, 571);
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_report_handler_panic() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(1),
+            input_message("{\"name\":\"test\"}"),
+        ]));
+
+        handle_invocation(panic_fn, None, receiver, sender, true).await;
+
+        let Some(ProtocolMessage::Error(error)) = output_rx.recv().await else {
+            panic!("Expected an error message");
+        };
+        assert_eq!(error.code, 500);
+        assert!(error.message.contains("Cannot greet test"));
+        assert!(!error.description.is_empty());
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+        // The connection is released once the invocation failed
+        assert!(output_rx.recv().await.is_none());
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_reject_journal_too_long() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(1),
+            input_message("{\"name\":\"test\"}"),
+        ]));
+        let receiver = receiver.with_options(ConnectionOptions {
+            max_journal_length: Some(1),
+            ..Default::default()
+        });
+
+        // The output entry would be the second entry of the journal
+        handle_invocation(greet_fn, None, receiver, sender, true).await;
+
+        let Some(ProtocolMessage::Error(error)) = output_rx.recv().await else {
+            panic!("Expected an error message");
+        };
+        assert_eq!(error.code, 500);
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+        assert!(output_rx.recv().await.is_none());
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_send_named_entry() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(1),
+            input_message("{\"name\":\"test\"}"),
+        ]));
+
+        let handle = tokio::spawn(handle_invocation(named_fn, None, receiver, sender, true));
+
+        let Some(ProtocolMessage::UnparsedEntry(entry)) = output_rx.recv().await else {
+            panic!("Expected an entry message");
+        };
+        let call = CallEntryMessage::decode(entry.serialized_entry().clone()).unwrap();
+        assert_eq!(call.name, "greeting");
+        handle.abort();
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_fail_context_used_from_spawned_task() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(1),
+            input_message("{\"name\":\"test\"}"),
+        ]));
+
+        handle_invocation(spawn_fn, None, receiver, sender, false).await;
+
+        let Some(ProtocolMessage::Error(error)) = output_rx.recv().await else {
+            panic!("Expected an error message");
+        };
+        assert_eq!(error.code, 500);
+        assert!(error
+            .message
+            .contains("outside of the task running the invocation"));
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_send_call_key() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(1),
+            input_message("{\"name\":\"test\"}"),
+        ]));
+
+        let handle = tokio::spawn(handle_invocation(keyed_fn, None, receiver, sender, true));
+
+        let Some(ProtocolMessage::UnparsedEntry(entry)) = output_rx.recv().await else {
+            panic!("Expected an entry message");
+        };
+        let call = CallEntryMessage::decode(entry.serialized_entry().clone()).unwrap();
+        assert_eq!(call.service_name, "Counter");
+        assert_eq!(call.key, "counter-1");
+        handle.abort();
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_return_call_parameter_encoding_error() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(1),
+            input_message("{\"name\":\"test\"}"),
+        ]));
+
+        handle_invocation(unencodable_fn, None, receiver, sender, false).await;
+
+        // The call is not journaled, its error is returned to the handler
+        expect_output(output_rx.recv().await, "{\"status\":\"key must be a string\"}");
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_resume_on_completion() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(1),
+            input_message("{\"name\":\"test\"}"),
+            (
+                None,
+                MessageType::Completion,
+                ProtocolMessage::Completion(CompletionMessage {
+                    entry_index: 1,
+                    result: Some(completion_message::Result::Value("{\"status\":\"done\"}".into())),
+                }),
+            ),
+        ]));
+
+        handle_invocation(service_fn, None, receiver, sender, false).await;
+
+        let Some(ProtocolMessage::UnparsedEntry(call)) = output_rx.recv().await else {
+            panic!("Expected the call entry");
+        };
+        assert_eq!(call.ty(), EntryType::Call);
+        expect_output(output_rx.recv().await, "{\"status\":\"done\"}");
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+    }
+
+    fn expect_output(output: Option<ProtocolMessage>, expected: &'static str) {
+        let Some(ProtocolMessage::UnparsedEntry(output)) = output else {
+            panic!("Expected the output entry");
+        };
+        let output = OutputEntryMessage::decode(output.serialized_entry().clone()).unwrap();
+        assert_eq!(
+            output.result,
+            Some(output_entry_message::Result::Value(expected.into()))
+        );
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_pass_raw_body() {
+        let body = "<delivery id=\"1\"/>\u{0}";
+        let (receiver, sender, mut output_rx) =
+            setup_mock_connection(VecDeque::from([start_message(1), input_message(body)]));
+
+        handle_invocation(raw_fn, None, receiver, sender, false).await;
+
+        expect_output(output_rx.recv().await, body);
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_handle_without_input_and_output() {
+        let (receiver, sender, mut output_rx) =
+            setup_mock_connection(VecDeque::from([start_message(1), input_message("")]));
+
+        handle_invocation(empty_fn, None, receiver, sender, false).await;
+
+        expect_output(output_rx.recv().await, "");
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_run_inline() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(1),
+            input_message("{\"name\":\"test\"}"),
+        ]));
+
+        handle_invocation(run_fn, None, receiver, sender, false).await;
+
+        let Some(ProtocolMessage::UnparsedEntry(run)) = output_rx.recv().await else {
+            panic!("Expected the run entry");
+        };
+        let run = RunEntryMessage::decode(run.serialized_entry().clone()).unwrap();
+        assert_eq!(run.name, "greeting");
+        assert_eq!(
+            run.result,
+            Some(run_entry_message::Result::Value("\"Hello test\"".into()))
+        );
+        expect_output(output_rx.recv().await, "{\"status\":\"Hello test\"}");
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_skip_replayed_run() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(2),
+            input_message("{\"name\":\"test\"}"),
+            (
+                None,
+                MessageType::SideEffectEntry,
+                PlainRawEntry::new(
+                    PlainEntryHeader::Run,
+                    RunEntryMessage {
+                        name: "greeting".to_string(),
+                        result: Some(run_entry_message::Result::Value("\"Hello replay\"".into())),
+                    }
+                    .encode_to_vec()
+                    .into(),
+                )
+                .into(),
+            ),
+        ]));
+
+        handle_invocation(run_fn, None, receiver, sender, false).await;
+
+        // The run entry is not written again, its closure is skipped
+        expect_output(output_rx.recv().await, "{\"status\":\"Hello replay\"}");
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+    }
+
+    fn run_failure_message(code: u32, message: &str) -> (Option<String>, MessageType, ProtocolMessage) {
+        (
+            None,
+            MessageType::SideEffectEntry,
+            PlainRawEntry::new(
+                PlainEntryHeader::Run,
+                RunEntryMessage {
+                    name: "greeting".to_string(),
+                    result: Some(run_entry_message::Result::Failure(Failure {
+                        code,
+                        message: message.to_string(),
+                    })),
+                }
+                .encode_to_vec()
+                .into(),
+            )
+            .into(),
+        )
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_journal_run_failure() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(1),
+            input_message("{\"name\":\"test\"}"),
+        ]));
+
+        handle_invocation(failing_run_fn, None, receiver, sender, false).await;
+
+        let Some(ProtocolMessage::UnparsedEntry(run)) = output_rx.recv().await else {
+            panic!("Expected the run entry");
+        };
+        let run = RunEntryMessage::decode(run.serialized_entry().clone()).unwrap();
+        assert_eq!(run.name, "greeting");
+        assert_eq!(
+            run.result,
+            Some(run_entry_message::Result::Failure(Failure {
+                code: 409,
+                message: "Cannot greet test".to_string(),
+            }))
+        );
+        expect_output(output_rx.recv().await, "{\"status\":\"[409] Cannot greet test\"}");
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_replay_run_failure() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(2),
+            input_message("{\"name\":\"test\"}"),
+            run_failure_message(410, "Greeting expired"),
+        ]));
+
+        handle_invocation(failing_run_fn, None, receiver, sender, false).await;
+
+        // The closure is skipped, the journaled failure is restored
+        expect_output(output_rx.recv().await, "{\"status\":\"[410] Greeting expired\"}");
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_reject_run_replaying_set_state() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(2),
+            input_message("{\"name\":\"test\"}"),
+            set_state_message(),
+        ]));
+
+        handle_invocation(failing_run_fn, None, receiver, sender, false).await;
+
+        // Retried rather than completed with a failure, the closure is not run
+        let Some(ProtocolMessage::Error(error)) = output_rx.recv().await else {
+            panic!("Expected an error message");
+        };
+        assert_eq!(error.code, 570);
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+        assert!(output_rx.recv().await.is_none());
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_retry_run_failure() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(1),
+            input_message("{\"name\":\"test\"}"),
+        ]));
+
+        handle_invocation(retried_run_fn, None, receiver, sender, false).await;
+
+        // The failure is not journaled, the invocation is retried and runs the closure again
+        let Some(ProtocolMessage::Error(error)) = output_rx.recv().await else {
+            panic!("Expected an error message");
+        };
+        assert!(error.description.contains("Greeter unavailable"));
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_record_custom_entry() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(1),
+            input_message("{\"name\":\"test\"}"),
+        ]));
+
+        handle_invocation(versioned_fn, None, receiver, sender, false).await;
+
+        let Some(ProtocolMessage::UnparsedEntry(marker)) = output_rx.recv().await else {
+            panic!("Expected the custom entry");
+        };
+        assert_eq!(marker.header(), &PlainEntryHeader::Custom { code: 0xFC01 });
+        assert_eq!(marker.serialized_entry(), &Bytes::from_static(b"v2"));
+        expect_output(output_rx.recv().await, "{\"status\":\"v2\"}");
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_replay_custom_entry() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(2),
+            input_message("{\"name\":\"test\"}"),
+            custom_entry_message(0xFC01, "v1"),
+        ]));
+
+        handle_invocation(versioned_fn, None, receiver, sender, false).await;
+
+        // The recorded entry is returned, a new one is not built
+        expect_output(output_rx.recv().await, "{\"status\":\"v1\"}");
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_custom_entry_index() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(1),
+            input_message("{\"name\":\"test\"}"),
+        ]));
+        handle_invocation(indexed_marker_fn, None, receiver, sender, false).await;
+        assert!(matches!(
+            output_rx.recv().await,
+            Some(ProtocolMessage::UnparsedEntry(_))
+        ));
+        expect_output(output_rx.recv().await, "{\"status\":\"1\"}");
+
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(2),
+            input_message("{\"name\":\"test\"}"),
+            custom_entry_message(0xFC01, "v1"),
+        ]));
+        handle_invocation(indexed_marker_fn, None, receiver, sender, false).await;
+        expect_output(output_rx.recv().await, "{\"status\":\"1\"}");
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_reject_custom_entry_replaying_set_state() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(2),
+            input_message("{\"name\":\"test\"}"),
+            set_state_message(),
+        ]));
+
+        handle_invocation(versioned_fn, None, receiver, sender, false).await;
+
+        let Some(ProtocolMessage::Error(error)) = output_rx.recv().await else {
+            panic!("Expected an error message");
+        };
+        assert_eq!(error.code, 570);
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+        assert!(output_rx.recv().await.is_none());
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_reject_mismatched_custom_entry() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(2),
+            input_message("{\"name\":\"test\"}"),
+            custom_entry_message(0xFC02, "v1"),
+        ]));
+
+        handle_invocation(versioned_fn, None, receiver, sender, false).await;
+
+        let Some(ProtocolMessage::Error(error)) = output_rx.recv().await else {
+            panic!("Expected an error message");
+        };
+        assert_eq!(error.code, 570);
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+        assert!(output_rx.recv().await.is_none());
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_suspend_in_request_response_mode() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(1),
+            input_message("{\"name\":\"test\"}"),
+        ]));
+        let receiver = receiver.with_protocol_mode(ProtocolMode::RequestResponse);
+
+        handle_invocation(service_fn, None, receiver, sender, false).await;
+
+        let Some(ProtocolMessage::UnparsedEntry(call)) = output_rx.recv().await else {
+            panic!("Expected the call entry");
+        };
+        assert_eq!(call.ty(), EntryType::Call);
+        // The completion of the call can only be sent with the next attempt
+        let Some(ProtocolMessage::Suspension(suspension)) = output_rx.recv().await else {
+            panic!("Expected a suspension message");
+        };
+        assert_eq!(suspension.entry_indexes, vec![1]);
+        assert!(output_rx.recv().await.is_none());
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_suspend_when_draining() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(1),
+            input_message("{\"name\":\"test\"}"),
+        ]));
+        let draining = Draining::default();
+        draining.started.cancel();
+        let receiver = receiver.with_draining(draining);
+
+        handle_invocation(service_fn, None, receiver, sender, false).await;
+
+        let Some(ProtocolMessage::UnparsedEntry(call)) = output_rx.recv().await else {
+            panic!("Expected the call entry");
+        };
+        assert_eq!(call.ty(), EntryType::Call);
+        // The streamed completion of the call is not awaited while the endpoint drains
+        let Some(ProtocolMessage::Suspension(suspension)) = output_rx.recv().await else {
+            panic!("Expected a suspension message");
+        };
+        assert_eq!(suspension.entry_indexes, vec![1]);
+        assert!(output_rx.recv().await.is_none());
+    }
+
+    async fn stuck_fn(_ctx: Context, _input: ExecInput) -> Result<ExecOutput, anyhow::Error> {
+        std::future::pending().await
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_interrupt_when_drain_expires() {
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(1),
+            input_message("{\"name\":\"test\"}"),
+        ]));
+        let draining = Draining::default();
+        draining.expired.cancel();
+        let receiver = receiver.with_draining(draining);
+
+        handle_invocation(stuck_fn, None, receiver, sender, false).await;
+
+        // Without any entry to await the invocation cannot suspend, the runtime retries it
+        let Some(ProtocolMessage::Error(error)) = output_rx.recv().await else {
+            panic!("Expected an error message");
+        };
+        assert_eq!(error.code, 500);
+        assert!(matches!(output_rx.recv().await, Some(ProtocolMessage::End(_))));
+        assert!(output_rx.recv().await.is_none());
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_record_invocation_metrics() {
+        static METRICS: Metrics = Metrics::new();
+        let target = InvocationTarget {
+            service: "MetricsGreeter".to_string(),
+            handler: "greet".to_string(),
+        };
+        let (receiver, sender, _output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(1),
+            input_message("{\"name\":\"test\"}"),
+        ]));
+        let receiver = receiver.with_target(target.clone()).with_metrics(&METRICS);
+        handle_invocation(greet_fn, None, receiver, sender, false).await;
+        let (receiver, sender, _output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(1),
+            input_message("{\"name\":\"test\"}"),
+        ]));
+        let receiver = receiver
+            .with_target(target)
+            .with_metrics(&METRICS)
+            .with_protocol_mode(ProtocolMode::RequestResponse);
+        handle_invocation(service_fn, None, receiver, sender, false).await;
+
+        let rendered = METRICS.render();
+        let labels = "{service=\"MetricsGreeter\",handler=\"greet\"}";
+        assert!(rendered.contains(&format!("restate_sdk_invocations_started_total{} 2\n", labels)));
+        assert!(rendered.contains(&format!("restate_sdk_invocations_completed_total{} 1\n", labels)));
+        assert!(rendered.contains(&format!("restate_sdk_invocations_suspended_total{} 1\n", labels)));
+        // The input and output entries, then the input and call entries
+        assert!(rendered.contains(&format!("restate_sdk_journal_length_sum{} 4\n", labels)));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_record_replay_duration_of_interrupted_replay() {
+        static METRICS: Metrics = Metrics::new();
+        let labels = "{service=\"\",handler=\"\"}";
+
+        // Suspends awaiting the replayed call, before the replay completes
+        let (receiver, sender, mut output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(2),
+            input_message("{\"name\":\"test\"}"),
+            (
+                None,
+                MessageType::InvokeEntry,
+                PlainRawEntry::new(
+                    PlainEntryHeader::Call {
+                        is_completed: false,
+                        enrichment_result: None,
+                    },
+                    CallEntryMessage {
+                        service_name: "Greeter".to_string(),
+                        handler_name: "greet".to_string(),
+                        parameter: Bytes::from("{\"name\":\"test\"}"),
+                        ..Default::default()
+                    }
+                    .encode_to_vec()
+                    .into(),
+                )
+                .into(),
+            ),
+        ]));
+        let receiver = receiver
+            .with_metrics(&METRICS)
+            .with_protocol_mode(ProtocolMode::RequestResponse);
+        handle_invocation(service_fn, None, receiver, sender, false).await;
+        assert!(matches!(
+            output_rx.recv().await,
+            Some(ProtocolMessage::Suspension(_))
+        ));
+        assert!(METRICS.render().contains(&format!(
+            "restate_sdk_replay_duration_seconds_count{} 1\n",
+            labels
+        )));
+
+        // Fails on the mismatched replayed entry
+        let (receiver, sender, _output_rx) = setup_mock_connection(VecDeque::from([
+            start_message(2),
+            input_message("{\"name\":\"test\"}"),
+            custom_entry_message(0xFC02, "v1"),
+        ]));
+        handle_invocation(versioned_fn, None, receiver.with_metrics(&METRICS), sender, false).await;
+        let rendered = METRICS.render();
+        assert!(rendered.contains(&format!(
+            "restate_sdk_replay_duration_seconds_count{} 2\n",
+            labels
+        )));
+        assert!(rendered.contains(&format!("restate_sdk_invocations_failed_total{} 1\n", labels)));
+    }
 }
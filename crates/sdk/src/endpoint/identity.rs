@@ -0,0 +1,255 @@
+use crate::utils::base64::URL_SAFE;
+use base64::Engine;
+use http::HeaderMap;
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde::Deserialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+pub const SIGNATURE_SCHEME_HEADER: &str = "x-restate-signature-scheme";
+pub const JWT_V1_HEADER: &str = "x-restate-jwt-v1";
+const SCHEME_V1: &str = "v1";
+const SCHEME_UNSIGNED: &str = "unsigned";
+const PUBLIC_KEY_V1_PREFIX: &str = "publickeyv1_";
+const ED25519_PUBLIC_KEY_LEN: usize = 32;
+
+#[derive(Debug, Error)]
+pub enum IdentityError {
+    #[error("Invalid request identity key {0}, expected publickeyv1_ followed by a base58 ed25519 key")]
+    InvalidKey(String),
+    #[error("Missing header {0}")]
+    MissingHeader(&'static str),
+    #[error("Unsigned request")]
+    Unsigned,
+    #[error("Unsupported signature scheme {0}")]
+    UnsupportedScheme(String),
+    #[error("Malformed token: {0}")]
+    MalformedToken(&'static str),
+    #[error("The token is not signed by a trusted key")]
+    InvalidSignature,
+    #[error("The token is not issued for {0}")]
+    InvalidAudience(String),
+    #[error("The token is expired or not yet valid")]
+    InvalidTime,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    aud: Audience,
+    exp: u64,
+    nbf: u64,
+    #[allow(dead_code)]
+    iat: u64,
+}
+
+/// Verifies that requests are signed by one of the Restate clusters trusted by the endpoint.
+///
+/// The runtime signs every request with an EdDSA JWT whose audience is the request path.
+pub struct IdentityVerifier {
+    keys: Vec<[u8; ED25519_PUBLIC_KEY_LEN]>,
+}
+
+impl IdentityVerifier {
+    /// Parses the `publickeyv1_...` keys printed by the Restate cluster.
+    pub fn new<Key: AsRef<str>>(keys: impl IntoIterator<Item = Key>) -> Result<Self, IdentityError> {
+        let keys = keys
+            .into_iter()
+            .map(|key| {
+                let key = key.as_ref();
+                key.strip_prefix(PUBLIC_KEY_V1_PREFIX)
+                    .and_then(|encoded| bs58::decode(encoded).into_vec().ok())
+                    .and_then(|decoded| decoded.try_into().ok())
+                    .ok_or_else(|| IdentityError::InvalidKey(key.to_string()))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { keys })
+    }
+
+    pub fn verify(&self, path: &str, headers: &HeaderMap) -> Result<(), IdentityError> {
+        let scheme = header(headers, SIGNATURE_SCHEME_HEADER)?;
+        match scheme {
+            SCHEME_V1 => self.verify_jwt(path, header(headers, JWT_V1_HEADER)?),
+            SCHEME_UNSIGNED => Err(IdentityError::Unsigned),
+            scheme => Err(IdentityError::UnsupportedScheme(scheme.to_string())),
+        }
+    }
+
+    fn verify_jwt(&self, path: &str, token: &str) -> Result<(), IdentityError> {
+        let (message, signature) = token
+            .rsplit_once('.')
+            .ok_or(IdentityError::MalformedToken("missing signature"))?;
+        let (header, claims) = message
+            .split_once('.')
+            .ok_or(IdentityError::MalformedToken("missing claims"))?;
+
+        let header: Header = decode_json(header)?;
+        if header.alg != "EdDSA" {
+            return Err(IdentityError::MalformedToken("unsupported algorithm"));
+        }
+        let signature = URL_SAFE
+            .decode(signature)
+            .map_err(|_| IdentityError::MalformedToken("invalid signature encoding"))?;
+        if !self.keys.iter().any(|key| {
+            UnparsedPublicKey::new(&ED25519, key)
+                .verify(message.as_bytes(), &signature)
+                .is_ok()
+        }) {
+            return Err(IdentityError::InvalidSignature);
+        }
+
+        // The claims are only trusted once the signature is verified
+        let claims: Claims = decode_json(claims)?;
+        let audience_matches = match &claims.aud {
+            Audience::One(audience) => audience == path,
+            Audience::Many(audiences) => audiences.iter().any(|audience| audience == path),
+        };
+        if !audience_matches {
+            return Err(IdentityError::InvalidAudience(path.to_string()));
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or_default();
+        if now < claims.nbf || now > claims.exp {
+            return Err(IdentityError::InvalidTime);
+        }
+        Ok(())
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &'static str) -> Result<&'a str, IdentityError> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .ok_or(IdentityError::MissingHeader(name))
+}
+
+fn decode_json<T: for<'a> Deserialize<'a>>(segment: &str) -> Result<T, IdentityError> {
+    let json = URL_SAFE
+        .decode(segment)
+        .map_err(|_| IdentityError::MalformedToken("invalid base64"))?;
+    serde_json::from_slice(&json).map_err(|_| IdentityError::MalformedToken("invalid json"))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    pub(crate) fn key_pair(seed: u8) -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&[seed; 32]).unwrap()
+    }
+
+    pub(crate) fn public_key(key_pair: &Ed25519KeyPair) -> String {
+        format!(
+            "{}{}",
+            PUBLIC_KEY_V1_PREFIX,
+            bs58::encode(key_pair.public_key().as_ref()).into_string()
+        )
+    }
+
+    fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    fn token(key_pair: &Ed25519KeyPair, audience: &str, nbf: u64, exp: u64) -> String {
+        let header = URL_SAFE.encode(r#"{"typ":"JWT","alg":"EdDSA"}"#);
+        let claims = URL_SAFE
+            .encode(serde_json::json!({ "aud": audience, "exp": exp, "iat": nbf, "nbf": nbf }).to_string());
+        let message = format!("{}.{}", header, claims);
+        let signature = URL_SAFE.encode(key_pair.sign(message.as_bytes()));
+        format!("{}.{}", message, signature)
+    }
+
+    pub(crate) fn signed_headers(key_pair: &Ed25519KeyPair, path: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(SIGNATURE_SCHEME_HEADER, SCHEME_V1.parse().unwrap());
+        headers.insert(
+            JWT_V1_HEADER,
+            token(key_pair, path, now() - 60, now() + 60).parse().unwrap(),
+        );
+        headers
+    }
+
+    fn verifier() -> IdentityVerifier {
+        IdentityVerifier::new([public_key(&key_pair(2)), public_key(&key_pair(1))]).unwrap()
+    }
+
+    #[test]
+    fn test_accept_trusted_signature() {
+        let path = "/invoke/Greeter/greet";
+        assert!(verifier()
+            .verify(path, &signed_headers(&key_pair(1), path))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_reject_untrusted_signature() {
+        let path = "/discover";
+        assert!(matches!(
+            verifier().verify(path, &signed_headers(&key_pair(3), path)),
+            Err(IdentityError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_reject_other_audience() {
+        assert!(matches!(
+            verifier().verify(
+                "/discover",
+                &signed_headers(&key_pair(1), "/invoke/Greeter/greet")
+            ),
+            Err(IdentityError::InvalidAudience(_))
+        ));
+    }
+
+    #[test]
+    fn test_reject_expired_token() {
+        let mut headers = HeaderMap::new();
+        headers.insert(SIGNATURE_SCHEME_HEADER, SCHEME_V1.parse().unwrap());
+        headers.insert(
+            JWT_V1_HEADER,
+            token(&key_pair(1), "/discover", now() - 120, now() - 60)
+                .parse()
+                .unwrap(),
+        );
+        assert!(matches!(
+            verifier().verify("/discover", &headers),
+            Err(IdentityError::InvalidTime)
+        ));
+    }
+
+    #[test]
+    fn test_reject_unsigned_request() {
+        let mut headers = HeaderMap::new();
+        assert!(matches!(
+            verifier().verify("/discover", &headers),
+            Err(IdentityError::MissingHeader(SIGNATURE_SCHEME_HEADER))
+        ));
+        headers.insert(SIGNATURE_SCHEME_HEADER, SCHEME_UNSIGNED.parse().unwrap());
+        assert!(matches!(
+            verifier().verify("/discover", &headers),
+            Err(IdentityError::Unsigned)
+        ));
+    }
+
+    #[test]
+    fn test_reject_invalid_key() {
+        assert!(matches!(
+            IdentityVerifier::new(["publickeyv1_notbase58!"]),
+            Err(IdentityError::InvalidKey(_))
+        ));
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{
+    connection::Draining,
+    endpoint::{
+        identity::IdentityError, tower_service::RequestGate, RestateEndpointOptions, ServiceRegistry,
+    },
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use http::{HeaderName, HeaderValue, Method, Request, Response, StatusCode, Version};
+use http_body_util::{combinators::BoxBody, BodyExt, Full};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{collections::HashMap, sync::Arc};
+use tracing::info;
+
+/// Event the Restate runtime invokes a function with, in the format of an API Gateway proxy request.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LambdaEvent {
+    pub path: String,
+    pub http_method: String,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub is_base64_encoded: bool,
+}
+
+/// Headers sent as `null` by the function runtimes when the request has none.
+fn null_as_empty<'de, D>(deserializer: D) -> Result<HashMap<String, String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Result of a function, in the format of an API Gateway proxy response. The body holds the encoded
+/// protocol messages of the invocation.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LambdaResponse {
+    pub status_code: u16,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub is_base64_encoded: bool,
+}
+
+/// Serves the discovery and the invocations of the bound services from function events rather than
+/// from HTTP connections.
+///
+/// Each event carries a whole request, the invocations run in request-response mode and suspend
+/// whenever they wait on the runtime. The handler does not depend on a function runtime, the events
+/// are handed over by the one the services are deployed with.
+#[derive(Clone)]
+pub struct LambdaHandler {
+    gate: RequestGate,
+    registry: Arc<ServiceRegistry>,
+}
+
+impl LambdaHandler {
+    pub(crate) fn new(
+        options: &RestateEndpointOptions,
+        registry: Arc<ServiceRegistry>,
+    ) -> Result<Self, IdentityError> {
+        Ok(Self {
+            gate: RequestGate::new(options, Draining::default())?,
+            registry,
+        })
+    }
+
+    /// Runs the request of the event to completion and returns its response.
+    pub async fn handle(&self, event: LambdaEvent) -> LambdaResponse {
+        let mut request = match request(event) {
+            Ok(request) => request,
+            Err(err) => {
+                info!("Rejecting event: {}", err);
+                return LambdaResponse {
+                    status_code: StatusCode::BAD_REQUEST.as_u16(),
+                    ..Default::default()
+                };
+            }
+        };
+        let response = match self.gate.intercept(&mut request) {
+            Some(response) => response,
+            None => self.registry.handle(request),
+        };
+        into_event_response(response).await
+    }
+
+    /// Same as [`LambdaHandler::handle`], with the event and the response encoded as JSON.
+    pub async fn handle_json(&self, event: &[u8]) -> Result<Vec<u8>, serde_json::Error> {
+        let response = self.handle(serde_json::from_slice(event)?).await;
+        serde_json::to_vec(&response)
+    }
+}
+
+fn request(event: LambdaEvent) -> Result<Request<Full<Bytes>>, anyhow::Error> {
+    let body = match event.body {
+        Some(body) if event.is_base64_encoded => STANDARD.decode(body)?,
+        Some(body) => body.into_bytes(),
+        None => vec![],
+    };
+    // A whole request per event, as over HTTP/1.1
+    let mut request = Request::builder()
+        .method(Method::from_bytes(event.http_method.as_bytes())?)
+        .uri(event.path)
+        .version(Version::HTTP_11)
+        .body(Full::new(Bytes::from(body)))?;
+    for (name, value) in event.headers {
+        request.headers_mut().insert(
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(&value)?,
+        );
+    }
+    Ok(request)
+}
+
+async fn into_event_response(response: Response<BoxBody<Bytes, anyhow::Error>>) -> LambdaResponse {
+    let (parts, body) = response.into_parts();
+    // The body ends once the invocation completed, failed or suspended
+    let body = match body.collect().await {
+        Ok(collected) => collected.to_bytes(),
+        Err(err) => {
+            info!("Failed to read the response: {}", err);
+            return LambdaResponse {
+                status_code: StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                ..Default::default()
+            };
+        }
+    };
+    LambdaResponse {
+        status_code: parts.status.as_u16(),
+        headers: parts
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect(),
+        body: Some(STANDARD.encode(body)),
+        is_base64_encoded: true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::Context,
+        endpoint::{RestateEndpoint, ServiceDefinition, ServiceType},
+    };
+    use prost::Message;
+    use restate_sdk_types::{
+        journal::EntryType,
+        service_protocol::{output_entry_message, OutputEntryMessage, ServiceProtocolVersion},
+    };
+    use restate_service_protocol::message::{Decoder, ProtocolMessage};
+
+    /// Recorded events in the format of the runtime, the invocation starts with the input `"Till"`.
+    fn recorded_event(name: &str) -> Vec<u8> {
+        std::fs::read(format!(
+            "{}/testdata/lambda/{}.json",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        ))
+        .unwrap()
+    }
+
+    async fn greet(_ctx: Context, name: String) -> Result<String, anyhow::Error> {
+        Ok(format!("Greetings {}", name))
+    }
+
+    fn greeter_handler() -> LambdaHandler {
+        RestateEndpoint::builder()
+            .bind(ServiceDefinition::new("Greeter", ServiceType::Service).handler("greet", greet))
+            .build()
+            .unwrap()
+            .into_lambda()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_discover_from_recorded_event() {
+        let response = greeter_handler()
+            .handle_json(&recorded_event("discover"))
+            .await
+            .unwrap();
+        let response: LambdaResponse = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response.status_code, 200);
+        assert!(response.is_base64_encoded);
+        let manifest = String::from_utf8(STANDARD.decode(response.body.unwrap()).unwrap()).unwrap();
+        assert!(manifest.contains("\"Greeter\""));
+        assert!(manifest.contains("\"REQUEST_RESPONSE\""));
+    }
+
+    #[tokio::test]
+    async fn test_discover_without_headers() {
+        let event: LambdaEvent = serde_json::from_slice(&recorded_event("discover_null_headers")).unwrap();
+        assert!(event.headers.is_empty());
+        let response = greeter_handler().handle(event).await;
+        assert_eq!(response.status_code, 200);
+        assert_eq!(
+            response.headers.get("content-type").map(String::as_str),
+            Some("application/vnd.restate.endpointmanifest.v1+json")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invoke_from_recorded_event() {
+        let event = serde_json::from_slice(&recorded_event("invoke_greet")).unwrap();
+        let response = greeter_handler().handle(event).await;
+        assert_eq!(response.status_code, 200);
+        assert_eq!(
+            response.headers.get("content-type").map(String::as_str),
+            Some("application/vnd.restate.invocation.v1")
+        );
+
+        let mut decoder = Decoder::new(ServiceProtocolVersion::V1, usize::MAX, None);
+        decoder.push(STANDARD.decode(response.body.unwrap()).unwrap().into());
+        let Some((_, ProtocolMessage::UnparsedEntry(output))) = decoder.consume_next().unwrap() else {
+            panic!("Expected the output entry");
+        };
+        assert_eq!(output.ty(), EntryType::Output);
+        assert_eq!(
+            OutputEntryMessage::decode(output.serialized_entry().clone())
+                .unwrap()
+                .result,
+            Some(output_entry_message::Result::Value("\"Greetings Till\"".into()))
+        );
+        assert!(matches!(
+            decoder.consume_next().unwrap(),
+            Some((_, ProtocolMessage::End(_)))
+        ));
+        assert!(decoder.consume_next().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reject_malformed_event() {
+        let response = greeter_handler()
+            .handle(LambdaEvent {
+                path: "/discover".to_string(),
+                http_method: "GET".to_string(),
+                body: Some("not base64!".to_string()),
+                is_base64_encoded: true,
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(response.status_code, 400);
+
+        let response = greeter_handler()
+            .handle(LambdaEvent {
+                path: "/invoke/Greeter/unknown".to_string(),
+                http_method: "POST".to_string(),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(response.status_code, 404);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::{
+    connection::Draining,
+    endpoint::{tower_service::RequestGate, RestateEndpointOptions},
+};
+use bytes::Bytes;
+use futures_util::future::{self, Either};
+use http_body_util::combinators::BoxBody;
+use hyper::{body::Incoming, service::service_fn, Request, Response, Result};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use std::{fmt, future::Future, io, pin::pin, time::Duration};
+#[cfg(unix)] use tokio::net::UnixListener;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use tokio_util::task::TaskTracker;
+use tracing::info;
+
+/// Socket accepting the connections served by an endpoint.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Binds the Unix domain socket of the options if set, else the listen address and port.
+    ///
+    /// The listen address may be a host name, an IPv4 or an IPv6 address.
+    pub async fn bind(options: &RestateEndpointOptions) -> io::Result<Self> {
+        #[cfg(unix)]
+        if let Some(path) = &options.unix_socket {
+            use std::os::unix::fs::FileTypeExt;
+            // A socket left behind by a previous process would make the bind fail
+            if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                std::fs::remove_file(path)?;
+            }
+            return UnixListener::bind(path).map(Listener::Unix);
+        }
+        TcpListener::bind((options.listen_address.as_str(), options.listen_port))
+            .await
+            .map(Listener::Tcp)
+    }
+
+    /// Accepts the next connection and hands it over to the acceptor.
+    pub(crate) async fn accept<H, F>(&self, acceptor: &Acceptor, handler: H) -> io::Result<()>
+    where
+        H: Fn(Request<Incoming>) -> F + Send + 'static,
+        F: Future<Output = Result<Response<BoxBody<Bytes, anyhow::Error>>>> + Send + 'static,
+    {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept().await?;
+                acceptor.serve(stream, handler);
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                acceptor.serve(stream, handler);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Serves the accepted connections, terminating TLS first when the options configure it.
+#[derive(Clone)]
+pub(crate) struct Acceptor {
+    gate: RequestGate,
+    draining: Draining,
+    connections: TaskTracker,
+    #[cfg(feature = "tls")]
+    tls: Option<(tokio_rustls::TlsAcceptor, Duration)>,
+}
+
+impl Acceptor {
+    pub(crate) fn new(
+        options: &RestateEndpointOptions,
+    ) -> std::result::Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let draining = Draining::default();
+        Ok(Self {
+            gate: RequestGate::new(options, draining.clone())?,
+            draining,
+            connections: TaskTracker::new(),
+            #[cfg(feature = "tls")]
+            tls: options
+                .tls
+                .as_ref()
+                .map(|tls| tls.acceptor().map(|acceptor| (acceptor, tls.handshake_timeout)))
+                .transpose()?,
+        })
+    }
+
+    /// Spawns the task serving the connection, the TLS handshake does not hold up the accept loop.
+    pub(crate) fn serve<I, H, F>(&self, stream: I, handler: H)
+    where
+        I: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+        H: Fn(Request<Incoming>) -> F + Send + 'static,
+        F: Future<Output = Result<Response<BoxBody<Bytes, anyhow::Error>>>> + Send + 'static,
+    {
+        let acceptor = self.clone();
+        self.connections.spawn(async move {
+            #[cfg(feature = "tls")]
+            if let Some((tls, handshake_timeout)) = acceptor.tls.clone() {
+                // A client stalling the handshake would hold the connection and its task forever
+                match tokio::time::timeout(handshake_timeout, tls.accept(stream)).await {
+                    Ok(Ok(stream)) => acceptor.serve_connection(stream, handler).await,
+                    Ok(Err(err)) => info!("TLS handshake failed: {}", err),
+                    Err(_) => info!("TLS handshake timed out after {:?}", handshake_timeout),
+                }
+                return;
+            }
+            acceptor.serve_connection(stream, handler).await
+        });
+    }
+
+    async fn serve_connection<I, H, F>(self, stream: I, handler: H)
+    where
+        I: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+        H: Fn(Request<Incoming>) -> F + Send + 'static,
+        F: Future<Output = Result<Response<BoxBody<Bytes, anyhow::Error>>>> + Send + 'static,
+    {
+        let Acceptor { gate, draining, .. } = self;
+        let io = TokioIo::new(stream);
+        let executor = TokioExecutor::new();
+        let service = service_fn(
+            move |mut request: Request<Incoming>| match gate.intercept(&mut request) {
+                Some(response) => Either::Left(future::ready(Ok(response))),
+                None => Either::Right(handler(request)),
+            },
+        );
+        // HTTP/2 with prior knowledge is detected from the connection preface, else HTTP/1.1 is served.
+        // Upgrades to h2c are ignored, as RFC 7540 allows, and the request is served over HTTP/1.1.
+        let builder = auto::Builder::new(executor);
+        let mut connection = pin!(builder.serve_connection(io, service));
+        let result = tokio::select! {
+            result = connection.as_mut() => result,
+            _ = draining.started.cancelled() => {
+                // Refuses new requests, the in-flight ones are served until their invocation ends
+                connection.as_mut().graceful_shutdown();
+                connection.await
+            }
+        };
+        if let Err(err) = result {
+            info!("Error serving connection: {:?}", err);
+        }
+    }
+
+    /// Lets the invocations in flight suspend or complete, the ones still running once the timeout
+    /// elapsed are suspended right away.
+    pub(crate) async fn drain(&self, timeout: Duration) {
+        self.draining.started.cancel();
+        self.connections.close();
+        if tokio::time::timeout(timeout, self.connections.wait())
+            .await
+            .is_err()
+        {
+            info!("Drain timeout elapsed, suspending the invocations in flight");
+            self.draining.expired.cancel();
+            self.connections.wait().await;
+        }
+    }
+}
+
+impl From<TcpListener> for Listener {
+    fn from(listener: TcpListener) -> Self {
+        Listener::Tcp(listener)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixListener> for Listener {
+    fn from(listener: UnixListener) -> Self {
+        Listener::Unix(listener)
+    }
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "http://{}", addr),
+                Err(_) => write!(f, "http://<unknown>"),
+            },
+            #[cfg(unix)]
+            Listener::Unix(listener) => match listener.local_addr() {
+                Ok(addr) => match addr.as_pathname() {
+                    Some(path) => write!(f, "unix:{}", path.display()),
+                    None => write!(f, "unix:<unnamed>"),
+                },
+                Err(_) => write!(f, "unix:<unknown>"),
+            },
+        }
+    }
+}
@@ -1,21 +1,64 @@
-use crate::connection::RestateStreamConsumer;
+use crate::connection::{ConnectionOptions, Draining, RestateStreamConsumer};
 use bytes::Bytes;
+use futures_util::{future::BoxFuture, FutureExt};
 use http_body_util::{combinators::BoxBody, BodyExt};
-use hyper::{server::conn::http2, service::service_fn, Request, Response, Result};
-use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper::{Request, Response, Result};
+use identity::IdentityError;
+use listener::Acceptor;
 use prost::Message;
-use std::{future::Future, net::SocketAddr};
-use tokio::net::TcpListener;
+use restate_sdk_types::endpoint_manifest::error::ConversionError;
+use std::{future::Future, path::PathBuf, sync::Arc, time::Duration};
+use tower_service::RequestGate;
 use tracing::info;
 
+pub mod discovery;
 pub mod handler;
 pub mod http2_handler;
-mod service;
+pub mod identity;
+#[cfg(feature = "lambda")] pub mod lambda;
+mod listener;
+mod registry;
+#[cfg(feature = "tls")] pub mod tls;
+mod tower_service;
 
-// TODO: builder
+pub use handler::HandlerError;
+#[cfg(feature = "lambda")]
+pub use lambda::{LambdaEvent, LambdaHandler, LambdaResponse};
+pub use listener::Listener;
+pub use registry::{
+    BoxedHandler, HandlerOptions, IntoServiceDefinition, Invocation, ServiceDefinition, ServiceOptions,
+    ServiceRegistry,
+};
+pub use restate_sdk_types::endpoint_manifest::{HandlerType, ServiceType};
+#[cfg(feature = "tls")] pub use tls::{TlsError, TlsOptions};
+pub use tower_service::RestateService;
+
+#[derive(Clone)]
 pub struct RestateEndpointOptions {
+    /// Host name, IPv4 or IPv6 address to listen on, `0.0.0.0` or `::` for every interface
     pub listen_address: String,
     pub listen_port: u16,
+    /// Unix domain socket to listen on instead of the listen address
+    pub unix_socket: Option<PathBuf>,
+    /// Limits applied to every invocation served by the endpoint
+    pub connection: ConnectionOptions,
+    /// Public keys `publickeyv1_...` of the Restate clusters allowed to call the endpoint, requests
+    /// without a valid signature are rejected with `401 Unauthorized`. Requests are not verified when
+    /// empty.
+    pub identity_keys: Vec<String>,
+    /// Time the invocations in flight are given to suspend or complete once the shutdown signal fires,
+    /// the remaining ones are suspended right away
+    pub drain_timeout: Duration,
+    /// Invocations running at once on the endpoint, the excess ones are rejected with
+    /// `503 Service Unavailable` for the runtime to retry them. The invocations of a handler are limited
+    /// by [`HandlerOptions::max_concurrent_invocations`].
+    pub max_concurrent_invocations: Option<usize>,
+    /// Serves the Prometheus metrics of the invocations on `GET /metrics`, without verifying the request
+    /// identity
+    pub metrics: bool,
+    /// Terminates TLS on the accepted connections, announcing `h2` and `http/1.1` through ALPN
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsOptions>,
 }
 
 impl Default for RestateEndpointOptions {
@@ -23,51 +66,381 @@ impl Default for RestateEndpointOptions {
         Self {
             listen_address: "localhost".to_string(),
             listen_port: 3000,
+            unix_socket: None,
+            connection: ConnectionOptions::default(),
+            identity_keys: vec![],
+            drain_timeout: Duration::from_secs(30),
+            max_concurrent_invocations: None,
+            metrics: false,
+            #[cfg(feature = "tls")]
+            tls: None,
         }
     }
 }
 
-pub struct RestateEndpoint {}
+/// Builds a [`RestateEndpoint`] serving the services bound to it.
+pub struct RestateEndpointBuilder {
+    options: RestateEndpointOptions,
+    services: Vec<ServiceDefinition>,
+    shutdown: Option<BoxFuture<'static, ()>>,
+}
+
+impl RestateEndpointBuilder {
+    pub fn options(mut self, options: RestateEndpointOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Binds the handlers of a service, services from several crates can be bound to one endpoint.
+    pub fn bind(mut self, service: impl IntoServiceDefinition) -> Self {
+        self.services.push(service.into_definition());
+        self
+    }
+
+    /// Once the signal fires the endpoint stops accepting connections and drains the invocations in
+    /// flight, as configured by [`RestateEndpointOptions::drain_timeout`].
+    pub fn shutdown_signal(mut self, signal: impl Future<Output = ()> + Send + 'static) -> Self {
+        self.shutdown = Some(signal.boxed());
+        self
+    }
+
+    /// Fails when a service or handler name is not valid in the discovery manifest, or is bound twice.
+    pub fn build(self) -> std::result::Result<RestateEndpoint, ConversionError> {
+        let mut registry = ServiceRegistry::new(self.services)?;
+        if let Some(limit) = self.options.max_concurrent_invocations {
+            registry = registry.max_concurrent_invocations(limit);
+        }
+        Ok(RestateEndpoint {
+            options: self.options,
+            registry: Arc::new(registry),
+            shutdown: self.shutdown,
+        })
+    }
+}
+
+pub struct RestateEndpoint {
+    options: RestateEndpointOptions,
+    registry: Arc<ServiceRegistry>,
+    shutdown: Option<BoxFuture<'static, ()>>,
+}
 
 impl RestateEndpoint {
-    pub async fn listen<H, F>(
-        self,
-        options: RestateEndpointOptions,
-        handler: H,
-    ) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>>
-    where
-        H: Fn(Request<hyper::body::Incoming>) -> F + Send + Clone + 'static,
-        F: Future<Output = Result<Response<BoxBody<Bytes, anyhow::Error>>>> + Send + 'static,
-    {
-        let addr = SocketAddr::from(([127, 0, 0, 1], options.listen_port));
+    pub fn builder() -> RestateEndpointBuilder {
+        RestateEndpointBuilder {
+            options: RestateEndpointOptions::default(),
+            services: vec![],
+            shutdown: None,
+        }
+    }
 
-        let listener = TcpListener::bind(addr).await?;
-        info!("Listening on http://{}", addr);
+    /// The bound services as a [`tower::Service`], to be served by an existing HTTP server instead of
+    /// [`RestateEndpoint::serve`]. The listen address, TLS and shutdown options are left to the server.
+    pub fn into_service(self) -> std::result::Result<RestateService, IdentityError> {
+        Ok(RestateService::new(
+            RequestGate::new(&self.options, Draining::default())?,
+            self.registry,
+        ))
+    }
+
+    /// The bound services as a handler of function events, for the services deployed as functions
+    /// rather than behind a listener. The listen address, TLS and shutdown options do not apply.
+    #[cfg(feature = "lambda")]
+    pub fn into_lambda(self) -> std::result::Result<LambdaHandler, IdentityError> {
+        LambdaHandler::new(&self.options, self.registry)
+    }
+
+    /// Serves the discovery manifest and the invocations of the bound services, over HTTP/2 with prior
+    /// knowledge or HTTP/1.1. The requests offering to upgrade HTTP/1.1 to h2c are served over HTTP/1.1,
+    /// in request-response mode.
+    pub async fn serve(self) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let listener = Listener::bind(&self.options).await?;
+        self.serve_on(listener).await
+    }
+
+    /// Serves the bound services on a listener bound by the caller, its address takes precedence over
+    /// the one of the options.
+    ///
+    /// Returns once the shutdown signal fired and the invocations in flight are drained.
+    pub async fn serve_on(
+        self,
+        listener: impl Into<Listener>,
+    ) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let listener = listener.into();
+        let acceptor = Acceptor::new(&self.options)?;
+        let registry = self.registry;
+        let handler = move |request| std::future::ready(Ok(registry.handle(request)));
+        let mut shutdown = self.shutdown.unwrap_or_else(|| std::future::pending().boxed());
+        info!("Listening on {}", listener);
         loop {
-            let (stream, _) = listener.accept().await?;
-            let io = TokioIo::new(stream);
-            let handler = handler.clone();
-            let executor = TokioExecutor::new();
-            tokio::task::spawn(async move {
-                if let Err(err) = http2::Builder::new(executor)
-                    .serve_connection(io, service_fn(handler))
-                    .await
-                {
-                    info!("Error serving connection: {:?}", err);
-                }
-            });
+            tokio::select! {
+                _ = &mut shutdown => break,
+                result = listener.accept(&acceptor, handler.clone()) => result?,
+            }
         }
+        // Connections are refused while draining
+        drop(listener);
+        info!("Shutting down, draining the invocations in flight");
+        acceptor.drain(self.options.drain_timeout).await;
+        Ok(())
     }
 }
 
-pub async fn endpoint<H, F>(
+/// Serves the services with the options, the services of a `#[restate::bundle]` module are listed by
+/// its `services()` function.
+pub async fn endpoint(
     options: RestateEndpointOptions,
-    handler: H,
-) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>>
-where
-    H: Fn(Request<hyper::body::Incoming>) -> F + Send + Clone + 'static,
-    F: Future<Output = Result<Response<BoxBody<Bytes, anyhow::Error>>>> + Send + 'static,
-{
-    let endpoint = RestateEndpoint {};
-    endpoint.listen(options, handler).await
+    services: impl IntoIterator<Item = ServiceDefinition>,
+) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    services
+        .into_iter()
+        .fold(RestateEndpoint::builder().options(options), |builder, service| {
+            builder.bind(service)
+        })
+        .build()?
+        .serve()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::Context,
+        endpoint::identity::tests::{key_pair, public_key, signed_headers},
+    };
+    use http::{HeaderMap, Method, StatusCode};
+    use http_body_util::Empty;
+    use hyper::client::conn::http2 as client;
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use std::time::Duration;
+    use tokio::{
+        io::{AsyncRead, AsyncWrite},
+        net::TcpListener,
+    };
+
+    async fn greet(_ctx: Context, name: String) -> std::result::Result<String, anyhow::Error> {
+        Ok(name)
+    }
+
+    pub(super) fn greeter_endpoint(options: RestateEndpointOptions) -> RestateEndpoint {
+        RestateEndpoint::builder()
+            .options(options)
+            .bind(ServiceDefinition::new("Greeter", ServiceType::Service).handler("greet", greet))
+            .build()
+            .unwrap()
+    }
+
+    pub(super) async fn discover<I>(stream: I) -> String
+    where
+        I: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let (status, body) = discover_with_headers(stream, HeaderMap::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        body
+    }
+
+    async fn discover_with_headers<I>(stream: I, headers: HeaderMap) -> (StatusCode, String)
+    where
+        I: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let (mut sender, connection) = client::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        let mut request = Request::builder()
+            .method(Method::GET)
+            .uri("http://localhost/discover")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        *request.headers_mut() = headers;
+        let response = sender.send_request(request).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_serve_on_bound_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(greeter_endpoint(RestateEndpointOptions::default()).serve_on(listener));
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        assert!(discover(stream).await.contains("\"Greeter\""));
+    }
+
+    #[tokio::test]
+    async fn test_bind_listen_address() {
+        let listener = Listener::bind(&RestateEndpointOptions {
+            listen_address: "127.0.0.1".to_string(),
+            listen_port: 0,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        assert!(listener.to_string().starts_with("http://127.0.0.1:"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_serve_on_unix_socket() {
+        let path = std::env::temp_dir().join(format!("restate-{}.sock", uuid::Uuid::new_v4()));
+        tokio::spawn(
+            greeter_endpoint(RestateEndpointOptions {
+                unix_socket: Some(path.clone()),
+                ..Default::default()
+            })
+            .serve(),
+        );
+
+        let stream = loop {
+            match tokio::net::UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        assert!(discover(stream).await.contains("\"Greeter\""));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_discover_over_http1() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(greeter_endpoint(RestateEndpointOptions::default()).serve_on(listener));
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("/discover")
+            .header("host", "localhost")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        // HTTP/1.1 cannot stream in both directions
+        assert!(String::from_utf8(body.to_vec())
+            .unwrap()
+            .contains("\"REQUEST_RESPONSE\""));
+    }
+
+    #[tokio::test]
+    async fn test_ignore_h2c_upgrade() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(greeter_endpoint(RestateEndpointOptions::default()).serve_on(listener));
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("/discover")
+            .header("host", "localhost")
+            .header("connection", "Upgrade, HTTP2-Settings")
+            .header("upgrade", "h2c")
+            .header("http2-settings", "AAMAAABkAAQCAAAAAAIAAAAA")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        // Served over HTTP/1.1 rather than switching protocols
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), http::Version::HTTP_11);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8(body.to_vec())
+            .unwrap()
+            .contains("\"REQUEST_RESPONSE\""));
+    }
+
+    #[tokio::test]
+    async fn test_verify_request_identity() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            greeter_endpoint(RestateEndpointOptions {
+                identity_keys: vec![public_key(&key_pair(1))],
+                ..Default::default()
+            })
+            .serve_on(listener),
+        );
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (status, body) = discover_with_headers(stream, signed_headers(&key_pair(1), "/discover")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("\"Greeter\""));
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (status, _) = discover_with_headers(stream, HeaderMap::new()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (status, _) = discover_with_headers(stream, signed_headers(&key_pair(2), "/discover")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_stops_accepting() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let endpoint = RestateEndpoint::builder()
+            .bind(ServiceDefinition::new("Greeter", ServiceType::Service).handler("greet", greet))
+            .shutdown_signal(async move {
+                let _ = shutdown_rx.await;
+            })
+            .build()
+            .unwrap();
+        let serving = tokio::spawn(endpoint.serve_on(listener));
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        assert!(discover(stream).await.contains("\"Greeter\""));
+
+        shutdown_tx.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(5), serving)
+            .await
+            .expect("The endpoint drains the idle connection")
+            .unwrap()
+            .unwrap();
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_serve_metrics() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            greeter_endpoint(RestateEndpointOptions {
+                identity_keys: vec![public_key(&key_pair(1))],
+                metrics: true,
+                ..Default::default()
+            })
+            .serve_on(listener),
+        );
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri("/metrics")
+            .header("host", "localhost")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        // Served without a request identity
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8(body.to_vec())
+            .unwrap()
+            .contains("# TYPE restate_sdk_invocations_started_total counter"));
+    }
 }
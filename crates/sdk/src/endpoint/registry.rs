@@ -0,0 +1,636 @@
+use crate::{
+    connection::{empty, setup_connection, Http2Receiver, Http2Sender},
+    context::ContextInstance,
+    endpoint::{discovery, handler::handle_invocation},
+    metrics::{InvocationTarget, METRICS},
+    payload::Payload,
+};
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use http::{Method, Request, Response, StatusCode};
+use http_body::Body;
+use http_body_util::{combinators::BoxBody, BodyExt};
+use restate_sdk_core::{Service, ServiceHandler};
+use restate_sdk_types::endpoint_manifest::{
+    self, error::ConversionError, HandlerName, HandlerType, InputPayload, OutputPayload, ProtocolMode,
+    ServiceName, ServiceType,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    marker::PhantomData,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, info};
+
+/// Connection of an invocation routed to a handler.
+pub struct Invocation {
+    pub receiver: Http2Receiver,
+    pub sender: Http2Sender,
+}
+
+/// Type erased handler of a registered service.
+pub type BoxedHandler = Box<
+    dyn Service<
+            Invocation,
+            Response = (),
+            Error = Infallible,
+            Future = BoxFuture<'static, Result<(), Infallible>>,
+        > + Send
+        + Sync,
+>;
+
+struct HandlerService<Context, Func, Input, Output> {
+    handler: Func,
+    _types: PhantomData<fn(Context, Input) -> Output>,
+}
+
+impl<Context, Func, Input, Output> Service<Invocation> for HandlerService<Context, Func, Input, Output>
+where
+    Input: Payload,
+    Output: Payload,
+    Func: ServiceHandler<Context, Input, Output = Result<Output, anyhow::Error>>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    Context: ContextInstance,
+{
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<(), Infallible>>;
+    type Response = ();
+
+    fn call(&self, invocation: Invocation) -> Self::Future {
+        let handler = self.handler.clone();
+        Box::pin(async move {
+            handle_invocation(handler, None, invocation.receiver, invocation.sender, false).await;
+            Ok(())
+        })
+    }
+}
+
+/// Settings of a service announced in the discovery manifest and applied by the runtime, the unset
+/// ones default to the configuration of the runtime. Durations are truncated to milliseconds.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceOptions {
+    pub documentation: Option<String>,
+    pub metadata: HashMap<String, String>,
+    /// Time after which an idle invocation is suspended, or aborted when it cannot suspend
+    pub inactivity_timeout: Option<Duration>,
+    /// Time after which an invocation is aborted once the inactivity timeout fired
+    pub abort_timeout: Option<Duration>,
+    /// Time the journal of a completed invocation is retained for
+    pub journal_retention: Option<Duration>,
+    /// Time the response of an idempotent invocation is retained for
+    pub idempotency_retention: Option<Duration>,
+    /// Only other services can invoke the service, it is not exposed by the ingress
+    pub ingress_private: bool,
+}
+
+/// Settings of a handler announced in the discovery manifest, overriding the ones of its service.
+#[derive(Debug, Clone, Default)]
+pub struct HandlerOptions {
+    /// Kind of the handler, by default the handlers of objects are exclusive and the ones of workflows
+    /// are shared, except for the `run` handler running the workflow
+    pub ty: Option<HandlerType>,
+    /// Invocations of the handler running at once on the endpoint, the excess ones are rejected with
+    /// `503 Service Unavailable` for the runtime to retry them. Not announced in the manifest.
+    pub max_concurrent_invocations: Option<usize>,
+    pub documentation: Option<String>,
+    pub metadata: HashMap<String, String>,
+    pub inactivity_timeout: Option<Duration>,
+    pub abort_timeout: Option<Duration>,
+    pub journal_retention: Option<Duration>,
+    pub idempotency_retention: Option<Duration>,
+    /// Time the completion of a workflow is retained for, only for the `run` handler of a workflow
+    pub workflow_completion_retention: Option<Duration>,
+    pub ingress_private: bool,
+}
+
+fn millis(duration: Option<Duration>) -> Option<u64> {
+    duration.map(|duration| duration.as_millis() as u64)
+}
+
+fn ingress_private(ingress_private: bool) -> Option<bool> {
+    ingress_private.then_some(true)
+}
+
+struct HandlerDefinition {
+    name: String,
+    ty: Option<HandlerType>,
+    input: InputPayload,
+    output: OutputPayload,
+    options: HandlerOptions,
+    service: BoxedHandler,
+}
+
+impl HandlerDefinition {
+    fn manifest(&self) -> Result<endpoint_manifest::Handler, ConversionError> {
+        let options = &self.options;
+        Ok(endpoint_manifest::Handler {
+            abort_timeout: millis(options.abort_timeout),
+            documentation: options.documentation.clone(),
+            idempotency_retention: millis(options.idempotency_retention),
+            inactivity_timeout: millis(options.inactivity_timeout),
+            ingress_private: ingress_private(options.ingress_private),
+            input: Some(self.input.clone()),
+            journal_retention: millis(options.journal_retention),
+            metadata: options.metadata.clone(),
+            name: HandlerName::try_from(&self.name)?,
+            output: Some(self.output.clone()),
+            ty: self.ty,
+            workflow_completion_retention: millis(options.workflow_completion_retention),
+        })
+    }
+}
+
+/// Handlers of a service to bind to an endpoint, together with their manifest.
+pub struct ServiceDefinition {
+    name: String,
+    ty: ServiceType,
+    options: ServiceOptions,
+    handlers: Vec<HandlerDefinition>,
+}
+
+impl ServiceDefinition {
+    pub fn new(name: impl Into<String>, ty: ServiceType) -> Self {
+        Self {
+            name: name.into(),
+            ty,
+            options: ServiceOptions::default(),
+            handlers: vec![],
+        }
+    }
+
+    pub fn options(mut self, options: ServiceOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn handler<Context, Func, Input, Output>(self, name: impl Into<String>, handler: Func) -> Self
+    where
+        Input: Payload + 'static,
+        Output: Payload + 'static,
+        Func: ServiceHandler<Context, Input, Output = Result<Output, anyhow::Error>>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        Context: ContextInstance + 'static,
+    {
+        self.handler_with_options(name, handler, HandlerOptions::default())
+    }
+
+    pub fn handler_with_options<Context, Func, Input, Output>(
+        mut self,
+        name: impl Into<String>,
+        handler: Func,
+        options: HandlerOptions,
+    ) -> Self
+    where
+        Input: Payload + 'static,
+        Output: Payload + 'static,
+        Func: ServiceHandler<Context, Input, Output = Result<Output, anyhow::Error>>
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        Context: ContextInstance + 'static,
+    {
+        let name = name.into();
+        let ty = options.ty.or(match self.ty {
+            ServiceType::Service => None,
+            ServiceType::VirtualObject => Some(HandlerType::Exclusive),
+            ServiceType::Workflow if name == "run" => Some(HandlerType::Workflow),
+            ServiceType::Workflow => Some(HandlerType::Shared),
+        });
+        self.handlers.push(HandlerDefinition {
+            name,
+            ty,
+            input: Input::input_payload(),
+            output: Output::output_payload(),
+            options,
+            service: Box::new(HandlerService {
+                handler,
+                _types: PhantomData,
+            }),
+        });
+        self
+    }
+
+    fn manifest(&self) -> Result<endpoint_manifest::Service, ConversionError> {
+        let options = &self.options;
+        Ok(endpoint_manifest::Service {
+            abort_timeout: millis(options.abort_timeout),
+            documentation: options.documentation.clone(),
+            handlers: self
+                .handlers
+                .iter()
+                .map(HandlerDefinition::manifest)
+                .collect::<Result<_, ConversionError>>()?,
+            idempotency_retention: millis(options.idempotency_retention),
+            inactivity_timeout: millis(options.inactivity_timeout),
+            ingress_private: ingress_private(options.ingress_private),
+            journal_retention: millis(options.journal_retention),
+            metadata: options.metadata.clone(),
+            name: ServiceName::try_from(&self.name)?,
+            ty: self.ty,
+        })
+    }
+}
+
+/// Implemented by the services generated with `#[restate::service]`, `#[restate::object]` and
+/// `#[restate::workflow]`.
+pub trait IntoServiceDefinition {
+    fn into_definition(self) -> ServiceDefinition;
+}
+
+impl IntoServiceDefinition for ServiceDefinition {
+    fn into_definition(self) -> ServiceDefinition {
+        self
+    }
+}
+
+/// Delay the runtime is asked to wait for before retrying an invocation rejected by a concurrency limit.
+const RETRY_AFTER_SECONDS: u64 = 1;
+
+/// Handler routed to by the registry, together with the permits of its concurrency limit.
+struct RegisteredHandler {
+    service: BoxedHandler,
+    limit: Option<Arc<Semaphore>>,
+}
+
+/// Services bound to an endpoint, serving the discovery manifest and routing invocations to their
+/// handlers.
+pub struct ServiceRegistry {
+    manifest: endpoint_manifest::Endpoint,
+    handlers: HashMap<String, RegisteredHandler>,
+    limit: Option<Arc<Semaphore>>,
+}
+
+impl ServiceRegistry {
+    /// Fails when a service or handler name is not valid in the discovery manifest, or when two
+    /// services or two handlers of a service share a name.
+    pub fn new(services: Vec<ServiceDefinition>) -> Result<Self, ConversionError> {
+        let mut service_names = HashSet::new();
+        for service in &services {
+            if !service_names.insert(&service.name) {
+                return Err(format!("The service `{}` is bound more than once", service.name).into());
+            }
+            let mut handler_names = HashSet::new();
+            for handler in &service.handlers {
+                if !handler_names.insert(&handler.name) {
+                    return Err(format!(
+                        "The service `{}` has more than one handler named `{}`",
+                        service.name, handler.name
+                    )
+                    .into());
+                }
+            }
+        }
+        let manifest = endpoint_manifest::Endpoint {
+            max_protocol_version: 1,
+            min_protocol_version: 1,
+            protocol_mode: Some(ProtocolMode::BidiStream),
+            services: services
+                .iter()
+                .map(ServiceDefinition::manifest)
+                .collect::<Result<_, _>>()?,
+        };
+        let handlers = services
+            .into_iter()
+            .flat_map(|service| {
+                let ServiceDefinition { name, handlers, .. } = service;
+                handlers.into_iter().map(move |handler| {
+                    (format!("/invoke/{}/{}", name, handler.name), RegisteredHandler {
+                        service: handler.service,
+                        limit: handler
+                            .options
+                            .max_concurrent_invocations
+                            .map(|limit| Arc::new(Semaphore::new(limit))),
+                    })
+                })
+            })
+            .collect();
+        Ok(Self {
+            manifest,
+            handlers,
+            limit: None,
+        })
+    }
+
+    /// Invocations of all the handlers running at once, the excess ones are rejected with
+    /// `503 Service Unavailable` for the runtime to retry them.
+    pub fn max_concurrent_invocations(mut self, limit: usize) -> Self {
+        self.limit = Some(Arc::new(Semaphore::new(limit)));
+        self
+    }
+
+    /// Permits of the endpoint and the handler limits, held until the invocation ends.
+    fn acquire(
+        &self,
+        handler: &RegisteredHandler,
+    ) -> Option<(Option<OwnedSemaphorePermit>, Option<OwnedSemaphorePermit>)> {
+        let endpoint = match &self.limit {
+            Some(limit) => Some(limit.clone().try_acquire_owned().ok()?),
+            None => None,
+        };
+        let handler = match &handler.limit {
+            Some(limit) => Some(limit.clone().try_acquire_owned().ok()?),
+            None => None,
+        };
+        Some((endpoint, handler))
+    }
+
+    pub fn handle<B>(&self, req: Request<B>) -> Response<BoxBody<Bytes, anyhow::Error>>
+    where
+        B: Body<Data = Bytes> + Send + 'static,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        debug!("{}, {}", req.method(), req.uri().path());
+        let method = req.method().clone();
+        let path = req.uri().path().to_owned();
+        match (method, self.handlers.get(&path)) {
+            (Method::GET, _) if path == "/discover" => discovery::response(&self.manifest, &req),
+            (Method::POST, Some(handler)) => {
+                let Some(permits) = self.acquire(handler) else {
+                    info!("Rejecting {}, too many invocations running", path);
+                    METRICS
+                        .invocations_rejected
+                        .inc(&InvocationTarget::from_path(&path));
+                    return Response::builder()
+                        .status(StatusCode::SERVICE_UNAVAILABLE)
+                        .header("retry-after", RETRY_AFTER_SECONDS)
+                        .body(empty().map_err(|e| e.into()).boxed())
+                        .unwrap();
+                };
+                let (receiver, sender, boxed_body) = setup_connection(req);
+                let invocation = handler.service.call(Invocation { receiver, sender });
+                tokio::spawn(async move {
+                    let _ = invocation.await;
+                    drop(permits);
+                });
+                Response::builder()
+                    .status(StatusCode::OK)
+                    .header("content-type", "application/vnd.restate.invocation.v1")
+                    .header("x-restate-server", "restate-sdk-rust/0.1.0")
+                    .body(boxed_body)
+                    .unwrap()
+            }
+            // Return the 404 Not Found for other routes.
+            (method, _) => {
+                info!("No route for {} {}", method, path);
+                Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(empty().map_err(|e| e.into()).boxed())
+                    .unwrap()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::{Context, ObjectContext, WorkflowContext},
+        payload::{Empty, Raw},
+    };
+    use http_body_util::Full;
+    use prost::Message;
+    use restate_sdk_types::{
+        journal::raw::{PlainEntryHeader, PlainRawEntry},
+        service_protocol::{InputEntryMessage, ServiceProtocolVersion, StartMessage},
+    };
+    use restate_service_protocol::message::{Encoder, ProtocolMessage};
+
+    async fn greet(_ctx: Context, name: String) -> Result<String, anyhow::Error> {
+        Ok(name)
+    }
+
+    async fn add(_ctx: ObjectContext, value: u64) -> Result<u64, anyhow::Error> {
+        Ok(value)
+    }
+
+    async fn run(_ctx: WorkflowContext, value: u64) -> Result<u64, anyhow::Error> {
+        Ok(value)
+    }
+
+    #[test]
+    fn test_manifest_of_bound_services() {
+        let registry = ServiceRegistry::new(vec![
+            ServiceDefinition::new("Greeter", ServiceType::Service).handler("greet", greet),
+            ServiceDefinition::new("Counter", ServiceType::VirtualObject).handler("add", add),
+            ServiceDefinition::new("Signup", ServiceType::Workflow)
+                .handler("run", run)
+                .handler("add", add),
+        ])
+        .unwrap();
+
+        let manifest = &registry.manifest;
+        assert_eq!(manifest.protocol_mode, Some(ProtocolMode::BidiStream));
+        let handlers = manifest
+            .services
+            .iter()
+            .flat_map(|service| {
+                service
+                    .handlers
+                    .iter()
+                    .map(move |handler| (service.name.to_string(), handler.name.to_string(), handler.ty))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(handlers, vec![
+            ("Greeter".to_string(), "greet".to_string(), None),
+            (
+                "Counter".to_string(),
+                "add".to_string(),
+                Some(HandlerType::Exclusive)
+            ),
+            (
+                "Signup".to_string(),
+                "run".to_string(),
+                Some(HandlerType::Workflow)
+            ),
+            ("Signup".to_string(), "add".to_string(), Some(HandlerType::Shared)),
+        ]);
+        assert!(registry.handlers.contains_key("/invoke/Signup/run"));
+    }
+
+    #[test]
+    fn test_manifest_of_service_options() {
+        let service = ServiceDefinition::new("Greeter", ServiceType::Service)
+            .options(ServiceOptions {
+                documentation: Some("Greets people".to_string()),
+                inactivity_timeout: Some(Duration::from_secs(60)),
+                ..Default::default()
+            })
+            .handler_with_options("greet", greet, HandlerOptions {
+                metadata: HashMap::from([("team".to_string(), "onboarding".to_string())]),
+                abort_timeout: Some(Duration::from_millis(1500)),
+                ingress_private: true,
+                ..Default::default()
+            });
+        let registry = ServiceRegistry::new(vec![service]).unwrap();
+
+        let service = &registry.manifest.services[0];
+        assert_eq!(service.documentation.as_deref(), Some("Greets people"));
+        assert_eq!(service.inactivity_timeout, Some(60_000));
+        assert_eq!(service.ingress_private, None);
+        let handler = &service.handlers[0];
+        assert_eq!(handler.metadata["team"], "onboarding");
+        assert_eq!(handler.abort_timeout, Some(1500));
+        assert_eq!(handler.ingress_private, Some(true));
+        assert_eq!(handler.inactivity_timeout, None);
+    }
+
+    #[test]
+    fn test_handler_type_of_options() {
+        let service = ServiceDefinition::new("Counter", ServiceType::VirtualObject)
+            .handler_with_options("get", add, HandlerOptions {
+                ty: Some(HandlerType::Shared),
+                ..Default::default()
+            })
+            .handler("add", add);
+        let types = service
+            .manifest()
+            .unwrap()
+            .handlers
+            .iter()
+            .map(|handler| handler.ty)
+            .collect::<Vec<_>>();
+        assert_eq!(types, vec![
+            Some(HandlerType::Shared),
+            Some(HandlerType::Exclusive)
+        ]);
+    }
+
+    #[test]
+    fn test_payloads_of_handlers() {
+        async fn receive(_ctx: Context, _body: Raw) -> Result<Empty, anyhow::Error> {
+            Ok(Empty)
+        }
+
+        let service = ServiceDefinition::new("Webhook", ServiceType::Service)
+            .handler("greet", greet)
+            .handler("receive", receive);
+        let payloads = service
+            .manifest()
+            .unwrap()
+            .handlers
+            .into_iter()
+            .map(|handler| {
+                let (input, output) = (handler.input.unwrap(), handler.output.unwrap());
+                (input.content_type, input.required, output.content_type)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(payloads, vec![
+            (
+                Some("application/json".to_string()),
+                Some(true),
+                Some("application/json".to_string())
+            ),
+            (Some("*/*".to_string()), Some(false), None),
+        ]);
+    }
+
+    async fn slow_greet(_ctx: Context, name: String) -> Result<String, anyhow::Error> {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        Ok(name)
+    }
+
+    fn invoke(path: &str) -> Request<Full<Bytes>> {
+        let encoder = Encoder::new(ServiceProtocolVersion::V1);
+        let mut body = encoder
+            .encode(ProtocolMessage::Start(StartMessage {
+                known_entries: 1,
+                ..Default::default()
+            }))
+            .to_vec();
+        body.extend_from_slice(
+            &encoder.encode(
+                PlainRawEntry::new(
+                    PlainEntryHeader::Input,
+                    InputEntryMessage {
+                        value: "\"Till\"".into(),
+                        ..Default::default()
+                    }
+                    .encode_to_vec()
+                    .into(),
+                )
+                .into(),
+            ),
+        );
+        Request::post(path).body(Full::new(body.into())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_limit_concurrent_invocations_of_handler() {
+        let registry = ServiceRegistry::new(vec![ServiceDefinition::new("Greeter", ServiceType::Service)
+            .handler_with_options("slow", slow_greet, HandlerOptions {
+                max_concurrent_invocations: Some(1),
+                ..Default::default()
+            })
+            .handler("greet", greet)])
+        .unwrap();
+
+        let running = registry.handle(invoke("/invoke/Greeter/slow"));
+        assert_eq!(running.status(), StatusCode::OK);
+        let rejected = registry.handle(invoke("/invoke/Greeter/slow"));
+        assert_eq!(rejected.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(rejected.headers()["retry-after"], "1");
+        assert_eq!(
+            registry.handle(invoke("/invoke/Greeter/greet")).status(),
+            StatusCode::OK
+        );
+
+        // The permit is released once the invocation ends
+        running.into_body().collect().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while registry.handle(invoke("/invoke/Greeter/slow")).status() != StatusCode::OK {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_limit_concurrent_invocations_of_endpoint() {
+        let registry = ServiceRegistry::new(vec![ServiceDefinition::new("Greeter", ServiceType::Service)
+            .handler("slow", slow_greet)
+            .handler("greet", greet)])
+        .unwrap()
+        .max_concurrent_invocations(1);
+
+        let running = registry.handle(invoke("/invoke/Greeter/slow"));
+        assert_eq!(running.status(), StatusCode::OK);
+        assert_eq!(
+            registry.handle(invoke("/invoke/Greeter/greet")).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        // Discovery is not limited
+        let discover = Request::get("/discover").body(Full::new(Bytes::new())).unwrap();
+        assert_eq!(registry.handle(discover).status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_reject_duplicate_names() {
+        let greeter = || ServiceDefinition::new("Greeter", ServiceType::Service).handler("greet", greet);
+        let error = ServiceRegistry::new(vec![greeter(), greeter()]).err().unwrap();
+        assert!(error.to_string().contains("`Greeter` is bound more than once"));
+
+        let service = greeter().handler("greet", greet);
+        let error = ServiceRegistry::new(vec![service]).err().unwrap();
+        assert!(error
+            .to_string()
+            .contains("`Greeter` has more than one handler named `greet`"));
+    }
+
+    #[test]
+    fn test_reject_invalid_service_name() {
+        let service = ServiceDefinition::new("Not a name", ServiceType::Service).handler("greet", greet);
+        assert!(ServiceRegistry::new(vec![service]).is_err());
+    }
+}
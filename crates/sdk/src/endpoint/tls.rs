@@ -0,0 +1,227 @@
+pub use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{
+    crypto::ring,
+    pki_types::pem::{self, PemObject},
+    server::{VerifierBuilderError, WebPkiClientVerifier},
+    RootCertStore, ServerConfig,
+};
+use std::{path::Path, sync::Arc, time::Duration};
+use thiserror::Error;
+use tokio_rustls::TlsAcceptor;
+
+/// Protocols announced through ALPN, HTTP/2 is preferred to stream invocations in both directions.
+const ALPN_PROTOCOLS: [&[u8]; 2] = [b"h2", b"http/1.1"];
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("Invalid PEM: {0}")]
+    Pem(#[from] pem::Error),
+    #[error("Invalid TLS configuration: {0}")]
+    Rustls(#[from] rustls::Error),
+    #[error("Invalid client certificate verifier: {0}")]
+    Verifier(#[from] VerifierBuilderError),
+}
+
+/// Certificate chain and private key the endpoint terminates TLS with.
+#[derive(Clone)]
+pub struct TlsOptions {
+    certificates: Vec<CertificateDer<'static>>,
+    private_key: Arc<PrivateKeyDer<'static>>,
+    client_ca_certificates: Option<Vec<CertificateDer<'static>>>,
+    pub(crate) handshake_timeout: Duration,
+}
+
+impl TlsOptions {
+    /// The certificate chain starts with the certificate of the endpoint.
+    pub fn new(certificates: Vec<CertificateDer<'static>>, private_key: PrivateKeyDer<'static>) -> Self {
+        Self {
+            certificates,
+            private_key: Arc::new(private_key),
+            client_ca_certificates: None,
+            handshake_timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Reads the certificate chain and the private key from PEM encoded data.
+    pub fn from_pem(certificates: &[u8], private_key: &[u8]) -> Result<Self, TlsError> {
+        Ok(Self::new(
+            CertificateDer::pem_slice_iter(certificates).collect::<Result<_, _>>()?,
+            PrivateKeyDer::from_pem_slice(private_key)?,
+        ))
+    }
+
+    /// Reads the certificate chain and the private key from PEM files.
+    pub fn from_pem_files(
+        certificates: impl AsRef<Path>,
+        private_key: impl AsRef<Path>,
+    ) -> Result<Self, TlsError> {
+        Ok(Self::new(
+            CertificateDer::pem_file_iter(certificates)?.collect::<Result<_, _>>()?,
+            PrivateKeyDer::from_pem_file(private_key)?,
+        ))
+    }
+
+    /// Rejects the clients not presenting a certificate issued by one of the certificate authorities.
+    pub fn require_client_certificates(mut self, ca_certificates: Vec<CertificateDer<'static>>) -> Self {
+        self.client_ca_certificates = Some(ca_certificates);
+        self
+    }
+
+    /// Same as [`TlsOptions::require_client_certificates`], with the certificate authorities read from
+    /// a PEM file.
+    pub fn require_client_certificates_from_pem_file(
+        self,
+        ca_certificates: impl AsRef<Path>,
+    ) -> Result<Self, TlsError> {
+        let ca_certificates = CertificateDer::pem_file_iter(ca_certificates)?.collect::<Result<_, _>>()?;
+        Ok(self.require_client_certificates(ca_certificates))
+    }
+
+    /// Time a client is given to complete the TLS handshake before its connection is closed, 10 seconds
+    /// by default.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    pub(crate) fn acceptor(&self) -> Result<TlsAcceptor, TlsError> {
+        let provider = Arc::new(ring::default_provider());
+        let builder =
+            ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions()?;
+        let builder = match &self.client_ca_certificates {
+            Some(ca_certificates) => {
+                let mut roots = RootCertStore::empty();
+                for certificate in ca_certificates {
+                    roots.add(certificate.clone())?;
+                }
+                builder.with_client_cert_verifier(
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?,
+                )
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder.with_single_cert(self.certificates.clone(), self.private_key.clone_key())?;
+        config.alpn_protocols = ALPN_PROTOCOLS.iter().map(|protocol| protocol.to_vec()).collect();
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoint::{
+        tests::{discover, greeter_endpoint},
+        RestateEndpointOptions,
+    };
+    use rustls::ClientConfig;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use tokio_rustls::{client::TlsStream, TlsConnector};
+
+    fn testdata(name: &str) -> String {
+        format!("{}/testdata/tls/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn server_tls() -> TlsOptions {
+        TlsOptions::from_pem_files(testdata("server.pem"), testdata("server.key")).unwrap()
+    }
+
+    async fn serve(tls: TlsOptions) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            greeter_endpoint(RestateEndpointOptions {
+                tls: Some(tls),
+                ..Default::default()
+            })
+            .serve_on(listener),
+        );
+        addr
+    }
+
+    async fn connect(
+        addr: std::net::SocketAddr,
+        client_certificate: bool,
+    ) -> std::io::Result<TlsStream<tokio::net::TcpStream>> {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(CertificateDer::from_pem_file(testdata("ca.pem")).unwrap())
+            .unwrap();
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let mut config = if client_certificate {
+            builder
+                .with_client_auth_cert(
+                    CertificateDer::pem_file_iter(testdata("client.pem"))
+                        .unwrap()
+                        .collect::<Result<_, _>>()
+                        .unwrap(),
+                    PrivateKeyDer::from_pem_file(testdata("client.key")).unwrap(),
+                )
+                .unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+        config.alpn_protocols = vec![b"h2".to_vec()];
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        TlsConnector::from(Arc::new(config))
+            .connect("localhost".try_into().unwrap(), stream)
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_serve_over_tls() {
+        let addr = serve(server_tls()).await;
+
+        let stream = connect(addr, false).await.unwrap();
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+        assert!(discover(stream).await.contains("\"Greeter\""));
+    }
+
+    #[tokio::test]
+    async fn test_verify_client_certificate() {
+        let tls = server_tls()
+            .require_client_certificates_from_pem_file(testdata("ca.pem"))
+            .unwrap();
+        let addr = serve(tls).await;
+
+        let stream = connect(addr, true).await.unwrap();
+        assert!(discover(stream).await.contains("\"Greeter\""));
+
+        // With TLS 1.3 the client only learns about the rejection once it reads
+        let rejected = match connect(addr, false).await {
+            Ok(mut stream) => !matches!(stream.read(&mut [0; 1]).await, Ok(1)),
+            Err(_) => true,
+        };
+        assert!(rejected);
+    }
+
+    #[tokio::test]
+    async fn test_close_stalled_handshake() {
+        let addr = serve(server_tls().handshake_timeout(Duration::from_millis(100))).await;
+
+        // Sends a partial record header and never completes the ClientHello
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&[0x16, 0x03, 0x01]).await.unwrap();
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut [0; 1]))
+            .await
+            .expect("The endpoint closes the connection once the handshake timed out");
+        assert!(!matches!(read, Ok(1)));
+
+        // The accept loop still serves the other clients
+        let stream = connect(addr, false).await.unwrap();
+        assert!(discover(stream).await.contains("\"Greeter\""));
+    }
+
+    #[test]
+    fn test_reject_invalid_pem() {
+        assert!(matches!(
+            TlsOptions::from_pem(b"not a certificate", b"not a key"),
+            Err(TlsError::Pem(_))
+        ));
+    }
+}
@@ -0,0 +1,301 @@
+use crate::{
+    connection::{empty, full, ConnectionOptions, Draining},
+    endpoint::{
+        identity::{IdentityError, IdentityVerifier},
+        RestateEndpointOptions, ServiceRegistry,
+    },
+    metrics,
+};
+use bytes::Bytes;
+use futures_util::future::{self, Ready};
+use http::{Method, Request, Response, StatusCode};
+use http_body::Body;
+use http_body_util::{combinators::BoxBody, BodyExt};
+use std::{
+    convert::Infallible,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tracing::info;
+
+/// Checks applied to every request before it reaches the handler of the endpoint.
+#[derive(Clone)]
+pub(crate) struct RequestGate {
+    connection: ConnectionOptions,
+    draining: Draining,
+    metrics: bool,
+    identity: Option<Arc<IdentityVerifier>>,
+    /// Prefix stripped from the path by the server routing to the endpoint, part of the signed path
+    path_prefix: Arc<str>,
+}
+
+impl RequestGate {
+    pub(crate) fn new(options: &RestateEndpointOptions, draining: Draining) -> Result<Self, IdentityError> {
+        Ok(Self {
+            connection: options.connection,
+            draining,
+            metrics: options.metrics,
+            identity: match options.identity_keys.as_slice() {
+                [] => None,
+                keys => Some(Arc::new(IdentityVerifier::new(keys)?)),
+            },
+            path_prefix: "".into(),
+        })
+    }
+
+    /// Answers the metrics and the unauthorized requests, else hands over the connection options and the
+    /// draining signal to the handler.
+    pub(crate) fn intercept<B>(
+        &self,
+        request: &mut Request<B>,
+    ) -> Option<Response<BoxBody<Bytes, anyhow::Error>>> {
+        // Scraped by Prometheus, which holds no request identity. Only this exact route skips the
+        // verification, it exposes counters without any payload of the invocations.
+        if self.metrics && request.method() == Method::GET && request.uri().path() == "/metrics" {
+            return Some(
+                Response::builder()
+                    .status(StatusCode::OK)
+                    .header("content-type", metrics::CONTENT_TYPE)
+                    .body(full(metrics::render()).map_err(|e| e.into()).boxed())
+                    .unwrap(),
+            );
+        }
+        if let Some(identity) = &self.identity {
+            // Restate signs the path of the endpoint URL it was registered with
+            let path = format!("{}{}", self.path_prefix, request.uri().path());
+            if let Err(err) = identity.verify(&path, request.headers()) {
+                info!("Rejecting {} {}: {}", request.method(), request.uri().path(), err);
+                return Some(
+                    Response::builder()
+                        .status(StatusCode::UNAUTHORIZED)
+                        .body(empty().map_err(|e| e.into()).boxed())
+                        .unwrap(),
+                );
+            }
+        }
+        // The connection options and the draining signal are picked up by `setup_connection`
+        request.extensions_mut().insert(self.connection);
+        request.extensions_mut().insert(self.draining.clone());
+        None
+    }
+}
+
+/// The discovery and invoke routes of an endpoint as a [`tower::Service`], to be mounted on an existing
+/// HTTP server and wrapped with tower middleware.
+///
+/// The service accepts any request body. There is no dedicated axum adapter, an axum `Router` mounts the
+/// service with `nest_service` or `fallback_service`. Nested under a prefix, the endpoint routes on the
+/// path with the prefix stripped, the endpoint URL registered to Restate includes the prefix, and the
+/// prefix is given to [`RestateService::path_prefix`] for the request identity to be verified.
+///
+/// Invocations are streamed in both directions over HTTP/2, they run in request-response mode over
+/// HTTP/1.1. Draining the invocations in flight is left to the server.
+#[derive(Clone)]
+pub struct RestateService {
+    gate: RequestGate,
+    registry: Arc<ServiceRegistry>,
+}
+
+impl RestateService {
+    pub(crate) fn new(gate: RequestGate, registry: Arc<ServiceRegistry>) -> Self {
+        Self { gate, registry }
+    }
+
+    /// Prefix the server strips from the path before calling the service, `/restate` when nested with
+    /// `nest_service("/restate", ..)`.
+    pub fn path_prefix(mut self, prefix: impl AsRef<str>) -> Self {
+        self.gate.path_prefix = prefix.as_ref().trim_end_matches('/').into();
+        self
+    }
+}
+
+impl<B> tower::Service<Request<B>> for RestateService
+where
+    B: Body<Data = Bytes> + Send + 'static,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Infallible>>;
+    type Response = Response<BoxBody<Bytes, anyhow::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        let response = match self.gate.intercept(&mut request) {
+            Some(response) => response,
+            None => self.registry.handle(request),
+        };
+        future::ready(Ok(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::Context,
+        endpoint::{
+            identity::tests::{key_pair, public_key, signed_headers},
+            RestateEndpoint, ServiceDefinition, ServiceType,
+        },
+    };
+    use http_body_util::{Empty, Full};
+    use hyper_util::{
+        rt::{TokioExecutor, TokioIo},
+        server::conn::auto,
+        service::TowerToHyperService,
+    };
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+    use tower_http::normalize_path::NormalizePathLayer;
+
+    async fn greet(_ctx: Context, name: String) -> Result<String, anyhow::Error> {
+        Ok(name)
+    }
+
+    fn greeter_service() -> RestateService {
+        RestateEndpoint::builder()
+            .bind(ServiceDefinition::new("Greeter", ServiceType::Service).handler("greet", greet))
+            .build()
+            .unwrap()
+            .into_service()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_call_service() {
+        let request = Request::get("/discover").body(Full::new(Bytes::new())).unwrap();
+        let response = greeter_service().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8(body.to_vec()).unwrap().contains("\"Greeter\""));
+
+        let request = Request::get("/unknown").body(Full::new(Bytes::new())).unwrap();
+        let response = greeter_service().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_nest_in_axum_router() {
+        let router = axum::Router::new()
+            .route("/health", axum::routing::get(|| async { "ok" }))
+            .nest_service("/restate", greeter_service());
+
+        let request = Request::get("/restate/discover")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8(body.to_vec()).unwrap().contains("\"Greeter\""));
+
+        let request = Request::post("/restate/invoke/Greeter/greet")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/vnd.restate.invocation.v1"
+        );
+
+        // The routes of the endpoint are only served under the prefix
+        let request = Request::get("/discover").body(axum::body::Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let request = Request::get("/health").body(axum::body::Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_verify_identity_nested_in_axum_router() {
+        let service = RestateEndpoint::builder()
+            .options(RestateEndpointOptions {
+                identity_keys: vec![public_key(&key_pair(1))],
+                ..Default::default()
+            })
+            .bind(ServiceDefinition::new("Greeter", ServiceType::Service).handler("greet", greet))
+            .build()
+            .unwrap()
+            .into_service()
+            .unwrap()
+            .path_prefix("/restate");
+        let router = axum::Router::new().nest_service("/restate", service);
+        let status = |signed_path: &str| {
+            let mut request = Request::get("/restate/discover")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            *request.headers_mut() = signed_headers(&key_pair(1), signed_path);
+            let response = router.clone().oneshot(request);
+            async move { response.await.unwrap().status() }
+        };
+
+        // Signed for the URL registered to Restate, prefix included
+        assert_eq!(status("/restate/discover").await, StatusCode::OK);
+        assert_eq!(status("/discover").await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_serve_with_middleware() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = tower::ServiceBuilder::new()
+            .layer(NormalizePathLayer::trim_trailing_slash())
+            .service(greeter_service());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            auto::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), TowerToHyperService::new(service))
+                .await
+                .unwrap();
+        });
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        let request = Request::get("/discover/")
+            .header("host", "localhost")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_verify_all_but_metrics() {
+        let service = RestateEndpoint::builder()
+            .options(RestateEndpointOptions {
+                identity_keys: vec![public_key(&key_pair(1))],
+                metrics: true,
+                ..Default::default()
+            })
+            .bind(ServiceDefinition::new("Greeter", ServiceType::Service).handler("greet", greet))
+            .build()
+            .unwrap()
+            .into_service()
+            .unwrap();
+        let status = |method: Method, path: &str| {
+            let request = Request::builder()
+                .method(method)
+                .uri(path)
+                .body(Full::new(Bytes::new()))
+                .unwrap();
+            let response = service.clone().oneshot(request);
+            async move { response.await.unwrap().status() }
+        };
+
+        assert_eq!(status(Method::GET, "/metrics").await, StatusCode::OK);
+        assert_eq!(status(Method::POST, "/metrics").await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Method::GET, "/metrics/").await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(Method::GET, "/discover").await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            status(Method::POST, "/invoke/Greeter/greet").await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
@@ -1 +1,118 @@
+use restate_sdk_types::{
+    errors::{codes, InvocationError},
+    journal::raw::RawEntryCodecError,
+    service_protocol,
+};
+use restate_service_protocol::message::{MessageType, ProtocolMessage};
+use std::{any::Any, backtrace::Backtrace, cell::RefCell, fmt, panic, sync::Once};
 
+thread_local! {
+    static PANIC_BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+/// Chains a panic hook recording the backtrace of the panicking thread, so that it can be
+/// reported together with the panic once it has been caught.
+pub(crate) fn capture_panic_backtraces() {
+    PANIC_HOOK.call_once(|| {
+        let previous_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            PANIC_BACKTRACE.with(|backtrace| *backtrace.borrow_mut() = Some(Backtrace::force_capture()));
+            previous_hook(info);
+        }));
+    });
+}
+
+/// Converts a caught panic into a retryable error.
+pub(crate) fn panic_error(payload: Box<dyn Any + Send>) -> InvocationError {
+    let message = if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    };
+    let error = InvocationError::internal(format!("Handler panicked: {}", message));
+    match PANIC_BACKTRACE.with(|backtrace| backtrace.borrow_mut().take()) {
+        Some(backtrace) => error.with_description(backtrace),
+        None => error,
+    }
+}
+
+pub(crate) fn unexpected_message(expected: &str, actual: MessageType) -> InvocationError {
+    InvocationError::new(
+        codes::PROTOCOL_VIOLATION,
+        format!("Unexpected message {:?}, expected {}", actual, expected),
+    )
+}
+
+pub(crate) fn mismatched_message(message_type: MessageType, message: &ProtocolMessage) -> InvocationError {
+    InvocationError::new(
+        codes::PROTOCOL_VIOLATION,
+        format!(
+            "Message header {:?} does not match the message body",
+            message_type
+        ),
+    )
+    .with_description(format!("{:?}", message))
+}
+
+pub(crate) fn malformed_entry(error: RawEntryCodecError) -> InvocationError {
+    InvocationError::new(codes::PROTOCOL_VIOLATION, "Cannot decode journal entry").with_description(error)
+}
+
+pub(crate) fn truncated_stream(received: usize, expected: u32) -> InvocationError {
+    InvocationError::new(
+        codes::PROTOCOL_VIOLATION,
+        format!(
+            "Stream closed before replay completed, received {} out of {} entries",
+            received, expected
+        ),
+    )
+}
+
+pub(crate) fn shutting_down() -> InvocationError {
+    InvocationError::new(
+        codes::INTERNAL,
+        "The endpoint shut down before the invocation could suspend",
+    )
+}
+
+pub(crate) fn unknown_entry(entry_index: u32) -> InvocationError {
+    InvocationError::new(
+        codes::PROTOCOL_VIOLATION,
+        format!("Received a message for the unknown entry index {}", entry_index),
+    )
+}
+
+pub(crate) fn journal_too_long(max_journal_length: u32) -> InvocationError {
+    InvocationError::internal(format!(
+        "The journal exceeds the maximum length of {} entries",
+        max_journal_length
+    ))
+}
+
+/// The entry replayed at the index is not the one the handler journals there, `expected` names the latter.
+pub(crate) fn journal_mismatch(entry_index: u32, expected: impl fmt::Display) -> InvocationError {
+    InvocationError::new(
+        codes::JOURNAL_MISMATCH,
+        format!(
+            "Replayed journal entry {} does not match the {} of the handler",
+            entry_index, expected
+        ),
+    )
+}
+
+/// Builds the `ErrorMessage` reported to the runtime for the given error.
+pub(crate) fn error_message(error: &InvocationError, related_entry_index: Option<u32>) -> ProtocolMessage {
+    ProtocolMessage::Error(service_protocol::ErrorMessage {
+        code: error.code().into(),
+        message: error.message().to_string(),
+        description: error.description().unwrap_or_default().to_string(),
+        related_entry_index,
+        related_entry_name: None,
+        related_entry_type: None,
+        next_retry_delay: None,
+    })
+}
@@ -1,7 +1,7 @@
-use crate::{connection::RestateStreamConsumer, store::LocalStateStore};
+use crate::{connection::RestateStreamConsumer, errors, store::LocalStateStore};
 use bytes::Bytes;
-use dashmap::DashMap;
 use restate_sdk_types::{
+    errors::{codes, InvocationError},
     journal::{Entry, InputEntry},
     service_protocol::StartMessage,
 };
@@ -24,17 +24,19 @@ pub(crate) struct Invocation {
     pub id: Bytes,
     pub debug_id: Option<String>,
     pub number_entries_to_replay: u32,
-    pub replay_entries: DashMap<u32, Entry>,
+    pub replay_entries: HashMap<u32, Entry>,
     pub invocation_value: Option<Bytes>,
     pub invocation_headers: Option<HashMap<String, String>>,
     pub local_state_store: Option<LocalStateStore>,
     pub user_key: Option<String>,
+    /// Attempts that failed since the last entry stored by the runtime
+    pub retry_count: u32,
 }
 
 pub(crate) struct InvocationBuilder {
     state: State,
     replay_index: u32,
-    replay_entries: DashMap<u32, Entry>,
+    replay_entries: HashMap<u32, Entry>,
     id: Option<Bytes>,
     debug_id: Option<String>,
     known_entries: u32,
@@ -42,6 +44,8 @@ pub(crate) struct InvocationBuilder {
     invocation_headers: Option<HashMap<String, String>>,
     local_state_store: Option<LocalStateStore>,
     user_key: Option<String>,
+    retry_count: u32,
+    max_journal_length: Option<u32>,
 }
 
 impl InvocationBuilder {
@@ -49,7 +53,7 @@ impl InvocationBuilder {
         Self {
             state: State::ExpectingStart,
             replay_index: 0,
-            replay_entries: DashMap::new(),
+            replay_entries: HashMap::new(),
             id: None,
             debug_id: None,
             known_entries: 0,
@@ -57,15 +61,25 @@ impl InvocationBuilder {
             invocation_headers: None,
             local_state_store: None,
             user_key: None,
+            retry_count: 0,
+            max_journal_length: None,
         }
     }
 
-    pub fn build(self) -> Invocation {
-        if !self.is_complete() {
-            // Error
-        }
-        Invocation {
-            id: self.id.unwrap(),
+    pub fn max_journal_length(mut self, max_journal_length: Option<u32>) -> Self {
+        self.max_journal_length = max_journal_length;
+        self
+    }
+
+    pub fn build(self) -> Result<Invocation, InvocationError> {
+        let (true, Some(id)) = (self.is_complete(), self.id) else {
+            return Err(errors::truncated_stream(
+                self.replay_entries.len(),
+                self.known_entries,
+            ));
+        };
+        Ok(Invocation {
+            id,
             debug_id: self.debug_id,
             number_entries_to_replay: self.known_entries,
             replay_entries: self.replay_entries,
@@ -73,28 +87,42 @@ impl InvocationBuilder {
             invocation_headers: self.invocation_headers,
             local_state_store: self.local_state_store,
             user_key: self.user_key,
-        }
+            retry_count: self.retry_count,
+        })
     }
 
-    fn handle_start_message(&mut self, message: StartMessage) {
+    fn handle_start_message(&mut self, message: StartMessage) -> Result<(), InvocationError> {
+        if message.known_entries == 0 {
+            // The input entry is always part of the replayed journal
+            return Err(InvocationError::new(
+                codes::PROTOCOL_VIOLATION,
+                "Start message must announce at least the input entry",
+            ));
+        }
+        if let Some(max_journal_length) = self.max_journal_length {
+            if message.known_entries > max_journal_length {
+                return Err(errors::journal_too_long(max_journal_length));
+            }
+        }
         self.known_entries = message.known_entries;
         self.id = Some(message.id);
         self.debug_id = Some(message.debug_id);
         self.user_key = Some(message.key);
-        self.local_state_store = Some(LocalStateStore::new(message.partial_state, message.state_map))
+        self.retry_count = message.retry_count_since_last_stored_entry;
+        self.local_state_store = Some(LocalStateStore::new(message.partial_state, message.state_map));
+        Ok(())
     }
 
     fn handle_input_message(&mut self, message: InputEntry) {
         self.invocation_value = Some(message.value);
     }
 
-    fn deserialize_entry(&mut self, message: ProtocolMessage) -> Option<Entry> {
-        if let ProtocolMessage::UnparsedEntry(raw_entry) = message {
-            let expected_entry = raw_entry.deserialize_entry_ref::<ProtobufRawEntryCodec>();
-            //TODO: Handle error
-            Some(expected_entry.unwrap())
-        } else {
-            None
+    fn deserialize_entry(&mut self, message: ProtocolMessage) -> Result<Entry, InvocationError> {
+        match message {
+            ProtocolMessage::UnparsedEntry(raw_entry) => raw_entry
+                .deserialize_entry_ref::<ProtobufRawEntryCodec>()
+                .map_err(errors::malformed_entry),
+            message => Err(errors::mismatched_message(MessageType::InputEntry, &message)),
         }
     }
 
@@ -109,44 +137,69 @@ impl InvocationBuilder {
 
     fn check_state(
         &self,
-        state: State,
-        expected: MessageType,
+        expected: &str,
         actual: MessageType,
         message: &ProtocolMessage,
-    ) {
+    ) -> Result<(), InvocationError> {
+        let is_valid = match self.state {
+            State::ExpectingStart => actual == MessageType::Start,
+            State::ExpectingInput => actual == MessageType::InputEntry,
+            State::ExpectingFurtherReplay => is_entry(actual),
+            State::Complete => false,
+        };
+        if !is_valid {
+            return Err(errors::unexpected_message(expected, actual));
+        }
+        let is_consistent = match message {
+            ProtocolMessage::Start(_) => actual == MessageType::Start,
+            ProtocolMessage::UnparsedEntry(_) => is_entry(actual),
+            _ => false,
+        };
+        if !is_consistent {
+            return Err(errors::mismatched_message(actual, message));
+        }
+        Ok(())
     }
 }
 
+fn is_entry(message_type: MessageType) -> bool {
+    !matches!(
+        message_type,
+        MessageType::Start
+            | MessageType::Completion
+            | MessageType::Suspension
+            | MessageType::Error
+            | MessageType::End
+            | MessageType::EntryAck
+    )
+}
+
 impl RestateStreamConsumer for InvocationBuilder {
-    fn handle_message(&mut self, message: (MessageType, ProtocolMessage)) -> bool {
+    fn handle_message(&mut self, message: (MessageType, ProtocolMessage)) -> Result<bool, InvocationError> {
         match self.state {
             State::ExpectingStart => {
-                self.check_state(self.state, MessageType::Start, message.0, &message.1);
-                match message.1 {
-                    ProtocolMessage::Start(start_message) => {
-                        self.handle_start_message(start_message);
-                    }
-                    _ => {
-                        // Invalid
-                    }
+                self.check_state("Start", message.0, &message.1)?;
+                if let ProtocolMessage::Start(start_message) = message.1 {
+                    self.handle_start_message(start_message)?;
                 }
                 self.state = State::ExpectingInput;
-                return false;
+                return Ok(false);
             }
             State::ExpectingInput => {
-                self.check_state(self.state, MessageType::InputEntry, message.0, &message.1);
-                let entry = self.deserialize_entry(message.1).unwrap();
+                self.check_state("InputEntry", message.0, &message.1)?;
+                let entry = self.deserialize_entry(message.1)?;
                 if let Entry::Input(input) = &entry {
                     self.handle_input_message(input.clone());
                 }
                 self.append_replay_entry(entry);
             }
             State::ExpectingFurtherReplay => {
-                let entry = self.deserialize_entry(message.1).unwrap();
+                self.check_state("a replayed entry", message.0, &message.1)?;
+                let entry = self.deserialize_entry(message.1)?;
                 self.append_replay_entry(entry);
             }
             State::Complete => {
-                // Error
+                self.check_state("no further replay entries", message.0, &message.1)?;
             }
         }
         if self.replay_entries.len() == self.known_entries as usize {
@@ -157,12 +210,124 @@ impl RestateStreamConsumer for InvocationBuilder {
         if self.state.eq(&State::Complete) {
             debug!("Invocation builder state: {:?}", self.state);
         }
-        self.is_complete()
+        Ok(self.is_complete())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use prost::Message;
+    use restate_sdk_types::{
+        journal::raw::{PlainEntryHeader, PlainRawEntry},
+        service_protocol,
+    };
+
+    fn start_message(known_entries: u32) -> (MessageType, ProtocolMessage) {
+        (
+            MessageType::Start,
+            ProtocolMessage::Start(StartMessage {
+                id: Bytes::from_static(b"id"),
+                debug_id: "inv_1".to_string(),
+                known_entries,
+                state_map: vec![],
+                partial_state: false,
+                key: "".to_string(),
+                retry_count_since_last_stored_entry: 0,
+                duration_since_last_stored_entry: 0,
+            }),
+        )
+    }
+
+    fn input_message() -> (MessageType, ProtocolMessage) {
+        (
+            MessageType::InputEntry,
+            PlainRawEntry::new(
+                PlainEntryHeader::Input,
+                service_protocol::InputEntryMessage {
+                    headers: vec![],
+                    value: "{}".into(),
+                    name: "".to_string(),
+                }
+                .encode_to_vec()
+                .into(),
+            )
+            .into(),
+        )
+    }
+
     #[test]
     fn test_invocation() {}
+
+    #[test]
+    fn test_build_invocation() {
+        let mut builder = InvocationBuilder::new();
+        assert!(!builder.handle_message(start_message(1)).unwrap());
+        assert!(builder.handle_message(input_message()).unwrap());
+        let invocation = builder.build().unwrap();
+        assert_eq!(invocation.number_entries_to_replay, 1);
+        assert_eq!(invocation.invocation_value, Some(Bytes::from_static(b"{}")));
+    }
+
+    #[test]
+    fn test_reject_missing_start() {
+        let mut builder = InvocationBuilder::new();
+        let error = builder.handle_message(input_message()).unwrap_err();
+        assert_eq!(error.code(), codes::PROTOCOL_VIOLATION);
+    }
+
+    #[test]
+    fn test_reject_missing_input() {
+        let mut builder = InvocationBuilder::new();
+        builder.handle_message(start_message(2)).unwrap();
+        let error = builder
+            .handle_message((
+                MessageType::Completion,
+                ProtocolMessage::Completion(service_protocol::CompletionMessage {
+                    entry_index: 1,
+                    result: None,
+                }),
+            ))
+            .unwrap_err();
+        assert_eq!(error.code(), codes::PROTOCOL_VIOLATION);
+    }
+
+    #[test]
+    fn test_reject_mismatched_header() {
+        let mut builder = InvocationBuilder::new();
+        let (_, start) = start_message(1);
+        let error = builder
+            .handle_message((MessageType::InputEntry, start))
+            .unwrap_err();
+        assert_eq!(error.code(), codes::PROTOCOL_VIOLATION);
+    }
+
+    #[test]
+    fn test_reject_malformed_entry() {
+        let mut builder = InvocationBuilder::new();
+        builder.handle_message(start_message(1)).unwrap();
+        let error = builder
+            .handle_message((
+                MessageType::InputEntry,
+                PlainRawEntry::new(PlainEntryHeader::Input, Bytes::from_static(&[0xff, 0xff])).into(),
+            ))
+            .unwrap_err();
+        assert_eq!(error.code(), codes::PROTOCOL_VIOLATION);
+    }
+
+    #[test]
+    fn test_reject_journal_too_long() {
+        let mut builder = InvocationBuilder::new().max_journal_length(Some(2));
+        let error = builder.handle_message(start_message(3)).unwrap_err();
+        assert_eq!(error.code(), codes::INTERNAL);
+    }
+
+    #[test]
+    fn test_reject_truncated_stream() {
+        let mut builder = InvocationBuilder::new();
+        builder.handle_message(start_message(3)).unwrap();
+        builder.handle_message(input_message()).unwrap();
+        let error = builder.build().err().unwrap();
+        assert_eq!(error.code(), codes::PROTOCOL_VIOLATION);
+    }
 }
@@ -1,11 +1,10 @@
 use crate::invocation::Invocation;
 use bytes::Bytes;
-use dashmap::DashMap;
 use futures_util::task::waker;
 use prost::Message;
 use restate_sdk_types::{
     journal::{
-        CancelInvocationEntry, CompleteResult, CompletionResult, Entry, EntryResult,
+        CancelInvocationEntry, CompletableEntry, CompleteResult, CompletionResult, Entry, EntryResult,
         GetCallInvocationIdEntry, GetStateKeysResult, InputEntry, RunEntry, SleepResult,
     },
     service_protocol::{
@@ -13,7 +12,7 @@ use restate_sdk_types::{
         get_state_keys_entry_message, CompletionMessage, EntryAckMessage, InputEntryMessage,
     },
 };
-use std::{cmp::PartialEq, task::Waker};
+use std::{cmp::PartialEq, collections::HashMap, task::Waker};
 use tracing::debug;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -32,7 +31,7 @@ pub struct JournalEntry {
 pub struct Journal {
     state: NewExecutionState,
     user_code_journal_index: u32,
-    pending_entries: DashMap<u32, JournalEntry>,
+    pending_entries: HashMap<u32, JournalEntry>,
     invocation: Invocation,
 }
 
@@ -113,11 +112,7 @@ impl Journal {
         );
         match self.state {
             NewExecutionState::REPLAYING => {
-                let replay_entry = self
-                    .invocation
-                    .replay_entries
-                    .get(&entry_index)
-                    .map(|entry| entry.clone());
+                let replay_entry = self.invocation.replay_entries.get(&entry_index).cloned();
                 if let Some(replay_entry) = replay_entry {
                     let journal_entry = JournalEntry { entry, waker };
                     return (
@@ -252,10 +247,11 @@ impl Journal {
             },
             Entry::CancelInvocation(_) => {}
             Entry::GetCallInvocationId(_) => {}
-            Entry::Custom(_) => {
-                let JournalEntry { entry, waker } = entry;
-                self.append_entry(entry, waker.unwrap());
-                return Some(Bytes::new());
+            Entry::Custom(replayed) => {
+                // The replayed entry must have been recorded by the same kind of entry
+                if matches!(&entry.entry, Entry::Custom(custom) if custom.code == replayed.code) {
+                    return Some(replayed.value);
+                }
             }
         }
         None
@@ -305,14 +301,12 @@ impl Journal {
             Entry::Run(_) => {}
             Entry::CancelInvocation(_) => {}
             Entry::GetCallInvocationId(_) => {}
-            Entry::Custom(_) => {
-                self.append_entry(entry, waker.unwrap());
-            }
+            Entry::Custom(_) => {}
         }
     }
 
     #[tracing::instrument(skip(self))]
-    pub fn resolve_result(&self, entry_index: u32) -> Option<Bytes> {
+    pub fn resolve_result(&mut self, entry_index: u32) -> Option<Bytes> {
         let mut resolved = true;
         if let Some(pending) = self.pending_entries.get(&entry_index) {
             match &pending.entry {
@@ -444,10 +438,10 @@ impl Journal {
     }
 
     #[tracing::instrument(parent = None, skip(self, message))]
-    pub fn handle_runtime_completion_message(&self, message: CompletionMessage) {
+    pub fn handle_runtime_completion_message(&mut self, message: CompletionMessage) {
         debug!("Handling runtime message entry: {:?}", message.entry_index);
         let journal_entry = self.pending_entries.get_mut(&message.entry_index);
-        if let Some(mut journal_entry) = journal_entry {
+        if let Some(journal_entry) = journal_entry {
             debug!("Journal runtime message entry: {:?}", journal_entry);
             match &mut journal_entry.entry {
                 Entry::GetState(get_state) => match message.result {
@@ -595,8 +589,8 @@ impl Journal {
     }
 
     #[tracing::instrument(parent = None, skip(self, message))]
-    pub fn handle_runtime_entry_ack_message(&self, message: EntryAckMessage) {
-        if let Some((_, entry)) = self.pending_entries.remove(&message.entry_index) {
+    pub fn handle_runtime_entry_ack_message(&mut self, message: EntryAckMessage) {
+        if let Some(entry) = self.pending_entries.remove(&message.entry_index) {
             if let Some(waker) = entry.waker {
                 waker.wake();
             }
@@ -613,7 +607,7 @@ impl Journal {
         //self.pending_entries.remove(&entry_index);
     }
 
-    pub fn append_entry(&self, entry: Entry, waker: Waker) {
+    pub fn append_entry(&mut self, entry: Entry, waker: Waker) {
         self.pending_entries
             .insert(self.user_code_journal_index, JournalEntry {
                 entry,
@@ -621,8 +615,20 @@ impl Journal {
             });
     }
 
+    /// Indexes of the entries awaiting a completion from the runtime.
+    pub fn uncompleted_entries(&self) -> Vec<u32> {
+        let mut entry_indexes = self
+            .pending_entries
+            .iter()
+            .filter(|(_, pending)| !is_completed(&pending.entry))
+            .map(|(entry_index, _)| *entry_index)
+            .collect::<Vec<_>>();
+        entry_indexes.sort();
+        entry_indexes
+    }
+
     pub fn is_unresolved(&self, index: u32) -> bool {
-        self.pending_entries.get(&index).is_some()
+        self.pending_entries.contains_key(&index)
     }
 
     pub fn is_closed(&self) -> bool {
@@ -655,14 +661,15 @@ impl Journal {
 
     pub fn is_output_replayed(&self) -> bool {
         let last_replay_entry_index = self.invocation.number_entries_to_replay - 1;
-        if let Some(last_entry) = self.invocation.replay_entries.get(&last_replay_entry_index) {
-            match last_entry.value() {
-                Entry::Output(_) => true,
-                _ => false,
-            }
-        } else {
-            false
-        }
+        matches!(
+            self.invocation.replay_entries.get(&last_replay_entry_index),
+            Some(Entry::Output(_))
+        )
+    }
+
+    /// Whether the entry was either replayed by the runtime or already written by the user code.
+    pub fn is_known_entry(&self, index: u32) -> bool {
+        index < self.invocation.number_entries_to_replay || index <= self.user_code_journal_index
     }
 
     pub fn is_next_entry_replaying(&self) -> bool {
@@ -670,6 +677,22 @@ impl Journal {
     }
 }
 
+fn is_completed(entry: &Entry) -> bool {
+    match entry {
+        Entry::GetState(entry) => entry.is_completed(),
+        Entry::GetStateKeys(entry) => entry.is_completed(),
+        Entry::GetPromise(entry) => entry.is_completed(),
+        Entry::PeekPromise(entry) => entry.is_completed(),
+        Entry::CompletePromise(entry) => entry.is_completed(),
+        Entry::Sleep(entry) => entry.is_completed(),
+        Entry::Call(entry) => entry.is_completed(),
+        Entry::Awakeable(entry) => entry.is_completed(),
+        Entry::GetCallInvocationId(entry) => entry.is_completed(),
+        // Other entries are not completed by the runtime
+        _ => true,
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -14,3 +14,5 @@ pub mod connection;
 pub mod context;
 pub mod endpoint;
 #[cfg(feature = "logger")] pub mod logger;
+pub mod metrics;
+pub mod payload;
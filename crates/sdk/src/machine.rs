@@ -1,18 +1,21 @@
 use crate::{
-    connection::{MessageSender, RestateStreamConsumer},
+    connection::{MessageReceiver, MessageSender, RestateStreamConsumer},
     context::{ContextData, ContextInstance, Request},
+    errors,
     invocation::Invocation,
     journal::Journal,
     logger::ReplayFilter,
+    metrics::{Counter, InvocationTarget, Metrics, METRICS},
+    payload::Payload,
     store::LocalStateStore,
 };
 use bytes::Bytes;
-use futures::channel::oneshot;
-use parking_lot::{Mutex, MutexGuard};
+use futures::{channel::oneshot, pin_mut, FutureExt};
 use prost::Message;
 use restate_sdk_core::ServiceHandler;
 use restate_sdk_types::{
     endpoint_manifest::ProtocolMode,
+    errors::{codes, InvocationError},
     journal::{
         raw::{PlainEntryHeader, PlainRawEntry},
         CancelInvocationTarget, Entry, EntryResult, GetCallInvocationIdResult, GetStateKeysResult,
@@ -25,14 +28,25 @@ use restate_sdk_types::{
     },
 };
 use restate_service_protocol::message::{MessageType, ProtocolMessage};
-use serde::{Deserialize, Serialize};
-use std::{future::Future, sync::Arc, task::Waker};
+use std::{
+    any::Any,
+    cell::RefCell,
+    future::{self, Future},
+    panic::AssertUnwindSafe,
+    task::{self, ready, Poll, Waker},
+    time::{Duration, Instant},
+};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, field, info_span, Instrument};
 
 const SUSPENSION_MILLIS: u32 = 30000;
 
+tokio::task_local! {
+    /// State machine of the invocation running on the current task.
+    static STATE_MACHINE: RefCell<StateMachine>;
+}
+
 pub(crate) struct StateMachine {
     journal: Journal,
     machine_closed: bool,
@@ -46,6 +60,15 @@ pub(crate) struct StateMachine {
     protocol_mode: ProtocolMode,
     input: Option<Bytes>,
     span_replay_flag: bool,
+    max_journal_length: Option<u32>,
+    /// Number of side effects currently running on the invocation task
+    running_actions: u32,
+    /// Set once the endpoint shuts down, the invocation suspends as in request-response mode
+    draining: bool,
+    target: InvocationTarget,
+    metrics: &'static Metrics,
+    /// Start of the attempt while the journal replays
+    replay_started: Option<Instant>,
 }
 
 impl StateMachine {
@@ -53,13 +76,16 @@ impl StateMachine {
         abort_on_replay: bool,
         connection: Option<Box<dyn MessageSender>>,
         mut invocation: Invocation,
+        max_journal_length: Option<u32>,
     ) -> (Self, UnboundedReceiver<String>) {
         let input = invocation.invocation_value.clone();
         let store = invocation.local_state_store.take();
         let (suspension_tx, suspension_rx) = unbounded_channel();
+        let journal = Journal::new(invocation);
+        let replay_started = journal.is_replaying().then(Instant::now);
         (
             Self {
-                journal: Journal::new(invocation),
+                journal,
                 machine_closed: false,
                 input_channel_closed: false,
                 local_state_store: store.unwrap(),
@@ -71,6 +97,12 @@ impl StateMachine {
                 protocol_mode: ProtocolMode::BidiStream,
                 input,
                 span_replay_flag: true,
+                max_journal_length,
+                running_actions: 0,
+                draining: false,
+                target: InvocationTarget::default(),
+                metrics: &METRICS,
+                replay_started,
             },
             suspension_rx,
         )
@@ -89,77 +121,269 @@ impl StateMachine {
         }
     }
 
+    /// Runs the state machine of the current invocation.
+    ///
+    /// Panics when called outside of the task running the invocation, the futures of the context go
+    /// through [`StateMachine::poll_with`] instead.
+    pub fn with<R>(f: impl FnOnce(&mut StateMachine) -> R) -> R {
+        STATE_MACHINE.with(|state_machine| f(&mut state_machine.borrow_mut()))
+    }
+
+    /// Polls the state machine of the current invocation, stays pending outside of the task running
+    /// it: the context used from another task failed the invocation, see [`Request::enter`].
+    pub fn poll_with<R>(f: impl FnOnce(&mut StateMachine) -> Poll<R>) -> Poll<R> {
+        STATE_MACHINE
+            .try_with(|state_machine| f(&mut state_machine.borrow_mut()))
+            .unwrap_or(Poll::Pending)
+    }
+
+    /// Whether the current task runs an invocation.
+    pub fn is_current() -> bool {
+        STATE_MACHINE.try_with(|_| ()).is_ok()
+    }
+
+    /// Like [`StateMachine::with`], returns `None` outside of the task running the invocation.
+    pub fn try_with<R>(f: impl FnOnce(&mut StateMachine) -> R) -> Option<R> {
+        STATE_MACHINE
+            .try_with(|state_machine| {
+                state_machine
+                    .try_borrow_mut()
+                    .ok()
+                    .map(|mut state_machine| f(&mut state_machine))
+            })
+            .ok()
+            .flatten()
+    }
+
+    pub fn start_action(&mut self) {
+        self.running_actions += 1;
+        // The previous attempt failed after the last stored entry, while running this side effect
+        let invocation = self.journal.invocation();
+        if invocation.retry_count > 0
+            && self.journal.get_user_code_journal_index() + 1 == invocation.number_entries_to_replay
+        {
+            self.metrics.run_retries.inc(&self.target);
+        }
+    }
+
+    pub fn finish_action(&mut self, duration: Duration) {
+        self.running_actions -= 1;
+        self.metrics.run_duration.observe_duration(&self.target, duration);
+    }
+
+    /// Runs the invocation to completion on the current task, polling the handler and consuming the
+    /// inbound messages of the connection.
     pub async fn invoke<Context, Func, Input, Output>(
+        mut self,
         token: CancellationToken,
         handler: Func,
-        state_machine: Arc<Mutex<StateMachine>>,
+        mut receiver: impl MessageReceiver,
+        mut suspension_rx: UnboundedReceiver<String>,
     ) where
-        for<'a> Input: Serialize + Deserialize<'a>,
-        for<'a> Output: Serialize + Deserialize<'a>,
+        Input: Payload,
+        Output: Payload,
         Func: ServiceHandler<Context, Input, Output = Result<Output, anyhow::Error>> + Send + Sync + 'static,
         Context: ContextInstance,
     {
-        let input = state_machine.lock().input.clone().unwrap();
-        let input = serde_json::from_slice(&input.to_vec()).unwrap();
-        let id = state_machine.lock().journal.invocation().id.clone();
-        let debug_id = state_machine
-            .lock()
-            .journal
-            .invocation()
-            .debug_id
-            .clone()
-            .unwrap();
-        let span = info_span!(
-            "invoke",
-            "otel.name" = debug_id,
-            "otel.kind" = "server",
-            "replay" = field::Empty,
-        );
-        let request = Request { id };
-        let (abort_tx, abort_rx) = oneshot::channel::<bool>();
-        state_machine.lock().abort_tx = Some(abort_tx);
-        let ctx = Context::new(request, state_machine.clone());
-        let handle = handler(ctx, input).instrument(span);
-        tokio::select! {
-            _ = token.cancelled() => {
-               debug!("State machine cancelled");
-            }
-            _ = abort_rx => {
-                debug!("Invocation aborted");
-            }
-            result = handle => {
-                match result {
-                    Ok(result) => {
-                        let result = serde_json::to_string(&result).unwrap();
-                        let mut state_machine = state_machine.lock();
-                         state_machine.handle_user_code_message(
-                            None,
-                            None,
-                            Entry::Output(OutputEntry {
-                                result: EntryResult::Success(result.clone().into()),
-                            }),
-                            None,
-                        );
-                        debug!("Invocation end");
-                        state_machine.send(ProtocolMessage::End(service_protocol::EndMessage {}));
-                    }
+        let input = self.input.clone().unwrap_or_default();
+        let id = self.journal.invocation().id.clone();
+        let debug_id = self.journal.invocation().debug_id.clone().unwrap();
+        let (abort_tx, mut abort_rx) = oneshot::channel::<bool>();
+        self.abort_tx = Some(abort_tx);
+        self.protocol_mode = receiver.protocol_mode();
+        self.target = receiver.target();
+        self.metrics = receiver.metrics();
+        self.metrics.invocations_started.inc(&self.target);
+        let draining = receiver.draining();
+        let misused = CancellationToken::new();
+
+        STATE_MACHINE
+            .scope(RefCell::new(self), async move {
+                let input = match Input::decode(input) {
+                    Ok(input) => input,
                     Err(err) => {
-                        let error: ProtocolMessage = ProtocolMessage::Error(service_protocol::ErrorMessage {
-                            code: 0,
-                            message: "".to_string(),
-                            description: err.to_string(),
-                            related_entry_index: None,
-                            related_entry_name: None,
-                            related_entry_type: None,
-                            next_retry_delay: None,
+                        StateMachine::with(|state_machine| {
+                            state_machine.fail(
+                                InvocationError::new(
+                                    codes::BAD_REQUEST,
+                                    "Cannot deserialize the handler input",
+                                )
+                                .with_description(err),
+                            )
                         });
-                        let mut state_machine = state_machine.lock();
-                        state_machine.send(error);
-                        state_machine.send(ProtocolMessage::End(service_protocol::EndMessage {}));
+                        return;
                     }
                 };
-                debug!("Invocation done");
+                let span = info_span!(
+                    "invoke",
+                    "otel.name" = debug_id,
+                    "otel.kind" = "server",
+                    "replay" = field::Empty,
+                );
+                let request = Request::new(id, misused.clone());
+                let ctx = Context::new(request);
+                errors::capture_panic_backtraces();
+                // Panics in the user code must not take down the invocation task, they are reported as errors
+                let handle = AssertUnwindSafe(handler(ctx, input).instrument(span)).catch_unwind();
+                pin_mut!(handle);
+                let mut inbound_closed = false;
+                let mut draining_started = false;
+                loop {
+                    // The handler is polled before consuming the next message, so that the entries it
+                    // writes are known when their completions arrive
+                    tokio::select! {
+                        biased;
+                        _ = token.cancelled() => {
+                            debug!("State machine cancelled");
+                            return;
+                        }
+                        _ = &mut abort_rx => {
+                            debug!("Invocation aborted");
+                            return;
+                        }
+                        _ = misused.cancelled() => {
+                            StateMachine::with(|state_machine| {
+                                state_machine.fail(InvocationError::new(
+                                    codes::INTERNAL,
+                                    "The context is used outside of the task running the invocation, \
+                                     the futures of the context cannot be spawned",
+                                ))
+                            });
+                            return;
+                        }
+                        // The handler pauses while the response is not read fast enough
+                        result = future::poll_fn(|cx| {
+                            ready!(StateMachine::with(|state_machine| state_machine.poll_outbound(cx)));
+                            handle.as_mut().poll(cx)
+                        }) => {
+                            StateMachine::with(|state_machine| state_machine.complete(result));
+                            debug!("Invocation done");
+                            return;
+                        }
+                        message = receiver.recv(), if !inbound_closed => {
+                            let Some(message) = message else {
+                                debug!("Stream consumption completed");
+                                inbound_closed = true;
+                                continue;
+                            };
+                            let result = StateMachine::with(|state_machine| {
+                                message.and_then(|message| state_machine.handle_message(message))
+                            });
+                            match result {
+                                Ok(true) => inbound_closed = true,
+                                Ok(false) => {}
+                                Err(error) => {
+                                    // Stop the user code, the invocation will be retried by the runtime
+                                    StateMachine::with(|state_machine| state_machine.fail(error));
+                                    return;
+                                }
+                            }
+                        }
+                        // Polled after the handler, once the handler can only progress with completions
+                        // the runtime will not send anymore, or the endpoint drains
+                        _ = future::poll_fn(|_| {
+                            if StateMachine::with(|state_machine| state_machine.should_suspend()) {
+                                Poll::Ready(())
+                            } else {
+                                Poll::Pending
+                            }
+                        }), if inbound_closed || draining_started => {
+                            StateMachine::with(|state_machine| state_machine.suspend());
+                            return;
+                        }
+                        _ = draining.started.cancelled(), if !draining_started => {
+                            debug!("Endpoint draining, suspending at the next completion awaited");
+                            draining_started = true;
+                            StateMachine::with(|state_machine| state_machine.draining = true);
+                        }
+                        _ = draining.expired.cancelled() => {
+                            StateMachine::with(|state_machine| state_machine.interrupt());
+                            return;
+                        }
+                        Some(message) = suspension_rx.recv() => {
+                            debug!("scheduling suspension: {:?}", message);
+                            StateMachine::with(|state_machine| state_machine.suspend());
+                            return;
+                        }
+                    }
+                }
+            })
+            .await
+    }
+
+    fn complete<Output: Payload>(
+        &mut self,
+        result: Result<Result<Output, anyhow::Error>, Box<dyn Any + Send>>,
+    ) {
+        if self.machine_closed {
+            // A syscall already ended the attempt, the handler only returned its error
+            return;
+        }
+        let result = match result {
+            Ok(result) => result,
+            Err(payload) => {
+                let error = errors::panic_error(payload);
+                debug!("Invocation panicked: {}", error);
+                self.fail(error);
+                return;
             }
+        };
+        match result {
+            Ok(result) => {
+                let result = match result.encode() {
+                    Ok(result) => result,
+                    Err(err) => {
+                        self.fail(
+                            InvocationError::internal("Cannot serialize the handler output")
+                                .with_description(err),
+                        );
+                        return;
+                    }
+                };
+                self.handle_user_code_message(
+                    None,
+                    None,
+                    Entry::Output(OutputEntry {
+                        result: EntryResult::Success(result),
+                    }),
+                    None,
+                );
+                debug!("Invocation end");
+                self.send(ProtocolMessage::End(service_protocol::EndMessage {}));
+                self.record_end(|metrics| &metrics.invocations_completed);
+            }
+            Err(err) => {
+                let error: ProtocolMessage = ProtocolMessage::Error(service_protocol::ErrorMessage {
+                    code: 0,
+                    message: "".to_string(),
+                    description: err.to_string(),
+                    related_entry_index: None,
+                    related_entry_name: None,
+                    related_entry_type: None,
+                    next_retry_delay: None,
+                });
+                self.send(error);
+                self.send(ProtocolMessage::End(service_protocol::EndMessage {}));
+                self.record_end(|metrics| &metrics.invocations_failed);
+            }
+        };
+    }
+
+    fn record_end(&mut self, counter: fn(&Metrics) -> &Counter) {
+        // The attempt may end before the replay does, when it suspends or fails
+        self.record_replay_end();
+        counter(self.metrics).inc(&self.target);
+        self.metrics.journal_length.observe(
+            &self.target,
+            (self.journal.get_user_code_journal_index() + 1) as f64,
+        );
+    }
+
+    fn record_replay_end(&mut self) {
+        if let Some(replay_started) = self.replay_started.take() {
+            self.metrics
+                .replay_duration
+                .observe_duration(&self.target, replay_started.elapsed());
         }
     }
 
@@ -176,8 +400,14 @@ impl StateMachine {
         }
         if let Some(entry_index) = entry_index {
             (entry_index, self.journal.resolve_result(entry_index))
+        } else if let Err(error) = self.check_journal_length() {
+            self.fail(error);
+            (self.journal.get_user_code_journal_index(), None)
         } else {
             let (entry_index, result) = self.journal.handle_user_code_message(message.clone(), waker);
+            if !self.journal.is_replaying() {
+                self.record_replay_end();
+            }
             if result.is_none() {
                 match &message {
                     Entry::Input(_) => {}
@@ -586,10 +816,13 @@ impl StateMachine {
                             .into(),
                         );
                     }
-                    Entry::Custom(bytes) => {
+                    Entry::Custom(custom) => {
                         self.send(
-                            PlainRawEntry::new(PlainEntryHeader::Custom { code: 0xfc00 }, bytes.clone())
-                                .into(),
+                            PlainRawEntry::new(
+                                PlainEntryHeader::Custom { code: custom.code },
+                                custom.value.clone(),
+                            )
+                            .into(),
                         );
                     }
                 }
@@ -613,15 +846,22 @@ impl StateMachine {
     ) -> (u32, Option<Bytes>) {
         if let Some(entry_index) = entry_index {
             (entry_index, self.journal.resolve_result(entry_index))
+        } else if let Err(error) = self.check_journal_length() {
+            self.fail(error);
+            (self.journal.get_user_code_journal_index(), None)
         } else {
             self.journal.increment_user_code_index();
             let entry_index = self.journal.get_user_code_journal_index();
             self.journal.append_entry(message.clone(), waker);
             match &message {
-                Entry::Custom(bytes) => {
+                Entry::Custom(custom) => {
                     // TODO: Acknowledge flag
                     self.send(
-                        PlainRawEntry::new(PlainEntryHeader::Custom { code: 0xfc00 }, bytes.clone()).into(),
+                        PlainRawEntry::new(
+                            PlainEntryHeader::Custom { code: custom.code },
+                            custom.value.clone(),
+                        )
+                        .into(),
                     );
                 }
                 _ => {}
@@ -630,6 +870,17 @@ impl StateMachine {
         }
     }
 
+    fn check_journal_length(&self) -> Result<(), InvocationError> {
+        match self.max_journal_length {
+            Some(max_journal_length)
+                if self.journal.get_next_user_code_journal_index() >= max_journal_length =>
+            {
+                Err(errors::journal_too_long(max_journal_length))
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn get_user_code_journal_index(&self) -> u32 {
         return self.journal.get_user_code_journal_index();
     }
@@ -642,6 +893,18 @@ impl StateMachine {
         return self.journal.is_next_entry_replaying();
     }
 
+    /// Entry replayed from the journal at the given index.
+    pub fn replayed_entry(&self, entry_index: u32) -> Option<&Entry> {
+        self.journal.invocation().replay_entries.get(&entry_index)
+    }
+
+    fn poll_outbound(&self, cx: &mut task::Context<'_>) -> Poll<()> {
+        match &self.connection {
+            Some(connection) => connection.poll_ready(cx),
+            None => Poll::Ready(()),
+        }
+    }
+
     fn send(&mut self, message: ProtocolMessage) {
         // If in processing or no use calls are performed at all
         if !self.journal.is_replaying() || self.journal.get_user_code_journal_index() == 0 {
@@ -660,7 +923,63 @@ impl StateMachine {
         self.suspension_tx.send("suspend".to_string()).unwrap()
     }
 
-    pub fn suspend(&self) {}
+    /// In request-response mode the invocation suspends when it awaits a completion and no side effect
+    /// is running, as the runtime sends completions with the next attempt only. The same applies once
+    /// the endpoint drains.
+    fn should_suspend(&self) -> bool {
+        (self.protocol_mode == ProtocolMode::RequestResponse || self.draining)
+            && self.running_actions == 0
+            && !self.journal.uncompleted_entries().is_empty()
+    }
+
+    /// Reports the entries the invocation awaits to the runtime and closes the state machine.
+    pub fn suspend(&mut self) {
+        if self.machine_closed {
+            return;
+        }
+        let entry_indexes = self.journal.uncompleted_entries();
+        debug!("Invocation suspended, awaiting entries {:?}", entry_indexes);
+        // Dropping the connection closes the response stream
+        if let Some(connection) = self.connection.take() {
+            connection.send(ProtocolMessage::Suspension(service_protocol::SuspensionMessage {
+                entry_indexes,
+            }));
+        }
+        self.machine_closed = true;
+        self.record_end(|metrics| &metrics.invocations_suspended);
+        self.journal.close();
+    }
+
+    /// Stops the invocation of an endpoint shutting down, suspending it when it awaits completions,
+    /// else failing it so that the runtime retries it.
+    pub fn interrupt(&mut self) {
+        if self.journal.uncompleted_entries().is_empty() {
+            self.fail(errors::shutting_down());
+        } else {
+            self.suspend();
+        }
+    }
+
+    /// Reports the error to the runtime and closes the state machine.
+    pub fn fail(&mut self, error: InvocationError) {
+        if self.machine_closed {
+            return;
+        }
+        debug!("Invocation failed: {}", error);
+        let related_entry_index = Some(self.journal.get_user_code_journal_index());
+        // Dropping the connection closes the response stream
+        if let Some(connection) = self.connection.take() {
+            connection.send(errors::error_message(&error, related_entry_index));
+            connection.send(ProtocolMessage::End(service_protocol::EndMessage {}));
+        }
+        self.machine_closed = true;
+        self.record_end(|metrics| &metrics.invocations_failed);
+        self.journal.close();
+        // Stop the user code, it cannot make progress anymore
+        if let Some(abort_tx) = self.abort_tx.take() {
+            let _ = abort_tx.send(true);
+        }
+    }
 
     pub fn set_span(&mut self) {
         /*
@@ -687,27 +1006,36 @@ impl StateMachine {
     }
 }
 
-impl RestateStreamConsumer for MutexGuard<'_, StateMachine> {
-    fn handle_message(&mut self, message: (MessageType, ProtocolMessage)) -> bool {
+impl RestateStreamConsumer for StateMachine {
+    fn handle_message(&mut self, message: (MessageType, ProtocolMessage)) -> Result<bool, InvocationError> {
         debug!("Machine runtime message handler: {:?}", message);
-        if self.machine_closed {
-            return false;
+        if self.machine_closed || self.journal.is_closed() {
+            // Completions racing with the end of the invocation are dropped
+            debug!("State machine closed, ignoring message: {:?}", message.0);
+            return Ok(true);
         }
-        if message.0 == MessageType::Completion {
-            if let ProtocolMessage::Completion(message) = message.1 {
-                self.journal.handle_runtime_completion_message(message);
-            } else {
-                // Wrong message type
+        match message {
+            (MessageType::Completion, ProtocolMessage::Completion(completion)) => {
+                if !self.journal.is_known_entry(completion.entry_index) {
+                    return Err(errors::unknown_entry(completion.entry_index));
+                }
+                self.journal.handle_runtime_completion_message(completion);
             }
-        } else if message.0 == MessageType::EntryAck {
-            if let ProtocolMessage::EntryAck(message) = message.1 {
-                self.journal.handle_runtime_entry_ack_message(message);
-            } else {
-                // Wrong message type
+            (MessageType::EntryAck, ProtocolMessage::EntryAck(entry_ack)) => {
+                if !self.journal.is_known_entry(entry_ack.entry_index) {
+                    return Err(errors::unknown_entry(entry_ack.entry_index));
+                }
+                self.journal.handle_runtime_entry_ack_message(entry_ack);
+            }
+            (message_type @ (MessageType::Completion | MessageType::EntryAck), message) => {
+                return Err(errors::mismatched_message(message_type, &message));
+            }
+            (message_type, _) => {
+                return Err(errors::unexpected_message("Completion or EntryAck", message_type));
             }
         }
         // Clear suspension tasks
-        false
+        Ok(false)
     }
 }
 
@@ -0,0 +1,278 @@
+//! Prometheus metrics of the invocations served by the process, rendered in the text exposition format.
+
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex, time::Duration};
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+const SECONDS_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+const ENTRIES_BUCKETS: &[f64] = &[1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0];
+
+/// Service and handler targeted by an invocation, labelling its metrics.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InvocationTarget {
+    pub service: String,
+    pub handler: String,
+}
+
+impl InvocationTarget {
+    /// Parses the `/invoke/{service}/{handler}` path of an invocation request.
+    pub fn from_path(path: &str) -> Self {
+        let mut segments = path.trim_start_matches('/').splitn(3, '/');
+        match (segments.next(), segments.next(), segments.next()) {
+            (Some("invoke"), Some(service), Some(handler)) => Self {
+                service: service.to_string(),
+                handler: handler.to_string(),
+            },
+            _ => Self::default(),
+        }
+    }
+}
+
+pub(crate) struct Counter {
+    name: &'static str,
+    help: &'static str,
+    values: Mutex<BTreeMap<InvocationTarget, u64>>,
+}
+
+impl Counter {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn inc(&self, target: &InvocationTarget) {
+        let mut values = self.values.lock().unwrap();
+        match values.get_mut(target) {
+            Some(value) => *value += 1,
+            None => {
+                values.insert(target.clone(), 1);
+            }
+        }
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} counter", self.name);
+        for (target, value) in self.values.lock().unwrap().iter() {
+            let _ = writeln!(out, "{}{{{}}} {}", self.name, labels(target), value);
+        }
+    }
+}
+
+#[derive(Default)]
+struct HistogramValue {
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+pub(crate) struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    buckets: &'static [f64],
+    values: Mutex<BTreeMap<InvocationTarget, HistogramValue>>,
+}
+
+impl Histogram {
+    const fn new(name: &'static str, help: &'static str, buckets: &'static [f64]) -> Self {
+        Self {
+            name,
+            help,
+            buckets,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn observe(&self, target: &InvocationTarget, value: f64) {
+        let mut values = self.values.lock().unwrap();
+        if !values.contains_key(target) {
+            values.insert(target.clone(), HistogramValue {
+                buckets: vec![0; self.buckets.len()],
+                ..Default::default()
+            });
+        }
+        let histogram = values.get_mut(target).unwrap();
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(self.buckets) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+
+    pub fn observe_duration(&self, target: &InvocationTarget, duration: Duration) {
+        self.observe(target, duration.as_secs_f64());
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} histogram", self.name);
+        for (target, histogram) in self.values.lock().unwrap().iter() {
+            let labels = labels(target);
+            for (count, bound) in histogram.buckets.iter().zip(self.buckets) {
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{{},le=\"{}\"}} {}",
+                    self.name, labels, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"+Inf\"}} {}",
+                self.name, labels, histogram.count
+            );
+            let _ = writeln!(out, "{}_sum{{{}}} {}", self.name, labels, histogram.sum);
+            let _ = writeln!(out, "{}_count{{{}}} {}", self.name, labels, histogram.count);
+        }
+    }
+}
+
+fn labels(target: &InvocationTarget) -> String {
+    format!(
+        "service=\"{}\",handler=\"{}\"",
+        escape(&target.service),
+        escape(&target.handler)
+    )
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Metrics fed by the state machines of the invocations.
+///
+/// The invocations served by the process feed one instance, rendered by [`render`].
+pub struct Metrics {
+    pub(crate) invocations_started: Counter,
+    pub(crate) invocations_completed: Counter,
+    pub(crate) invocations_failed: Counter,
+    pub(crate) invocations_suspended: Counter,
+    pub(crate) invocations_rejected: Counter,
+    pub(crate) run_retries: Counter,
+    pub(crate) replay_duration: Histogram,
+    pub(crate) journal_length: Histogram,
+    pub(crate) run_duration: Histogram,
+}
+
+pub(crate) static METRICS: Metrics = Metrics::new();
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Self {
+            invocations_started: Counter::new(
+                "restate_sdk_invocations_started_total",
+                "Invocation attempts started.",
+            ),
+            invocations_completed: Counter::new(
+                "restate_sdk_invocations_completed_total",
+                "Invocation attempts that wrote their output.",
+            ),
+            invocations_failed: Counter::new(
+                "restate_sdk_invocations_failed_total",
+                "Invocation attempts that failed with a retryable error.",
+            ),
+            invocations_suspended: Counter::new(
+                "restate_sdk_invocations_suspended_total",
+                "Invocation attempts that suspended.",
+            ),
+            invocations_rejected: Counter::new(
+                "restate_sdk_invocations_rejected_total",
+                "Invocation attempts rejected by a concurrency limit.",
+            ),
+            run_retries: Counter::new(
+                "restate_sdk_run_retries_total",
+                "Side effects run again after the previous attempt failed while running them.",
+            ),
+            replay_duration: Histogram::new(
+                "restate_sdk_replay_duration_seconds",
+                "Time spent replaying the journal of an invocation attempt.",
+                SECONDS_BUCKETS,
+            ),
+            journal_length: Histogram::new(
+                "restate_sdk_journal_length",
+                "Journal entries of an invocation attempt once it ends.",
+                ENTRIES_BUCKETS,
+            ),
+            run_duration: Histogram::new(
+                "restate_sdk_run_duration_seconds",
+                "Time spent running a side effect.",
+                SECONDS_BUCKETS,
+            ),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.invocations_started.render(&mut out);
+        self.invocations_completed.render(&mut out);
+        self.invocations_failed.render(&mut out);
+        self.invocations_suspended.render(&mut out);
+        self.invocations_rejected.render(&mut out);
+        self.run_retries.render(&mut out);
+        self.replay_duration.render(&mut out);
+        self.journal_length.render(&mut out);
+        self.run_duration.render(&mut out);
+        out
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Renders the metrics of the invocations served by the process.
+pub fn render() -> String {
+    METRICS.render()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_invocation_target() {
+        assert_eq!(
+            InvocationTarget::from_path("/invoke/Greeter/greet"),
+            InvocationTarget {
+                service: "Greeter".to_string(),
+                handler: "greet".to_string(),
+            }
+        );
+        assert_eq!(
+            InvocationTarget::from_path("/discover"),
+            InvocationTarget::default()
+        );
+    }
+
+    #[test]
+    fn test_render_histogram() {
+        let histogram = Histogram::new("test_seconds", "Test.", &[0.1, 1.0]);
+        let target = InvocationTarget {
+            service: "Greeter".to_string(),
+            handler: "gr\"eet".to_string(),
+        };
+        histogram.observe(&target, 0.5);
+        histogram.observe(&target, 2.0);
+        let mut out = String::new();
+        histogram.render(&mut out);
+        assert_eq!(
+            out,
+            "# HELP test_seconds Test.\n\
+             # TYPE test_seconds histogram\n\
+             test_seconds_bucket{service=\"Greeter\",handler=\"gr\\\"eet\",le=\"0.1\"} 0\n\
+             test_seconds_bucket{service=\"Greeter\",handler=\"gr\\\"eet\",le=\"1\"} 1\n\
+             test_seconds_bucket{service=\"Greeter\",handler=\"gr\\\"eet\",le=\"+Inf\"} 2\n\
+             test_seconds_sum{service=\"Greeter\",handler=\"gr\\\"eet\"} 2.5\n\
+             test_seconds_count{service=\"Greeter\",handler=\"gr\\\"eet\"} 2\n"
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! Encoding of the inputs and outputs of the handlers in the bodies of their invocations.
+
+use bytes::Bytes;
+use restate_sdk_types::endpoint_manifest::{InputPayload, OutputPayload};
+use serde::{de::DeserializeOwned, Serialize};
+
+const JSON: &str = "application/json";
+
+/// Input or output of a handler, decoded from and encoded in the body of its invocations.
+///
+/// The types implementing `Serialize` and `Deserialize` are encoded as JSON, [`Raw`] passes the body as
+/// it is and [`Empty`] stands for the absence of body.
+pub trait Payload: Sized {
+    /// Described in the manifest when the payload is the input of a handler.
+    fn input_payload() -> InputPayload;
+
+    /// Described in the manifest when the payload is the output of a handler.
+    fn output_payload() -> OutputPayload;
+
+    fn decode(body: Bytes) -> Result<Self, anyhow::Error>;
+
+    fn encode(&self) -> Result<Bytes, anyhow::Error>;
+}
+
+impl<T> Payload for T
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn input_payload() -> InputPayload {
+        InputPayload {
+            content_type: Some(JSON.to_string()),
+            json_schema: None,
+            required: Some(true),
+        }
+    }
+
+    fn output_payload() -> OutputPayload {
+        OutputPayload {
+            content_type: Some(JSON.to_string()),
+            json_schema: None,
+            set_content_type_if_empty: None,
+        }
+    }
+
+    fn decode(body: Bytes) -> Result<Self, anyhow::Error> {
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    fn encode(&self) -> Result<Bytes, anyhow::Error> {
+        Ok(serde_json::to_vec(self)?.into())
+    }
+}
+
+/// Payload encoded as JSON, the same as `T` itself but spelled out in the signature of a handler.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Json<T>(pub T);
+
+impl<T> Payload for Json<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn input_payload() -> InputPayload {
+        T::input_payload()
+    }
+
+    fn output_payload() -> OutputPayload {
+        T::output_payload()
+    }
+
+    fn decode(body: Bytes) -> Result<Self, anyhow::Error> {
+        T::decode(body).map(Json)
+    }
+
+    fn encode(&self) -> Result<Bytes, anyhow::Error> {
+        self.0.encode()
+    }
+}
+
+/// Body taken as it is, of any content type, and returned as `application/octet-stream`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Raw(pub Bytes);
+
+impl Payload for Raw {
+    fn input_payload() -> InputPayload {
+        InputPayload {
+            content_type: Some("*/*".to_string()),
+            json_schema: None,
+            required: Some(false),
+        }
+    }
+
+    fn output_payload() -> OutputPayload {
+        OutputPayload {
+            content_type: Some("application/octet-stream".to_string()),
+            json_schema: None,
+            set_content_type_if_empty: Some(false),
+        }
+    }
+
+    fn decode(body: Bytes) -> Result<Self, anyhow::Error> {
+        Ok(Raw(body))
+    }
+
+    fn encode(&self) -> Result<Bytes, anyhow::Error> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Absence of body, the input of the handlers taking none and the output of the ones returning `()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Empty;
+
+impl Payload for Empty {
+    fn input_payload() -> InputPayload {
+        InputPayload {
+            content_type: None,
+            json_schema: None,
+            required: None,
+        }
+    }
+
+    fn output_payload() -> OutputPayload {
+        OutputPayload {
+            content_type: None,
+            json_schema: None,
+            set_content_type_if_empty: None,
+        }
+    }
+
+    fn decode(_body: Bytes) -> Result<Self, anyhow::Error> {
+        Ok(Empty)
+    }
+
+    fn encode(&self) -> Result<Bytes, anyhow::Error> {
+        Ok(Bytes::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Greeting {
+        name: String,
+    }
+
+    #[test]
+    fn test_json_payload() {
+        let greeting = Greeting {
+            name: "Restate".to_string(),
+        };
+        let body = greeting.encode().unwrap();
+        assert_eq!(body, Bytes::from_static(b"{\"name\":\"Restate\"}"));
+        assert_eq!(Json::<Greeting>::decode(body.clone()).unwrap(), Json(greeting));
+        assert_eq!(Json(()).encode().unwrap(), Bytes::from_static(b"null"));
+        assert!(Greeting::decode(Bytes::new()).is_err());
+        assert_eq!(Greeting::input_payload().required, Some(true));
+    }
+
+    #[test]
+    fn test_raw_payload() {
+        let body = Bytes::from_static(b"\x00not json\xff");
+        assert_eq!(Raw::decode(body.clone()).unwrap().encode().unwrap(), body);
+        assert_eq!(Raw::input_payload().content_type.as_deref(), Some("*/*"));
+        assert_eq!(
+            Raw::output_payload().content_type.as_deref(),
+            Some("application/octet-stream")
+        );
+    }
+
+    #[test]
+    fn test_empty_payload() {
+        assert_eq!(Empty::decode(Bytes::new()).unwrap(), Empty);
+        assert!(Empty.encode().unwrap().is_empty());
+        assert!(Empty::input_payload().content_type.is_none());
+        assert!(Empty::output_payload().content_type.is_none());
+    }
+}
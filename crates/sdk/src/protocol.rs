@@ -2,6 +2,9 @@ use restate_sdk_types::service_protocol::*;
 
 pub const AWAKEABLE_IDENTIFIER_PREFIX: &str = "prom_1";
 
+/// Custom entry code of the combinator entries, codes above it are free for user defined entries.
+pub const COMBINATOR_ENTRY_CODE: u16 = 0xFC00;
+
 // These message types will trigger sending a suspension message from the runtime
 // for each of the protocol modes
 pub enum SuspensionTriggers {
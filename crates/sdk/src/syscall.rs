@@ -1,13 +1,16 @@
-use crate::machine::StateMachine;
+use crate::{errors, machine::StateMachine, payload::Payload, protocol::COMBINATOR_ENTRY_CODE};
+use anyhow::anyhow;
 use bytes::Bytes;
 use futures_util::FutureExt;
-use parking_lot::{Mutex, MutexGuard};
 use pin_project::pin_project;
 use prost::Message;
+use restate_sdk_core::RunAction;
 use restate_sdk_types::{
+    errors::InvocationError,
     journal::{
-        AwakeableEntry, ClearStateEntry, CompletePromiseEntry, Entry, EntryResult, GetPromiseEntry,
-        GetStateEntry, GetStateKeysEntry, InvokeEntry, PeekPromiseEntry, RunEntry, SetStateEntry, SleepEntry,
+        AwakeableEntry, ClearStateEntry, CompletePromiseEntry, CustomEntry, Entry, EntryResult,
+        GetPromiseEntry, GetStateEntry, GetStateKeysEntry, InvokeEntry, PeekPromiseEntry, RunEntry,
+        SetStateEntry, SleepEntry,
     },
     service_protocol,
     service_protocol::{get_state_keys_entry_message, CombinatorEntryMessage},
@@ -19,11 +22,10 @@ use std::{
     pin::Pin,
     sync::{
         atomic::{AtomicBool, AtomicU32, Ordering},
-        mpsc::{Receiver, RecvError, SyncSender},
         Arc,
     },
-    task::{Context, Poll, Waker},
-    time::SystemTime,
+    task::{ready, Context, Poll},
+    time::{Instant, SystemTime},
 };
 use tracing::{debug, info};
 
@@ -31,6 +33,108 @@ pub trait JournalIndex {
     fn entry_index(&self) -> u32;
 }
 
+/// A future backed by a journal entry.
+pub trait DurableFuture: Future + Sized {
+    /// Sets the name of the journal entry, shown when inspecting the journal of the invocation.
+    fn named(self, name: impl Into<String>) -> Self;
+}
+
+/// A journal entry defined outside of the SDK, recorded with its own code in the custom entry range.
+///
+/// The entry is recorded the first time the handler reaches it. When the invocation is replayed the
+/// recorded payload is decoded and returned instead, so the entry must be decodable by every later
+/// version of the handler.
+pub trait CustomJournalEntry: Sized {
+    /// Message type code of the entry, in the range `0xFC01..=0xFFFF`. Recording an entry with a code
+    /// outside of the range fails the build:
+    ///
+    /// ```compile_fail
+    /// # use bytes::Bytes;
+    /// # use restate_sdk::context::{Context, ContextBase, CustomJournalEntry};
+    /// struct Marker;
+    ///
+    /// impl CustomJournalEntry for Marker {
+    ///     const CODE: u16 = 0xFC00;
+    /// #   fn encode(&self) -> Result<Bytes, anyhow::Error> { Ok(Bytes::new()) }
+    /// #   fn decode(_value: Bytes) -> Result<Self, anyhow::Error> { Ok(Marker) }
+    /// }
+    ///
+    /// let record: fn(&Context) = |ctx| drop(ctx.record(|| Marker));
+    /// ```
+    ///
+    /// The code is checked against the replayed entry, a different code fails the invocation with a
+    /// journal mismatch.
+    const CODE: u16;
+
+    /// Encodes the payload of the entry.
+    fn encode(&self) -> Result<Bytes, anyhow::Error>;
+
+    /// Decodes the payload of a replayed entry.
+    fn decode(value: Bytes) -> Result<Self, anyhow::Error>;
+}
+
+macro_rules! durable_future_impl {
+    ($future:ident) => {
+        impl DurableFuture for $future {
+            fn named(mut self, name: impl Into<String>) -> Self {
+                self.entry_name = Some(name.into());
+                self
+            }
+        }
+    };
+}
+
+/// Maps the output of a [`DurableFuture`], keeping it nameable.
+#[pin_project]
+pub struct MapOutput<F, M> {
+    #[pin]
+    future: F,
+    map: Option<M>,
+}
+
+impl<F, M> MapOutput<F, M> {
+    pub fn new(future: F, map: M) -> Self {
+        Self {
+            future,
+            map: Some(map),
+        }
+    }
+}
+
+impl<F, M, T> Future for MapOutput<F, M>
+where
+    F: Future,
+    M: FnOnce(F::Output) -> T,
+{
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let output = ready!(this.future.poll(cx));
+        let map = this.map.take().expect("MapOutput polled after completion");
+        Poll::Ready(map(output))
+    }
+}
+
+impl<F, M, T> DurableFuture for MapOutput<F, M>
+where
+    F: DurableFuture,
+    M: FnOnce(F::Output) -> T,
+{
+    fn named(self, name: impl Into<String>) -> Self {
+        Self {
+            future: self.future.named(name),
+            map: self.map,
+        }
+    }
+}
+
+impl<F: JournalIndex, M> JournalIndex for MapOutput<F, M> {
+    fn entry_index(&self) -> u32 {
+        self.future.entry_index()
+    }
+}
+
 macro_rules! journal_index_impl {
     ($future:ident) => {
         impl JournalIndex for $future {
@@ -44,15 +148,10 @@ macro_rules! journal_index_impl {
 macro_rules! future_impl {
     ($future:ident, $entry:ident) => {
         impl $future {
-            pub fn new(
-                entry_name: Option<String>,
-                entry: $entry,
-                state_machine: Arc<Mutex<StateMachine>>,
-            ) -> Self {
+            pub fn new(entry_name: Option<String>, entry: $entry) -> Self {
                 Self {
                     entry_name,
                     entry,
-                    state_machine,
                     entry_index: Arc::new(AtomicU32::new(0)),
                     polled: Arc::new(AtomicBool::new(false)),
                 }
@@ -61,56 +160,52 @@ macro_rules! future_impl {
             fn entry_name(&self) -> Option<String> {
                 self.entry_name.clone()
             }
-
-            fn set_span(&self, mut state_machine: MutexGuard<'_, StateMachine>) {
-                state_machine.set_span()
-            }
         }
     };
 }
 
 pub struct GetStateFuture {
     entry: GetStateEntry,
-    state_machine: Arc<Mutex<StateMachine>>,
     entry_name: Option<String>,
     entry_index: Arc<AtomicU32>,
     polled: Arc<AtomicBool>,
 }
 
 future_impl!(GetStateFuture, GetStateEntry);
+durable_future_impl!(GetStateFuture);
 
 impl Future for GetStateFuture {
     type Output = Bytes;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut state_machine = self.state_machine.lock();
-        let entry_index = if self.polled.fetch_or(true, Ordering::Relaxed) {
-            Some(self.entry_index.load(Ordering::Relaxed))
-        } else {
-            None
-        };
-        let (entry_index, result) = state_machine.handle_user_code_message(
-            self.entry_name(),
-            entry_index,
-            Entry::GetState(self.entry.clone()),
-            Some(cx.waker().clone()),
-        );
-        if let Some(result) = result {
-            debug!("GetState Result ready for entry: {}", entry_index);
-            self.set_span(state_machine);
-            Poll::Ready(result)
-        } else {
-            debug!("GetState Result pending for entry: {}", entry_index);
-            self.entry_index.store(entry_index, Ordering::Relaxed);
-            state_machine.abort_on_replay();
-            Poll::Pending
-        }
+        StateMachine::poll_with(|state_machine| {
+            let entry_index = if self.polled.fetch_or(true, Ordering::Relaxed) {
+                Some(self.entry_index.load(Ordering::Relaxed))
+            } else {
+                None
+            };
+            let (entry_index, result) = state_machine.handle_user_code_message(
+                self.entry_name(),
+                entry_index,
+                Entry::GetState(self.entry.clone()),
+                Some(cx.waker().clone()),
+            );
+            if let Some(result) = result {
+                debug!("GetState Result ready for entry: {}", entry_index);
+                state_machine.set_span();
+                Poll::Ready(result)
+            } else {
+                debug!("GetState Result pending for entry: {}", entry_index);
+                self.entry_index.store(entry_index, Ordering::Relaxed);
+                state_machine.abort_on_replay();
+                Poll::Pending
+            }
+        })
     }
 }
 
 pub struct GetStateKeysFuture {
     entry: GetStateKeysEntry,
-    state_machine: Arc<Mutex<StateMachine>>,
     entry_name: Option<String>,
     entry_index: Arc<AtomicU32>,
     polled: Arc<AtomicBool>,
@@ -118,40 +213,41 @@ pub struct GetStateKeysFuture {
 
 journal_index_impl!(GetStateKeysFuture);
 future_impl!(GetStateKeysFuture, GetStateKeysEntry);
+durable_future_impl!(GetStateKeysFuture);
 
 impl Future for GetStateKeysFuture {
     type Output = Vec<Bytes>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut state_machine = self.state_machine.lock();
-        let entry_index = if self.polled.fetch_or(true, Ordering::Relaxed) {
-            Some(self.entry_index.load(Ordering::Relaxed))
-        } else {
-            None
-        };
-        let (entry_index, result) = state_machine.handle_user_code_message(
-            self.entry_name(),
-            entry_index,
-            Entry::GetStateKeys(self.entry.clone()),
-            Some(cx.waker().clone()),
-        );
-        if let Some(result) = result {
-            debug!("GetStateKeys Result ready for entry: {}", entry_index);
-            let result = get_state_keys_entry_message::StateKeys::decode(result).unwrap();
-            self.set_span(state_machine);
-            Poll::Ready(result.keys)
-        } else {
-            debug!("GetStateKeys Result pending for entry: {}", entry_index);
-            self.entry_index.store(entry_index, Ordering::Relaxed);
-            state_machine.abort_on_replay();
-            Poll::Pending
-        }
+        StateMachine::poll_with(|state_machine| {
+            let entry_index = if self.polled.fetch_or(true, Ordering::Relaxed) {
+                Some(self.entry_index.load(Ordering::Relaxed))
+            } else {
+                None
+            };
+            let (entry_index, result) = state_machine.handle_user_code_message(
+                self.entry_name(),
+                entry_index,
+                Entry::GetStateKeys(self.entry.clone()),
+                Some(cx.waker().clone()),
+            );
+            if let Some(result) = result {
+                debug!("GetStateKeys Result ready for entry: {}", entry_index);
+                let result = get_state_keys_entry_message::StateKeys::decode(result).unwrap();
+                state_machine.set_span();
+                Poll::Ready(result.keys)
+            } else {
+                debug!("GetStateKeys Result pending for entry: {}", entry_index);
+                self.entry_index.store(entry_index, Ordering::Relaxed);
+                state_machine.abort_on_replay();
+                Poll::Pending
+            }
+        })
     }
 }
 
 pub struct SetStateFuture {
     entry: SetStateEntry,
-    state_machine: Arc<Mutex<StateMachine>>,
     entry_name: Option<String>,
     entry_index: Arc<AtomicU32>,
     polled: Arc<AtomicBool>,
@@ -159,6 +255,7 @@ pub struct SetStateFuture {
 
 journal_index_impl!(SetStateFuture);
 future_impl!(SetStateFuture, SetStateEntry);
+durable_future_impl!(SetStateFuture);
 
 impl Future for SetStateFuture {
     type Output = ();
@@ -169,12 +266,15 @@ impl Future for SetStateFuture {
         } else {
             None
         };
-        self.state_machine.lock().handle_user_code_message(
-            self.entry_name(),
-            entry_index,
-            Entry::SetState(self.entry.clone()),
-            None,
-        );
+        ready!(StateMachine::poll_with(|state_machine| {
+            state_machine.handle_user_code_message(
+                self.entry_name(),
+                entry_index,
+                Entry::SetState(self.entry.clone()),
+                None,
+            );
+            Poll::Ready(())
+        }));
         debug!("SetState Result ready for entry: {:?}", entry_index);
         Poll::Ready(())
     }
@@ -182,7 +282,6 @@ impl Future for SetStateFuture {
 
 pub struct ClearStateFuture {
     entry: ClearStateEntry,
-    state_machine: Arc<Mutex<StateMachine>>,
     polled: Arc<AtomicBool>,
     entry_name: Option<String>,
     entry_index: Arc<AtomicU32>,
@@ -190,6 +289,7 @@ pub struct ClearStateFuture {
 
 journal_index_impl!(ClearStateFuture);
 future_impl!(ClearStateFuture, ClearStateEntry);
+durable_future_impl!(ClearStateFuture);
 
 impl Future for ClearStateFuture {
     type Output = ();
@@ -200,28 +300,29 @@ impl Future for ClearStateFuture {
         } else {
             None
         };
-        self.state_machine.lock().handle_user_code_message(
-            self.entry_name(),
-            entry_index,
-            Entry::ClearState(self.entry.clone()),
-            None,
-        );
+        ready!(StateMachine::poll_with(|state_machine| {
+            state_machine.handle_user_code_message(
+                self.entry_name(),
+                entry_index,
+                Entry::ClearState(self.entry.clone()),
+                None,
+            );
+            Poll::Ready(())
+        }));
         debug!("ClearState Result ready for entry: {:?}", entry_index);
         Poll::Ready(())
     }
 }
 
 pub struct ClearAllStateFuture {
-    state_machine: Arc<Mutex<StateMachine>>,
     polled: Arc<AtomicBool>,
     entry_name: Option<String>,
     entry_index: Arc<AtomicU32>,
 }
 
 impl ClearAllStateFuture {
-    pub fn new(entry_name: Option<String>, state_machine: Arc<Mutex<StateMachine>>) -> Self {
+    pub fn new(entry_name: Option<String>) -> Self {
         Self {
-            state_machine,
             entry_name,
             entry_index: Arc::new(AtomicU32::new(0)),
             polled: Arc::new(AtomicBool::new(false)),
@@ -238,6 +339,7 @@ impl ClearAllStateFuture {
 }
 
 journal_index_impl!(ClearAllStateFuture);
+durable_future_impl!(ClearAllStateFuture);
 
 impl Future for ClearAllStateFuture {
     type Output = ();
@@ -248,12 +350,15 @@ impl Future for ClearAllStateFuture {
         } else {
             None
         };
-        self.state_machine.lock().handle_user_code_message(
-            self.entry_name(),
-            entry_index,
-            Entry::ClearAllState,
-            None,
-        );
+        ready!(StateMachine::poll_with(|state_machine| {
+            state_machine.handle_user_code_message(
+                self.entry_name(),
+                entry_index,
+                Entry::ClearAllState,
+                None,
+            );
+            Poll::Ready(())
+        }));
         debug!("ClearAllState Result ready for entry: {:?}", entry_index);
         Poll::Ready(())
     }
@@ -261,23 +366,19 @@ impl Future for ClearAllStateFuture {
 
 pub struct AwakeableFuture {
     entry: AwakeableEntry,
-    state_machine: Arc<Mutex<StateMachine>>,
     entry_name: Option<String>,
     entry_index: Arc<AtomicU32>,
     polled: Arc<AtomicBool>,
 }
 
 impl AwakeableFuture {
-    pub fn new(
-        entry_name: Option<String>,
-        entry: AwakeableEntry,
-        state_machine: Arc<Mutex<StateMachine>>,
-    ) -> Self {
-        let entry_index = state_machine.lock().get_next_user_code_journal_index();
+    pub fn new(entry_name: Option<String>, entry: AwakeableEntry) -> Self {
+        let entry_index =
+            StateMachine::try_with(|state_machine| state_machine.get_next_user_code_journal_index())
+                .unwrap_or_default();
         Self {
             entry_name,
             entry,
-            state_machine,
             entry_index: Arc::new(AtomicU32::new(entry_index)),
             polled: Arc::new(AtomicBool::new(false)),
         }
@@ -290,47 +391,44 @@ impl AwakeableFuture {
     pub fn entry(&self) -> u32 {
         self.entry_index.load(Ordering::Relaxed)
     }
-
-    fn set_span(&self, mut state_machine: MutexGuard<'_, StateMachine>) {
-        state_machine.set_span()
-    }
 }
 
 journal_index_impl!(AwakeableFuture);
+durable_future_impl!(AwakeableFuture);
 //future_impl!(AwakeableFuture, AwakeableEntry);
 
 impl Future for AwakeableFuture {
     type Output = Bytes;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut state_machine = self.state_machine.lock();
-        let entry_index = if self.polled.fetch_or(true, Ordering::Relaxed) {
-            Some(self.entry_index.load(Ordering::Relaxed))
-        } else {
-            None
-        };
-        let (entry_index, result) = state_machine.handle_user_code_message(
-            self.entry_name(),
-            entry_index,
-            Entry::Awakeable(self.entry.clone()),
-            Some(cx.waker().clone()),
-        );
-        if let Some(result) = result {
-            debug!("Run Result ready for entry: {}", entry_index);
-            self.set_span(state_machine);
-            Poll::Ready(result)
-        } else {
-            debug!("Run Result pending for entry: {}", entry_index);
-            //self.entry_index.store(entry_index, Ordering::Relaxed);
-            state_machine.abort_on_replay();
-            Poll::Pending
-        }
+        StateMachine::poll_with(|state_machine| {
+            let entry_index = if self.polled.fetch_or(true, Ordering::Relaxed) {
+                Some(self.entry_index.load(Ordering::Relaxed))
+            } else {
+                None
+            };
+            let (entry_index, result) = state_machine.handle_user_code_message(
+                self.entry_name(),
+                entry_index,
+                Entry::Awakeable(self.entry.clone()),
+                Some(cx.waker().clone()),
+            );
+            if let Some(result) = result {
+                debug!("Run Result ready for entry: {}", entry_index);
+                state_machine.set_span();
+                Poll::Ready(result)
+            } else {
+                debug!("Run Result pending for entry: {}", entry_index);
+                //self.entry_index.store(entry_index, Ordering::Relaxed);
+                state_machine.abort_on_replay();
+                Poll::Pending
+            }
+        })
     }
 }
 
 pub struct SleepFuture {
     entry: SleepEntry,
-    state_machine: Arc<Mutex<StateMachine>>,
     entry_name: Option<String>,
     entry_index: Arc<AtomicU32>,
     polled: Arc<AtomicBool>,
@@ -338,150 +436,207 @@ pub struct SleepFuture {
 
 journal_index_impl!(SleepFuture);
 future_impl!(SleepFuture, SleepEntry);
+durable_future_impl!(SleepFuture);
 
 impl Future for SleepFuture {
     type Output = Bytes;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         debug!("Sleep future polling");
-        let mut state_machine = self.state_machine.lock();
-        let entry_index = if self.polled.fetch_or(true, Ordering::Relaxed) {
-            Some(self.entry_index.load(Ordering::Relaxed))
-        } else {
-            None
-        };
-        let (entry_index, result) = state_machine.handle_user_code_message(
-            self.entry_name(),
-            entry_index,
-            Entry::Sleep(self.entry.clone()),
-            Some(cx.waker().clone()),
-        );
-        if let Some(result) = result {
-            debug!("Sleep Result ready for entry: {}", entry_index);
-            self.set_span(state_machine);
-            Poll::Ready(result)
-        } else {
-            debug!("Sleep Result pending for entry: {}", entry_index);
-            self.entry_index.store(entry_index, Ordering::Relaxed);
-            state_machine.abort_on_replay();
-            Poll::Pending
-        }
+        StateMachine::poll_with(|state_machine| {
+            let entry_index = if self.polled.fetch_or(true, Ordering::Relaxed) {
+                Some(self.entry_index.load(Ordering::Relaxed))
+            } else {
+                None
+            };
+            let (entry_index, result) = state_machine.handle_user_code_message(
+                self.entry_name(),
+                entry_index,
+                Entry::Sleep(self.entry.clone()),
+                Some(cx.waker().clone()),
+            );
+            if let Some(result) = result {
+                debug!("Sleep Result ready for entry: {}", entry_index);
+                state_machine.set_span();
+                Poll::Ready(result)
+            } else {
+                debug!("Sleep Result pending for entry: {}", entry_index);
+                self.entry_index.store(entry_index, Ordering::Relaxed);
+                state_machine.abort_on_replay();
+                Poll::Pending
+            }
+        })
     }
 }
 
-pub struct RunFuture {
-    state_machine: Arc<Mutex<StateMachine>>,
+/// Runs a side effect on the invocation task and records its result in the journal.
+///
+/// The action is skipped when its result is replayed, and aborted when the future is dropped.
+#[pin_project]
+pub struct RunFuture<F: RunAction> {
     entry_name: Option<String>,
     entry_index: Arc<AtomicU32>,
-    polled: Arc<AtomicBool>,
-    run_tx: SyncSender<Waker>,
-    result_rx: Receiver<RunEntry>,
+    action: Option<F>,
+    #[pin]
+    running: Option<F::OutputFuture>,
+    running_action: Option<RunningAction>,
+}
+
+/// Counts a side effect as running on the state machine until dropped.
+struct RunningAction {
+    started: Instant,
+}
+
+impl RunningAction {
+    fn start() -> Self {
+        StateMachine::with(|state_machine| state_machine.start_action());
+        RunningAction {
+            started: Instant::now(),
+        }
+    }
 }
 
-journal_index_impl!(RunFuture);
-//future_impl!(RunFuture, RunEntry);
+impl Drop for RunningAction {
+    fn drop(&mut self) {
+        // The invocation task may be gone already when the side effect is dropped
+        StateMachine::try_with(|state_machine| state_machine.finish_action(self.started.elapsed()));
+    }
+}
 
-impl RunFuture {
-    pub fn new(
-        entry_name: Option<String>,
-        state_machine: Arc<Mutex<StateMachine>>,
-        run_tx: SyncSender<Waker>,
-        result_rx: Receiver<RunEntry>,
-    ) -> Self {
-        let entry_index = state_machine.lock().get_next_user_code_journal_index();
+impl<F: RunAction> RunFuture<F> {
+    pub fn new(entry_name: Option<String>, action: F) -> Self {
+        let entry_index =
+            StateMachine::try_with(|state_machine| state_machine.get_next_user_code_journal_index())
+                .unwrap_or_default();
         Self {
             entry_name,
-            state_machine,
             entry_index: Arc::new(AtomicU32::new(entry_index)),
-            polled: Arc::new(AtomicBool::new(false)),
-            run_tx,
-            result_rx,
+            action: Some(action),
+            running: None,
+            running_action: None,
         }
     }
+}
 
-    fn entry_name(&self) -> Option<String> {
-        self.entry_name.clone()
-    }
-
-    pub fn entry(&self) -> u32 {
+impl<F: RunAction> JournalIndex for RunFuture<F> {
+    fn entry_index(&self) -> u32 {
         self.entry_index.load(Ordering::Relaxed)
     }
+}
 
-    fn set_span(&self, mut state_machine: MutexGuard<'_, StateMachine>) {
-        state_machine.set_span()
+impl<F, T> DurableFuture for RunFuture<F>
+where
+    F: RunAction<Output = Result<T, anyhow::Error>>,
+    for<'a> T: Serialize + Deserialize<'a>,
+{
+    fn named(mut self, name: impl Into<String>) -> Self {
+        self.entry_name = Some(name.into());
+        self
     }
 }
 
-impl Future for RunFuture {
-    type Output = Bytes;
+impl<F, T> Future for RunFuture<F>
+where
+    F: RunAction<Output = Result<T, anyhow::Error>>,
+    for<'a> T: Serialize + Deserialize<'a>,
+{
+    type Output = Result<T, anyhow::Error>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut state_machine = self.state_machine.lock();
-        let running = self.polled.fetch_or(true, Ordering::Relaxed);
-        return if !state_machine.is_next_entry_replaying() {
-            if !running {
-                let _ = self.run_tx.send(cx.waker().clone());
-                Poll::Pending
-            } else {
-                match self.result_rx.recv() {
-                    Ok(result) => {
-                        let (entry_index, result) = state_machine.handle_user_code_message(
-                            self.entry_name(),
-                            None,
-                            Entry::Run(result),
-                            None,
-                        );
-                        debug!("Run Result ready for entry: {}", entry_index);
-                        self.set_span(state_machine);
-                        Poll::Ready(Bytes::new())
-                    }
-                    Err(err) => {
-                        debug!("Run Result pending with err: {}", err);
-                        Poll::Pending
-                    }
+        let mut this = self.project();
+        if this.running.is_none() {
+            let replayed = ready!(StateMachine::poll_with(|state_machine| {
+                if !state_machine.is_next_entry_replaying() {
+                    return Poll::Ready(None);
                 }
+                let (entry_index, result) = state_machine.handle_user_code_message(
+                    this.entry_name.clone(),
+                    None,
+                    Entry::Run(RunEntry {
+                        result: EntryResult::Success(Bytes::new()),
+                    }),
+                    None,
+                );
+                debug!("Run Result replayed for entry: {}", entry_index);
+                state_machine.set_span();
+                let result = match (result, state_machine.replayed_entry(entry_index)) {
+                    (
+                        _,
+                        Some(Entry::Run(RunEntry {
+                            result: EntryResult::Failure(code, message),
+                        })),
+                    ) => Err(InvocationError::new(*code, message).into()),
+                    (Some(result), Some(Entry::Run(_))) => Ok(result),
+                    // The attempt is retried, the journal does not hold the run of the handler
+                    _ => {
+                        state_machine.fail(errors::journal_mismatch(entry_index, "run"));
+                        Err(anyhow!("Replayed entry {} does not match the run", entry_index))
+                    }
+                };
+                Poll::Ready(Some(result))
+            }));
+            if let Some(result) = replayed {
+                return Poll::Ready(
+                    result.and_then(|result| serde_json::from_slice(&result).map_err(Into::into)),
+                );
             }
-        } else {
-            let (entry_index, result) = state_machine.handle_user_code_message(
-                self.entry_name(),
+            let action = this.action.take().expect("Run polled after completion");
+            *this.running_action = Some(RunningAction::start());
+            this.running.set(Some(action()));
+        }
+
+        let result = ready!(this.running.as_mut().as_pin_mut().unwrap().poll(cx));
+        this.running.set(None);
+        *this.running_action = None;
+        // Failures with a code are journaled so that they are replayed, the other ones are retried
+        let (entry_result, result) = match result {
+            Ok(value) => (
+                EntryResult::Success(serde_json::to_vec(&value)?.into()),
+                Ok(value),
+            ),
+            Err(error) => match error.downcast_ref::<InvocationError>() {
+                Some(failure) => (
+                    EntryResult::Failure(failure.code(), failure.message().to_string().into()),
+                    Err(error),
+                ),
+                None => return Poll::Ready(Err(error)),
+            },
+        };
+        ready!(StateMachine::poll_with(|state_machine| {
+            let (entry_index, _) = state_machine.handle_user_code_message(
+                this.entry_name.clone(),
                 None,
-                Entry::Run(RunEntry {
-                    result: EntryResult::Success(Bytes::new()),
-                }),
+                Entry::Run(RunEntry { result: entry_result }),
                 None,
             );
             debug!("Run Result ready for entry: {}", entry_index);
-            self.set_span(state_machine);
-            Poll::Ready(result.unwrap())
-        };
+            state_machine.set_span();
+            Poll::Ready(())
+        }));
+        Poll::Ready(result)
     }
 }
 
 pub struct CallServiceFuture<T>
 where
-    for<'a> T: Serialize + Deserialize<'a>,
+    T: Payload,
 {
-    invoke_entry: InvokeEntry,
-    state_machine: Arc<Mutex<StateMachine>>,
+    /// Call to journal, or the error encoding its parameter, returned without journaling the call
+    invoke_entry: Result<InvokeEntry, Option<anyhow::Error>>,
     entry_name: Option<String>,
     entry_index: Arc<AtomicU32>,
     polled: Arc<AtomicBool>,
-    _ret: PhantomData<T>,
+    _ret: PhantomData<fn() -> T>,
 }
 
 impl<T> CallServiceFuture<T>
 where
-    for<'a> T: Serialize + Deserialize<'a>,
+    T: Payload,
 {
-    pub fn new(
-        entry_name: Option<String>,
-        invoke_entry: InvokeEntry,
-        state_machine: Arc<Mutex<StateMachine>>,
-    ) -> Self {
+    pub fn new(entry_name: Option<String>, invoke_entry: Result<InvokeEntry, anyhow::Error>) -> Self {
         Self {
             entry_name,
-            invoke_entry,
-            state_machine,
+            invoke_entry: invoke_entry.map_err(Some),
             entry_index: Arc::new(AtomicU32::new(0)),
             polled: Arc::new(AtomicBool::new(false)),
             _ret: PhantomData,
@@ -491,59 +646,72 @@ where
     fn entry_name(&self) -> Option<String> {
         self.entry_name.clone()
     }
-
-    fn set_span(&self, mut state_machine: MutexGuard<'_, StateMachine>) {
-        state_machine.set_span()
-    }
 }
 
 impl<T> JournalIndex for CallServiceFuture<T>
 where
-    for<'a> T: Serialize + Deserialize<'a>,
+    T: Payload,
 {
     fn entry_index(&self) -> u32 {
         self.entry_index.load(Ordering::Relaxed)
     }
 }
 
+impl<T> DurableFuture for CallServiceFuture<T>
+where
+    T: Payload,
+{
+    fn named(mut self, name: impl Into<String>) -> Self {
+        self.entry_name = Some(name.into());
+        self
+    }
+}
+
 impl<T> Future for CallServiceFuture<T>
 where
-    for<'a> T: Serialize + Deserialize<'a>,
+    T: Payload,
 {
     type Output = Result<T, anyhow::Error>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         debug!("Call future polling");
-        let mut state_machine = self.state_machine.lock();
-        let entry_index = if self.polled.fetch_or(true, Ordering::Relaxed) {
-            Some(self.entry_index.load(Ordering::Relaxed))
-        } else {
-            None
+        let this = self.get_mut();
+        let invoke_entry = match &mut this.invoke_entry {
+            Ok(invoke_entry) => invoke_entry.clone(),
+            Err(error) => {
+                let error = error.take().expect("CallServiceFuture polled after completion");
+                return Poll::Ready(Err(error));
+            }
         };
-        let (entry_index, result) = state_machine.handle_user_code_message(
-            self.entry_name(),
-            entry_index,
-            Entry::Call(self.invoke_entry.clone()),
-            Some(cx.waker().clone()),
-        );
-        if let Some(result) = result {
-            debug!("Call Result ready for entry: {}", entry_index);
-            self.set_span(state_machine);
-
-            let result: T = serde_json::from_slice(&result).unwrap();
-            Poll::Ready(Ok(result))
-        } else {
-            debug!("Call Result pending for entry: {}", entry_index);
-            self.entry_index.store(entry_index, Ordering::Relaxed);
-            state_machine.abort_on_replay();
-            Poll::Pending
-        }
+        StateMachine::poll_with(|state_machine| {
+            let entry_index = if this.polled.fetch_or(true, Ordering::Relaxed) {
+                Some(this.entry_index.load(Ordering::Relaxed))
+            } else {
+                None
+            };
+            let (entry_index, result) = state_machine.handle_user_code_message(
+                this.entry_name(),
+                entry_index,
+                Entry::Call(invoke_entry),
+                Some(cx.waker().clone()),
+            );
+            if let Some(result) = result {
+                debug!("Call Result ready for entry: {}", entry_index);
+                state_machine.set_span();
+
+                Poll::Ready(T::decode(result))
+            } else {
+                debug!("Call Result pending for entry: {}", entry_index);
+                this.entry_index.store(entry_index, Ordering::Relaxed);
+                state_machine.abort_on_replay();
+                Poll::Pending
+            }
+        })
     }
 }
 
 pub struct GetPromiseFuture {
     entry: GetPromiseEntry,
-    state_machine: Arc<Mutex<StateMachine>>,
     entry_name: Option<String>,
     entry_index: Arc<AtomicU32>,
     polled: Arc<AtomicBool>,
@@ -551,39 +719,40 @@ pub struct GetPromiseFuture {
 
 journal_index_impl!(GetPromiseFuture);
 future_impl!(GetPromiseFuture, GetPromiseEntry);
+durable_future_impl!(GetPromiseFuture);
 
 impl Future for GetPromiseFuture {
     type Output = Bytes;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut state_machine = self.state_machine.lock();
-        let entry_index = if self.polled.fetch_or(true, Ordering::Relaxed) {
-            Some(self.entry_index.load(Ordering::Relaxed))
-        } else {
-            None
-        };
-        let (entry_index, result) = state_machine.handle_user_code_message(
-            self.entry_name(),
-            entry_index,
-            Entry::GetPromise(self.entry.clone()),
-            Some(cx.waker().clone()),
-        );
-        if let Some(result) = result {
-            debug!("GetPromise Result ready for entry: {}", entry_index);
-            self.set_span(state_machine);
-            Poll::Ready(result)
-        } else {
-            debug!("GetPromise Result pending for entry: {}", entry_index);
-            self.entry_index.store(entry_index, Ordering::Relaxed);
-            state_machine.abort_on_replay();
-            Poll::Pending
-        }
+        StateMachine::poll_with(|state_machine| {
+            let entry_index = if self.polled.fetch_or(true, Ordering::Relaxed) {
+                Some(self.entry_index.load(Ordering::Relaxed))
+            } else {
+                None
+            };
+            let (entry_index, result) = state_machine.handle_user_code_message(
+                self.entry_name(),
+                entry_index,
+                Entry::GetPromise(self.entry.clone()),
+                Some(cx.waker().clone()),
+            );
+            if let Some(result) = result {
+                debug!("GetPromise Result ready for entry: {}", entry_index);
+                state_machine.set_span();
+                Poll::Ready(result)
+            } else {
+                debug!("GetPromise Result pending for entry: {}", entry_index);
+                self.entry_index.store(entry_index, Ordering::Relaxed);
+                state_machine.abort_on_replay();
+                Poll::Pending
+            }
+        })
     }
 }
 
 pub struct PeekPromiseFuture {
     entry: PeekPromiseEntry,
-    state_machine: Arc<Mutex<StateMachine>>,
     entry_name: Option<String>,
     entry_index: Arc<AtomicU32>,
     polled: Arc<AtomicBool>,
@@ -591,43 +760,44 @@ pub struct PeekPromiseFuture {
 
 journal_index_impl!(PeekPromiseFuture);
 future_impl!(PeekPromiseFuture, PeekPromiseEntry);
+durable_future_impl!(PeekPromiseFuture);
 
 impl Future for PeekPromiseFuture {
     type Output = Option<Bytes>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut state_machine = self.state_machine.lock();
-        let entry_index = if self.polled.fetch_or(true, Ordering::Relaxed) {
-            Some(self.entry_index.load(Ordering::Relaxed))
-        } else {
-            None
-        };
-        let (entry_index, result) = state_machine.handle_user_code_message(
-            self.entry_name(),
-            entry_index,
-            Entry::PeekPromise(self.entry.clone()),
-            Some(cx.waker().clone()),
-        );
-        if let Some(result) = result {
-            debug!("PeekPromise Result ready for entry: {}", entry_index);
-            self.set_span(state_machine);
-            if !result.is_empty() {
-                Poll::Ready(Some(result))
+        StateMachine::poll_with(|state_machine| {
+            let entry_index = if self.polled.fetch_or(true, Ordering::Relaxed) {
+                Some(self.entry_index.load(Ordering::Relaxed))
+            } else {
+                None
+            };
+            let (entry_index, result) = state_machine.handle_user_code_message(
+                self.entry_name(),
+                entry_index,
+                Entry::PeekPromise(self.entry.clone()),
+                Some(cx.waker().clone()),
+            );
+            if let Some(result) = result {
+                debug!("PeekPromise Result ready for entry: {}", entry_index);
+                state_machine.set_span();
+                if !result.is_empty() {
+                    Poll::Ready(Some(result))
+                } else {
+                    Poll::Ready(None)
+                }
             } else {
-                Poll::Ready(None)
+                debug!("PeekPromise Result pending for entry: {}", entry_index);
+                self.entry_index.store(entry_index, Ordering::Relaxed);
+                state_machine.abort_on_replay();
+                Poll::Pending
             }
-        } else {
-            debug!("PeekPromise Result pending for entry: {}", entry_index);
-            self.entry_index.store(entry_index, Ordering::Relaxed);
-            state_machine.abort_on_replay();
-            Poll::Pending
-        }
+        })
     }
 }
 
 pub struct CompletePromiseFuture {
     entry: CompletePromiseEntry,
-    state_machine: Arc<Mutex<StateMachine>>,
     entry_name: Option<String>,
     entry_index: Arc<AtomicU32>,
     polled: Arc<AtomicBool>,
@@ -635,33 +805,154 @@ pub struct CompletePromiseFuture {
 
 journal_index_impl!(CompletePromiseFuture);
 future_impl!(CompletePromiseFuture, CompletePromiseEntry);
+durable_future_impl!(CompletePromiseFuture);
 
 impl Future for CompletePromiseFuture {
     type Output = ();
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut state_machine = self.state_machine.lock();
-        let entry_index = if self.polled.fetch_or(true, Ordering::Relaxed) {
-            Some(self.entry_index.load(Ordering::Relaxed))
-        } else {
-            None
-        };
-        let (entry_index, result) = state_machine.handle_user_code_message(
-            self.entry_name(),
-            entry_index,
-            Entry::CompletePromise(self.entry.clone()),
-            Some(cx.waker().clone()),
-        );
-        if let Some(_) = result {
-            debug!("CompletePromise Result ready for entry: {}", entry_index);
-            self.set_span(state_machine);
-            Poll::Ready(())
-        } else {
-            debug!("CompletePromise Result pending for entry: {}", entry_index);
-            self.entry_index.store(entry_index, Ordering::Relaxed);
-            state_machine.abort_on_replay();
-            Poll::Pending
+        StateMachine::poll_with(|state_machine| {
+            let entry_index = if self.polled.fetch_or(true, Ordering::Relaxed) {
+                Some(self.entry_index.load(Ordering::Relaxed))
+            } else {
+                None
+            };
+            let (entry_index, result) = state_machine.handle_user_code_message(
+                self.entry_name(),
+                entry_index,
+                Entry::CompletePromise(self.entry.clone()),
+                Some(cx.waker().clone()),
+            );
+            if let Some(_) = result {
+                debug!("CompletePromise Result ready for entry: {}", entry_index);
+                state_machine.set_span();
+                Poll::Ready(())
+            } else {
+                debug!("CompletePromise Result pending for entry: {}", entry_index);
+                self.entry_index.store(entry_index, Ordering::Relaxed);
+                state_machine.abort_on_replay();
+                Poll::Pending
+            }
+        })
+    }
+}
+
+/// Records a [`CustomJournalEntry`], or decodes the recorded one when it is replayed.
+///
+/// The entry is only built when it is not replayed.
+#[pin_project]
+pub struct CustomEntryFuture<E, F> {
+    entry_index: Arc<AtomicU32>,
+    entry: Option<F>,
+    _entry: PhantomData<fn() -> E>,
+}
+
+/// Fails the build when the code of a custom entry is taken by the entries of the SDK.
+///
+/// An associated constant stands in for an inline `const` block, which needs a newer Rust than the
+/// minimum supported one.
+struct CustomEntryCode<E>(PhantomData<E>);
+
+impl<E: CustomJournalEntry> CustomEntryCode<E> {
+    const IN_RANGE: () = assert!(
+        E::CODE > COMBINATOR_ENTRY_CODE,
+        "The code of a custom entry must be in the range 0xFC01..=0xFFFF"
+    );
+}
+
+impl<E: CustomJournalEntry, F> CustomEntryFuture<E, F> {
+    pub fn new(entry: F) -> Self {
+        // Evaluated when the entry type is known, the build fails once it is recorded with a wrong code
+        let () = CustomEntryCode::<E>::IN_RANGE;
+        let entry_index =
+            StateMachine::try_with(|state_machine| state_machine.get_next_user_code_journal_index())
+                .unwrap_or_default();
+        Self {
+            entry_index: Arc::new(AtomicU32::new(entry_index)),
+            entry: Some(entry),
+            _entry: PhantomData,
+        }
+    }
+}
+
+impl<E, F> JournalIndex for CustomEntryFuture<E, F> {
+    fn entry_index(&self) -> u32 {
+        self.entry_index.load(Ordering::Relaxed)
+    }
+}
+
+impl<E, F> Future for CustomEntryFuture<E, F>
+where
+    E: CustomJournalEntry,
+    F: FnOnce() -> E,
+{
+    type Output = Result<E, anyhow::Error>;
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let entry = this.entry.take().expect("Custom entry polled after completion");
+
+        let replayed = ready!(StateMachine::poll_with(|state_machine| {
+            if !state_machine.is_next_entry_replaying() {
+                return Poll::Ready(None);
+            }
+            // Any other entry journaled at this index makes the journal non-deterministic
+            let entry_index = state_machine.get_next_user_code_journal_index();
+            if !matches!(
+                state_machine.replayed_entry(entry_index),
+                Some(Entry::Custom(CustomEntry { code, .. })) if *code == E::CODE
+            ) {
+                state_machine.fail(errors::journal_mismatch(
+                    entry_index,
+                    format_args!("custom entry {:#06x}", E::CODE),
+                ));
+                return Poll::Ready(Some(None));
+            }
+            let (entry_index, result) = state_machine.handle_user_code_message(
+                None,
+                None,
+                Entry::Custom(CustomEntry {
+                    code: E::CODE,
+                    value: Bytes::new(),
+                }),
+                None,
+            );
+            debug!("Custom entry replayed for entry: {}", entry_index);
+            this.entry_index.store(entry_index, Ordering::Relaxed);
+            state_machine.set_span();
+            if result.is_none() {
+                state_machine.fail(errors::journal_mismatch(
+                    entry_index,
+                    format_args!("custom entry {:#06x}", E::CODE),
+                ));
+            }
+            Poll::Ready(Some(result))
+        }));
+        if let Some(result) = replayed {
+            return Poll::Ready(match result {
+                Some(value) => E::decode(value),
+                None => Err(anyhow!(
+                    "Replayed entry does not match the custom entry {:#06x}",
+                    E::CODE
+                )),
+            });
         }
+
+        let entry = entry();
+        let value = entry.encode()?;
+        ready!(StateMachine::poll_with(|state_machine| {
+            let (entry_index, _) = state_machine.handle_user_code_message(
+                None,
+                None,
+                Entry::Custom(CustomEntry { code: E::CODE, value }),
+                None,
+            );
+            debug!("Custom entry recorded for entry: {}", entry_index);
+            this.entry_index.store(entry_index, Ordering::Relaxed);
+            state_machine.set_span();
+            Poll::Ready(())
+        }));
+        Poll::Ready(Ok(entry))
     }
 }
 
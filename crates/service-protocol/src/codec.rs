@@ -14,7 +14,7 @@ use restate_sdk_types::{
     invocation::Header,
     journal::{
         raw::{ErrorKind, PlainEntryHeader, PlainRawEntry, RawEntry, RawEntryCodec, RawEntryCodecError},
-        CompletionResult, Entry, EntryType,
+        CompletionResult, CustomEntry, Entry, EntryType,
     },
     service_protocol,
 };
@@ -32,7 +32,10 @@ macro_rules! match_decode {
                     .map_err(|e| RawEntryCodecError::new($ty.clone(), ErrorKind::Decode { source: Some(e.into()) }))
                     .and_then(|msg| msg.try_into().map_err(|f| RawEntryCodecError::new($ty.clone(), ErrorKind::MissingField(f))))
               },)*
-             EntryType::Custom => Ok(Entry::Custom($buf.copy_to_bytes($buf.remaining()))),
+             EntryType::Custom(code) => Ok(Entry::Custom(CustomEntry {
+                 code,
+                 value: $buf.copy_to_bytes($buf.remaining()),
+             })),
         }
     };
 }
@@ -28,6 +28,12 @@ pub enum EncodingError {
     UnknownMessageType(#[from] UnknownMessageType),
     #[error("hit message size limit: {0} >= {1}")]
     MessageSizeLimit(usize, usize),
+    #[error("cannot encode message: {0}")]
+    EncodeMessage(#[from] prost::EncodeError),
+    #[error("stream closed in the middle of a message")]
+    UnexpectedEof,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
 }
 
 // --- Input message encoder
@@ -143,6 +149,11 @@ impl Decoder {
         self.buf.has_remaining()
     }
 
+    /// Returns `true` if no partially received message is buffered.
+    pub fn is_empty(&self) -> bool {
+        !self.has_remaining() && matches!(self.state, DecoderState::WaitingHeader)
+    }
+
     /// Concatenate a new chunk in the internal buffer.
     pub fn push(&mut self, buf: Bytes) {
         self.buf.push(buf)
@@ -0,0 +1,157 @@
+// Copyright (c) 2023 -  Restate Software, Inc., Restate GmbH.
+// All rights reserved.
+//
+// Use of this software is governed by the Business Source License
+// included in the LICENSE file.
+//
+// As of the Change Date specified in that file, in accordance with
+// the Business Source License, use of this software will be governed
+// by the Apache License, Version 2.0.
+
+//! [`tokio_util::codec`] adapter for the protocol [`Encoder`] and [`Decoder`].
+
+use super::*;
+
+use bytes::BytesMut;
+use restate_sdk_types::service_protocol::ServiceProtocolVersion;
+use tokio_util::codec;
+
+/// Codec framing a byte stream into [`ProtocolMessage`]s, to be used with
+/// [`FramedRead`](tokio_util::codec::FramedRead), [`FramedWrite`](tokio_util::codec::FramedWrite)
+/// or [`Framed`](tokio_util::codec::Framed).
+///
+/// Every complete message buffered is yielded before more bytes are read. Once a message cannot be
+/// decoded an error is returned, and the framed stream terminates.
+pub struct ProtocolCodec {
+    encoder: Encoder,
+    decoder: Decoder,
+}
+
+impl ProtocolCodec {
+    pub fn new(
+        service_protocol_version: ServiceProtocolVersion,
+        message_size_warning: usize,
+        message_size_limit: Option<usize>,
+    ) -> Self {
+        Self {
+            encoder: Encoder::new(service_protocol_version),
+            decoder: Decoder::new(service_protocol_version, message_size_warning, message_size_limit),
+        }
+    }
+}
+
+impl codec::Decoder for ProtocolCodec {
+    type Item = (MessageHeader, ProtocolMessage);
+    type Error = EncodingError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if !src.is_empty() {
+            self.decoder.push(src.split().freeze());
+        }
+        self.decoder.consume_next()
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decode(src)? {
+            Some(message) => Ok(Some(message)),
+            None if self.decoder.is_empty() => Ok(None),
+            None => Err(EncodingError::UnexpectedEof),
+        }
+    }
+}
+
+impl codec::Encoder<ProtocolMessage> for ProtocolCodec {
+    type Error = EncodingError;
+
+    fn encode(&mut self, message: ProtocolMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        dst.reserve(self.encoder.encoded_len(&message));
+        Ok(self.encoder.encode_to_buf_mut(dst, message)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::{SinkExt, StreamExt};
+    use pretty_assertions::assert_eq;
+    use restate_sdk_types::journal::raw::RawEntryCodec;
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    use crate::codec::ProtobufRawEntryCodec;
+
+    fn codec() -> ProtocolCodec {
+        ProtocolCodec::new(ServiceProtocolVersion::V1, usize::MAX, None)
+    }
+
+    fn messages() -> Vec<ProtocolMessage> {
+        vec![
+            ProtocolMessage::new_start_message(
+                "key".into(),
+                "key".into(),
+                Some("key".into()),
+                1,
+                true,
+                vec![],
+                10,
+                Duration::ZERO,
+            ),
+            ProtobufRawEntryCodec::serialize_as_input_entry(vec![], Bytes::from_static("input".as_bytes()))
+                .erase_enrichment()
+                .into(),
+            Completion {
+                entry_index: 1,
+                result: CompletionResult::Empty,
+            }
+            .into(),
+        ]
+    }
+
+    async fn encode_all(messages: Vec<ProtocolMessage>) -> Vec<u8> {
+        let mut framed = FramedWrite::new(Vec::new(), codec());
+        for message in messages {
+            framed.send(message).await.unwrap();
+        }
+        framed.into_inner()
+    }
+
+    #[tokio::test]
+    async fn decode_several_messages_in_one_chunk() {
+        let expected = messages();
+        let bytes = encode_all(expected.clone()).await;
+
+        let actual: Vec<_> = FramedRead::new(bytes.as_slice(), codec())
+            .map(|result| result.unwrap().1)
+            .collect()
+            .await;
+        assert_eq!(actual, expected);
+    }
+
+    #[tokio::test]
+    async fn decode_truncated_stream() {
+        let bytes = encode_all(messages()).await;
+
+        let mut framed = FramedRead::new(&bytes[..bytes.len() - 1], codec());
+        assert!(framed.next().await.unwrap().is_ok());
+        assert!(framed.next().await.unwrap().is_ok());
+        assert!(matches!(
+            framed.next().await,
+            Some(Err(EncodingError::UnexpectedEof))
+        ));
+        assert!(framed.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn decode_corrupted_stream() {
+        let mut bytes = encode_all(messages()).await;
+        // Unknown message type
+        bytes.splice(0..2, [0x00, 0x06]);
+
+        let mut framed = FramedRead::new(bytes.as_slice(), codec());
+        assert!(matches!(
+            framed.next().await,
+            Some(Err(EncodingError::UnknownMessageType(_)))
+        ));
+        assert!(framed.next().await.is_none());
+    }
+}
@@ -227,7 +227,7 @@ impl TryFrom<MessageType> for EntryType {
             MessageType::CompletePromiseEntry => Ok(EntryType::CompletePromise),
             MessageType::CancelInvocationEntry => Ok(EntryType::CancelInvocation),
             MessageType::GetCallInvocationIdEntry => Ok(EntryType::GetCallInvocationId),
-            MessageType::CustomEntry(_) => Ok(EntryType::Custom),
+            MessageType::CustomEntry(code) => Ok(EntryType::Custom(code)),
             MessageType::Start
             | MessageType::Completion
             | MessageType::Suspension
@@ -20,9 +20,11 @@ use restate_sdk_types::service_protocol;
 use std::time::Duration;
 
 mod encoding;
+mod framing;
 mod header;
 
 pub use encoding::{Decoder, Encoder, EncodingError};
+pub use framing::ProtocolCodec;
 pub use header::{MessageHeader, MessageKind, MessageType};
 
 #[derive(Debug, Clone, PartialEq)]
@@ -25,7 +25,7 @@ pub mod error {
         }
     }
 }
-///Restate endpoint manifest v1
+///Restate endpoint manifest v3
 ///
 /// <details><summary>JSON schema</summary>
 ///
@@ -33,7 +33,7 @@ pub mod error {
 ///{
 ///  "$id": "https://restate.dev/endpoint.manifest.json",
 ///  "title": "Endpoint",
-///  "description": "Restate endpoint manifest v1",
+///  "description": "Restate endpoint manifest v3",
 ///  "type": "object",
 ///  "required": [
 ///    "maxProtocolVersion",
@@ -71,6 +71,15 @@ pub mod error {
 ///          "ty"
 ///        ],
 ///        "properties": {
+///          "abortTimeout": {
+///            "description": "Duration in milliseconds after which the invocations of this service are aborted once the inactivity timeout fired, overriding the runtime default. Added in manifest v3.",
+///            "type": "integer",
+///            "minimum": 0.0
+///          },
+///          "documentation": {
+///            "description": "Documentation of the service, as shown by the UI, the CLI and the OpenAPI specification. Added in manifest v2.",
+///            "type": "string"
+///          },
 ///          "handlers": {
 ///            "type": "array",
 ///            "items": {
@@ -80,6 +89,29 @@ pub mod error {
 ///                "name"
 ///              ],
 ///              "properties": {
+///                "abortTimeout": {
+///                  "description": "Duration in milliseconds after which the invocations of this handler are aborted once the inactivity timeout fired, overriding the runtime default. Added in manifest v3.",
+///                  "type": "integer",
+///                  "minimum": 0.0
+///                },
+///                "documentation": {
+///                  "description": "Documentation of the handler, as shown by the UI, the CLI and the OpenAPI specification. Added in manifest v2.",
+///                  "type": "string"
+///                },
+///                "idempotencyRetention": {
+///                  "description": "Duration in milliseconds the responses of the idempotent invocations of this handler are retained for. Added in manifest v3.",
+///                  "type": "integer",
+///                  "minimum": 0.0
+///                },
+///                "inactivityTimeout": {
+///                  "description": "Duration in milliseconds after which the invocations of this handler are suspended or aborted when idle, overriding the runtime default. Added in manifest v3.",
+///                  "type": "integer",
+///                  "minimum": 0.0
+///                },
+///                "ingressPrivate": {
+///                  "description": "If true, the handler cannot be invoked through the ingress, only from other services. Added in manifest v3.",
+///                  "type": "boolean"
+///                },
 ///                "input": {
 ///                  "title": "InputPayload",
 ///                  "description": "Description of an input payload. This will be used by Restate to validate incoming requests.",
@@ -97,6 +129,18 @@ pub mod error {
 ///                  },
 ///                  "additionalProperties": false
 ///                },
+///                "journalRetention": {
+///                  "description": "Duration in milliseconds the journal of the completed invocations of this handler is retained for. Added in manifest v3.",
+///                  "type": "integer",
+///                  "minimum": 0.0
+///                },
+///                "metadata": {
+///                  "description": "Custom metadata of the handler, as key-value pairs. Added in manifest v2.",
+///                  "type": "object",
+///                  "additionalProperties": {
+///                    "type": "string"
+///                  }
+///                },
 ///                "name": {
 ///                  "type": "string",
 ///                  "pattern": "^([a-zA-Z]|_[a-zA-Z0-9])[a-zA-Z0-9_]*$"
@@ -126,11 +170,42 @@ pub mod error {
 ///                    "EXCLUSIVE",
 ///                    "SHARED"
 ///                  ]
+///                },
+///                "workflowCompletionRetention": {
+///                  "description": "Duration in milliseconds the completion of the workflow is retained for, only for WORKFLOW handlers. Added in manifest v3.",
+///                  "type": "integer",
+///                  "minimum": 0.0
 ///                }
 ///              },
 ///              "additionalProperties": false
 ///            }
 ///          },
+///          "idempotencyRetention": {
+///            "description": "Duration in milliseconds the responses of the idempotent invocations of this service are retained for. Added in manifest v3.",
+///            "type": "integer",
+///            "minimum": 0.0
+///          },
+///          "inactivityTimeout": {
+///            "description": "Duration in milliseconds after which the invocations of this service are suspended or aborted when idle, overriding the runtime default. Added in manifest v3.",
+///            "type": "integer",
+///            "minimum": 0.0
+///          },
+///          "ingressPrivate": {
+///            "description": "If true, the service cannot be invoked through the ingress, only from other services. Added in manifest v3.",
+///            "type": "boolean"
+///          },
+///          "journalRetention": {
+///            "description": "Duration in milliseconds the journal of the completed invocations of this service is retained for. Added in manifest v3.",
+///            "type": "integer",
+///            "minimum": 0.0
+///          },
+///          "metadata": {
+///            "description": "Custom metadata of the service, as key-value pairs. Added in manifest v2.",
+///            "type": "object",
+///            "additionalProperties": {
+///              "type": "string"
+///            }
+///          },
 ///          "name": {
 ///            "type": "string",
 ///            "pattern": "^([a-zA-Z]|_[a-zA-Z0-9])[a-zA-Z0-9._-]*$"
@@ -182,6 +257,29 @@ impl From<&Endpoint> for Endpoint {
 ///    "name"
 ///  ],
 ///  "properties": {
+///    "abortTimeout": {
+///      "description": "Duration in milliseconds after which the invocations of this handler are aborted once the inactivity timeout fired, overriding the runtime default. Added in manifest v3.",
+///      "type": "integer",
+///      "minimum": 0.0
+///    },
+///    "documentation": {
+///      "description": "Documentation of the handler, as shown by the UI, the CLI and the OpenAPI specification. Added in manifest v2.",
+///      "type": "string"
+///    },
+///    "idempotencyRetention": {
+///      "description": "Duration in milliseconds the responses of the idempotent invocations of this handler are retained for. Added in manifest v3.",
+///      "type": "integer",
+///      "minimum": 0.0
+///    },
+///    "inactivityTimeout": {
+///      "description": "Duration in milliseconds after which the invocations of this handler are suspended or aborted when idle, overriding the runtime default. Added in manifest v3.",
+///      "type": "integer",
+///      "minimum": 0.0
+///    },
+///    "ingressPrivate": {
+///      "description": "If true, the handler cannot be invoked through the ingress, only from other services. Added in manifest v3.",
+///      "type": "boolean"
+///    },
 ///    "input": {
 ///      "title": "InputPayload",
 ///      "description": "Description of an input payload. This will be used by Restate to validate incoming requests.",
@@ -199,6 +297,18 @@ impl From<&Endpoint> for Endpoint {
 ///      },
 ///      "additionalProperties": false
 ///    },
+///    "journalRetention": {
+///      "description": "Duration in milliseconds the journal of the completed invocations of this handler is retained for. Added in manifest v3.",
+///      "type": "integer",
+///      "minimum": 0.0
+///    },
+///    "metadata": {
+///      "description": "Custom metadata of the handler, as key-value pairs. Added in manifest v2.",
+///      "type": "object",
+///      "additionalProperties": {
+///        "type": "string"
+///      }
+///    },
 ///    "name": {
 ///      "type": "string",
 ///      "pattern": "^([a-zA-Z]|_[a-zA-Z0-9])[a-zA-Z0-9_]*$"
@@ -228,6 +338,11 @@ impl From<&Endpoint> for Endpoint {
 ///        "EXCLUSIVE",
 ///        "SHARED"
 ///      ]
+///    },
+///    "workflowCompletionRetention": {
+///      "description": "Duration in milliseconds the completion of the workflow is retained for, only for WORKFLOW handlers. Added in manifest v3.",
+///      "type": "integer",
+///      "minimum": 0.0
 ///    }
 ///  },
 ///  "additionalProperties": false
@@ -237,14 +352,54 @@ impl From<&Endpoint> for Endpoint {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Handler {
+    ///Duration in milliseconds after which the invocations of this handler are aborted once the inactivity timeout fired, overriding the runtime default. Added in manifest v3.
+    #[serde(rename = "abortTimeout", default, skip_serializing_if = "Option::is_none")]
+    pub abort_timeout: Option<u64>,
+    ///Documentation of the handler, as shown by the UI, the CLI and the OpenAPI specification. Added in manifest v2.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub documentation: Option<String>,
+    ///Duration in milliseconds the responses of the idempotent invocations of this handler are retained for. Added in manifest v3.
+    #[serde(
+        rename = "idempotencyRetention",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub idempotency_retention: Option<u64>,
+    ///Duration in milliseconds after which the invocations of this handler are suspended or aborted when idle, overriding the runtime default. Added in manifest v3.
+    #[serde(
+        rename = "inactivityTimeout",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub inactivity_timeout: Option<u64>,
+    ///If true, the handler cannot be invoked through the ingress, only from other services. Added in manifest v3.
+    #[serde(rename = "ingressPrivate", default, skip_serializing_if = "Option::is_none")]
+    pub ingress_private: Option<bool>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub input: Option<InputPayload>,
+    ///Duration in milliseconds the journal of the completed invocations of this handler is retained for. Added in manifest v3.
+    #[serde(
+        rename = "journalRetention",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub journal_retention: Option<u64>,
+    ///Custom metadata of the handler, as key-value pairs. Added in manifest v2.
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub metadata: std::collections::HashMap<String, String>,
     pub name: HandlerName,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub output: Option<OutputPayload>,
     ///If unspecified, defaults to EXCLUSIVE for Virtual Object or WORKFLOW for Workflows. This should be unset for Services.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub ty: Option<HandlerType>,
+    ///Duration in milliseconds the completion of the workflow is retained for, only for WORKFLOW handlers. Added in manifest v3.
+    #[serde(
+        rename = "workflowCompletionRetention",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub workflow_completion_retention: Option<u64>,
 }
 impl From<&Handler> for Handler {
     fn from(value: &Handler) -> Self {
@@ -577,6 +732,15 @@ impl std::convert::TryFrom<String> for ProtocolMode {
 ///    "ty"
 ///  ],
 ///  "properties": {
+///    "abortTimeout": {
+///      "description": "Duration in milliseconds after which the invocations of this service are aborted once the inactivity timeout fired, overriding the runtime default. Added in manifest v3.",
+///      "type": "integer",
+///      "minimum": 0.0
+///    },
+///    "documentation": {
+///      "description": "Documentation of the service, as shown by the UI, the CLI and the OpenAPI specification. Added in manifest v2.",
+///      "type": "string"
+///    },
 ///    "handlers": {
 ///      "type": "array",
 ///      "items": {
@@ -586,6 +750,29 @@ impl std::convert::TryFrom<String> for ProtocolMode {
 ///          "name"
 ///        ],
 ///        "properties": {
+///          "abortTimeout": {
+///            "description": "Duration in milliseconds after which the invocations of this handler are aborted once the inactivity timeout fired, overriding the runtime default. Added in manifest v3.",
+///            "type": "integer",
+///            "minimum": 0.0
+///          },
+///          "documentation": {
+///            "description": "Documentation of the handler, as shown by the UI, the CLI and the OpenAPI specification. Added in manifest v2.",
+///            "type": "string"
+///          },
+///          "idempotencyRetention": {
+///            "description": "Duration in milliseconds the responses of the idempotent invocations of this handler are retained for. Added in manifest v3.",
+///            "type": "integer",
+///            "minimum": 0.0
+///          },
+///          "inactivityTimeout": {
+///            "description": "Duration in milliseconds after which the invocations of this handler are suspended or aborted when idle, overriding the runtime default. Added in manifest v3.",
+///            "type": "integer",
+///            "minimum": 0.0
+///          },
+///          "ingressPrivate": {
+///            "description": "If true, the handler cannot be invoked through the ingress, only from other services. Added in manifest v3.",
+///            "type": "boolean"
+///          },
 ///          "input": {
 ///            "title": "InputPayload",
 ///            "description": "Description of an input payload. This will be used by Restate to validate incoming requests.",
@@ -603,6 +790,18 @@ impl std::convert::TryFrom<String> for ProtocolMode {
 ///            },
 ///            "additionalProperties": false
 ///          },
+///          "journalRetention": {
+///            "description": "Duration in milliseconds the journal of the completed invocations of this handler is retained for. Added in manifest v3.",
+///            "type": "integer",
+///            "minimum": 0.0
+///          },
+///          "metadata": {
+///            "description": "Custom metadata of the handler, as key-value pairs. Added in manifest v2.",
+///            "type": "object",
+///            "additionalProperties": {
+///              "type": "string"
+///            }
+///          },
 ///          "name": {
 ///            "type": "string",
 ///            "pattern": "^([a-zA-Z]|_[a-zA-Z0-9])[a-zA-Z0-9_]*$"
@@ -632,11 +831,42 @@ impl std::convert::TryFrom<String> for ProtocolMode {
 ///              "EXCLUSIVE",
 ///              "SHARED"
 ///            ]
+///          },
+///          "workflowCompletionRetention": {
+///            "description": "Duration in milliseconds the completion of the workflow is retained for, only for WORKFLOW handlers. Added in manifest v3.",
+///            "type": "integer",
+///            "minimum": 0.0
 ///          }
 ///        },
 ///        "additionalProperties": false
 ///      }
 ///    },
+///    "idempotencyRetention": {
+///      "description": "Duration in milliseconds the responses of the idempotent invocations of this service are retained for. Added in manifest v3.",
+///      "type": "integer",
+///      "minimum": 0.0
+///    },
+///    "inactivityTimeout": {
+///      "description": "Duration in milliseconds after which the invocations of this service are suspended or aborted when idle, overriding the runtime default. Added in manifest v3.",
+///      "type": "integer",
+///      "minimum": 0.0
+///    },
+///    "ingressPrivate": {
+///      "description": "If true, the service cannot be invoked through the ingress, only from other services. Added in manifest v3.",
+///      "type": "boolean"
+///    },
+///    "journalRetention": {
+///      "description": "Duration in milliseconds the journal of the completed invocations of this service is retained for. Added in manifest v3.",
+///      "type": "integer",
+///      "minimum": 0.0
+///    },
+///    "metadata": {
+///      "description": "Custom metadata of the service, as key-value pairs. Added in manifest v2.",
+///      "type": "object",
+///      "additionalProperties": {
+///        "type": "string"
+///      }
+///    },
 ///    "name": {
 ///      "type": "string",
 ///      "pattern": "^([a-zA-Z]|_[a-zA-Z0-9])[a-zA-Z0-9._-]*$"
@@ -657,7 +887,40 @@ impl std::convert::TryFrom<String> for ProtocolMode {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Service {
+    ///Duration in milliseconds after which the invocations of this service are aborted once the inactivity timeout fired, overriding the runtime default. Added in manifest v3.
+    #[serde(rename = "abortTimeout", default, skip_serializing_if = "Option::is_none")]
+    pub abort_timeout: Option<u64>,
+    ///Documentation of the service, as shown by the UI, the CLI and the OpenAPI specification. Added in manifest v2.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub documentation: Option<String>,
     pub handlers: Vec<Handler>,
+    ///Duration in milliseconds the responses of the idempotent invocations of this service are retained for. Added in manifest v3.
+    #[serde(
+        rename = "idempotencyRetention",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub idempotency_retention: Option<u64>,
+    ///Duration in milliseconds after which the invocations of this service are suspended or aborted when idle, overriding the runtime default. Added in manifest v3.
+    #[serde(
+        rename = "inactivityTimeout",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub inactivity_timeout: Option<u64>,
+    ///If true, the service cannot be invoked through the ingress, only from other services. Added in manifest v3.
+    #[serde(rename = "ingressPrivate", default, skip_serializing_if = "Option::is_none")]
+    pub ingress_private: Option<bool>,
+    ///Duration in milliseconds the journal of the completed invocations of this service is retained for. Added in manifest v3.
+    #[serde(
+        rename = "journalRetention",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub journal_retention: Option<u64>,
+    ///Custom metadata of the service, as key-value pairs. Added in manifest v2.
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub metadata: std::collections::HashMap<String, String>,
     pub name: ServiceName,
     pub ty: ServiceType,
 }
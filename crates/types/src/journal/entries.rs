@@ -43,7 +43,7 @@ pub enum Entry {
     Run(RunEntry),
     CancelInvocation(CancelInvocationEntry),
     GetCallInvocationId(GetCallInvocationIdEntry),
-    Custom(Bytes),
+    Custom(CustomEntry),
 }
 
 impl Entry {
@@ -181,7 +181,8 @@ pub enum EntryType {
     Run,
     CancelInvocation,
     GetCallInvocationId,
-    Custom,
+    /// Entry defined outside of the protocol, identified by its message type code.
+    Custom(u16),
 }
 
 impl fmt::Display for EntryType {
@@ -419,3 +420,10 @@ impl CompletableEntry for GetCallInvocationIdEntry {
         self.result.is_some()
     }
 }
+
+/// Entry with a message type code in the custom range, its value is opaque to the protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomEntry {
+    pub code: u16,
+    pub value: Bytes,
+}
@@ -213,7 +213,7 @@ impl<InvokeEnrichmentResult, AwakeableEnrichmentResult>
             EntryHeader::Awakeable { .. } => EntryType::Awakeable,
             EntryHeader::CompleteAwakeable { .. } => EntryType::CompleteAwakeable,
             EntryHeader::Run { .. } => EntryType::Run,
-            EntryHeader::Custom { .. } => EntryType::Custom,
+            EntryHeader::Custom { code } => EntryType::Custom(*code),
             EntryHeader::GetPromise { .. } => EntryType::GetPromise,
             EntryHeader::PeekPromise { .. } => EntryType::PeekPromise,
             EntryHeader::CompletePromise { .. } => EntryType::CompletePromise,
@@ -255,7 +255,7 @@ impl JournalClient {
                     EntryType::Run => MessageType::SideEffectEntry,
                     EntryType::CancelInvocation => MessageType::CancelInvocationEntry,
                     EntryType::GetCallInvocationId => MessageType::GetCallInvocationIdEntry,
-                    EntryType::Custom => MessageType::CustomEntry(0),
+                    EntryType::Custom(code) => MessageType::CustomEntry(code),
                 };
                 (
                     name,
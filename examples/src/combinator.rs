@@ -12,7 +12,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .with(env_filter)
         .with(tracing_subscriber::fmt::layer().with_filter(replay_filter))
         .init();
-    endpoint(RestateEndpointOptions::default(), service).await
+    endpoint(RestateEndpointOptions::default(), services()).await
 }
 
 trait ServiceHandler {
@@ -48,11 +48,8 @@ mod bundle {
         test: String,
     }
 
-    #[restate::service]
+    #[restate::service(name = "Echo")]
     impl EchoService {
-        const NAME: &'static str = "Echo";
-        const TYPE: &'static str = "SERVICE";
-
         #[restate::handler]
         pub async fn echo(ctx: Context, name: EchoInput) -> Result<ExecOutput, anyhow::Error> {
             tokio::time::sleep(Duration::from_secs(name.delay)).await;
@@ -62,9 +59,6 @@ mod bundle {
 
     #[restate::service]
     impl Service {
-        const NAME: &'static str = "Service";
-        const TYPE: &'static str = "SERVICE";
-
         #[restate::handler]
         pub async fn join(ctx: Context, name: ExecInput) -> Result<ExecOutput, anyhow::Error> {
             let output1 = ctx.echo_service_client().echo(EchoInput {
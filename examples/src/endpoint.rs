@@ -0,0 +1,62 @@
+use restate_sdk_api::{self as restate, RestateEndpoint};
+
+#[restate::main]
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    RestateEndpoint::builder()
+        .bind(greeter::Greeter)
+        .bind(counter::Counter)
+        // Rolling deployments stop the process with a signal, the invocations in flight are suspended
+        .shutdown_signal(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .build()?
+        .serve()
+        .await
+}
+
+mod greeter {
+    use restate_sdk_api::{self as restate, Context, ContextBase, JournalIndex};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct GreetInput {
+        name: String,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct GreetOutput {
+        greeting: String,
+    }
+
+    #[restate::service]
+    impl Greeter {
+        #[restate::handler]
+        pub async fn greet(ctx: Context, input: GreetInput) -> Result<GreetOutput, anyhow::Error> {
+            Ok(GreetOutput {
+                greeting: format!("Hello {}", input.name),
+            })
+        }
+    }
+}
+
+mod counter {
+    use restate_sdk_api::{
+        self as restate, ContextBase, JournalIndex, KeyValueStore, KeyValueStoreReadOnly, ObjectContext,
+    };
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct CounterInput {
+        value: u64,
+    }
+
+    #[restate::object]
+    impl Counter {
+        #[restate::handler]
+        pub async fn add(ctx: ObjectContext, input: CounterInput) -> Result<u64, anyhow::Error> {
+            let count = ctx.get::<u64, _>("count").await.unwrap_or_default() + input.value;
+            ctx.set("count", count).await;
+            Ok(count)
+        }
+    }
+}
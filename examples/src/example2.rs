@@ -1,158 +1,74 @@
-use restate_sdk_api::{self as restate, endpoint, RestateEndpointOptions};
-use tracing::info;
-/*
-use restate_sdk_api::{
-    empty, endpoint::endpoint, full, http2_handler, BodyExt, BoxBody, Bytes, Http2Receiver, Incoming, Method,
-    Request, Response, StatusCode,
-};
-*/
+use implementation::{ServiceImpl, SimpleServiceImpl};
+use interface::{Service, SimpleService};
+use restate_sdk_api::{self as restate, RestateEndpoint};
 
 #[restate::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    /*
-    let ingress = HttpIngress {};
-    let service_client = ingress.simple_service_client();
-    let o = service_client.greet(ExecInput { test: "".to_string() }).await?;
-    */
-
-    //endpoint_fn(SimpleService);
-    //endpoint_fn(Service);
-    endpoint(RestateEndpointOptions::default(), service).await
-}
-
-trait ServiceHandler {
-    fn name(&self) -> &'static str;
-    fn handlers(&self) -> &'static [&'static str];
+    RestateEndpoint::builder()
+        .bind(SimpleServiceImpl.serve())
+        .bind(ServiceImpl.serve())
+        .build()?
+        .serve()
+        .await
 }
 
-fn endpoint_fn<T: ServiceHandler>(service: T) {
-    info!("handlers {:?}", service.handlers());
-}
-
-#[restate::bundle]
-mod bundle {
-    use super::ServiceHandler;
-    use restate_sdk_api::{self as restate, async_recursion, Context, ContextBase, JournalIndex};
+/// Interface of the services, the callers only depend on it
+mod interface {
+    use restate_sdk_api::{self as restate, HandlerError};
     use serde::{Deserialize, Serialize};
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct ExecInput {
-        test: String,
+        pub test: String,
     }
 
     #[derive(Debug, Clone, Serialize, Deserialize)]
     pub struct ExecOutput {
-        test: String,
+        pub test: String,
     }
 
-    pub struct SimpleService;
-    impl SimpleService {
-        const NAME: &'static str = "SimpleService";
-        const TYPE: &'static str = "SERVICE";
-
-        #[restate::handler]
-        pub async fn greet(ctx: Context, name: ExecInput) -> Result<ExecOutput, anyhow::Error> {
-            Ok(ExecOutput { test: name.test })
-        }
+    #[restate::service]
+    pub trait SimpleService {
+        async fn greet(name: ExecInput) -> Result<ExecOutput, HandlerError>;
     }
 
-    trait SimpleServiceHandlerClient {
-        async fn greet(self, name: ExecInput) -> Result<ExecOutput, anyhow::Error>;
-    }
+    #[restate::service]
+    pub trait Service {
+        async fn service(name: ExecInput) -> Result<ExecOutput, HandlerError>;
 
-    struct SimpleServiceClientImpl<'a> {
-        ctx: &'a Context,
+        async fn greet(name: ExecInput) -> Result<ExecOutput, HandlerError>;
     }
+}
 
-    impl<'a> SimpleServiceClientImpl<'a> {
-        async fn greet(self, name: ExecInput) -> Result<ExecOutput, anyhow::Error> {
-            self.ctx
-                .invoke(
-                    SimpleService::greet,
-                    "SimpleService".to_string(),
-                    "greet".to_string(),
-                    name,
-                    None,
-                    None,
-                )
-                .await
-        }
-    }
+mod implementation {
+    use super::interface::{
+        ExecInput, ExecOutput, Service, ServiceClientExt, SimpleService, SimpleServiceClientExt,
+    };
+    use restate_sdk_api::{Context, HandlerError};
 
-    trait SimpleServiceClientExt {
-        fn simple_service_client(&self) -> SimpleServiceClientImpl;
-    }
+    pub struct SimpleServiceImpl;
 
-    impl SimpleServiceClientExt for Context {
-        fn simple_service_client(&self) -> SimpleServiceClientImpl {
-            SimpleServiceClientImpl { ctx: &self }
+    impl SimpleService for SimpleServiceImpl {
+        async fn greet(&self, _ctx: Context, name: ExecInput) -> Result<ExecOutput, HandlerError> {
+            Ok(ExecOutput { test: name.test })
         }
     }
 
-    pub struct Service;
-    impl Service {
-        const NAME: &'static str = "Service";
-        const TYPE: &'static str = "SERVICE";
+    pub struct ServiceImpl;
 
-        #[async_recursion]
-        #[restate::handler]
-        pub async fn service(ctx: Context, name: ExecInput) -> Result<ExecOutput, anyhow::Error> {
-            let output = ctx.simple_service_client().greet(name.clone()).await?;
+    impl Service for ServiceImpl {
+        async fn service(&self, ctx: Context, name: ExecInput) -> Result<ExecOutput, HandlerError> {
+            let output = ctx.simple_service_client().greet(name).await?;
             // Calling ourselves
-            let output = ctx.service_client().greet(name.clone()).await?;
+            let output = ctx
+                .service_client()
+                .greet(ExecInput { test: output.test })
+                .await?;
             Ok(ExecOutput { test: output.test })
         }
 
-        #[restate::handler]
-        pub async fn greet(ctx: Context, name: ExecInput) -> Result<ExecOutput, anyhow::Error> {
+        async fn greet(&self, _ctx: Context, name: ExecInput) -> Result<ExecOutput, HandlerError> {
             Ok(ExecOutput { test: name.test })
         }
     }
-
-    trait ServiceHandlerClient {
-        async fn service(self, name: ExecInput) -> Result<ExecOutput, anyhow::Error>;
-        async fn greet(self, name: ExecInput) -> Result<ExecOutput, anyhow::Error>;
-    }
-
-    struct ServiceClientImpl<'a> {
-        ctx: &'a Context,
-    }
-
-    impl<'a> ServiceClientImpl<'a> {
-        async fn service(self, name: ExecInput) -> Result<ExecOutput, anyhow::Error> {
-            self.ctx
-                .invoke(
-                    Service::service,
-                    "Service".to_string(),
-                    "service".to_string(),
-                    name,
-                    None,
-                    None,
-                )
-                .await
-        }
-
-        async fn greet(self, name: ExecInput) -> Result<ExecOutput, anyhow::Error> {
-            self.ctx
-                .invoke(
-                    Service::greet,
-                    "Service".to_string(),
-                    "greet".to_string(),
-                    name,
-                    None,
-                    None,
-                )
-                .await
-        }
-    }
-
-    trait ServiceClientExt {
-        fn service_client(&self) -> ServiceClientImpl;
-    }
-
-    impl ServiceClientExt for Context {
-        fn service_client(&self) -> ServiceClientImpl {
-            ServiceClientImpl { ctx: &self }
-        }
-    }
 }
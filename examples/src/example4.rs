@@ -2,7 +2,7 @@ use restate_sdk_api::{self as restate, endpoint, RestateEndpointOptions};
 
 #[restate::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    endpoint(RestateEndpointOptions::default(), service).await
+    endpoint(RestateEndpointOptions::default(), services()).await
 }
 
 #[restate::bundle]
@@ -29,19 +29,13 @@ mod bundle {
 
     #[restate::service]
     impl Service {
-        const NAME: &'static str = "Service";
-        const TYPE: &'static str = "SERVICE";
-
         #[async_recursion]
         #[restate::handler]
         pub async fn service(ctx: Context, name: ExecInput) -> Result<ExecOutput, anyhow::Error> {
             let (id, result) = ctx.awakeable::<SignalInput>();
-            ctx.run("action", move || {
-                let id = id.clone();
-                async move {
-                    info!("Service: service: saving signal: {}", id);
-                    Ok(())
-                }
+            ctx.run("action", || async {
+                info!("Service: service: saving signal: {}", id);
+                Ok(())
             })
             .await
             .unwrap();
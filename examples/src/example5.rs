@@ -11,7 +11,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .with(env_filter)
         .with(tracing_subscriber::fmt::layer().with_filter(replay_filter))
         .init();
-    endpoint(RestateEndpointOptions::default(), service).await
+    endpoint(RestateEndpointOptions::default(), services()).await
 }
 
 #[restate::bundle]
@@ -37,9 +37,6 @@ mod bundle {
 
     #[restate::service]
     impl SimpleService {
-        const NAME: &'static str = "SimpleService";
-        const TYPE: &'static str = "SERVICE";
-
         #[restate::handler]
         pub async fn greet(ctx: Context, name: ExecInput) -> Result<ExecOutput, anyhow::Error> {
             info!("SimpleService: greet: input {:?}", name);
@@ -49,9 +46,6 @@ mod bundle {
 
     #[restate::service]
     impl Service {
-        const NAME: &'static str = "Service";
-        const TYPE: &'static str = "SERVICE";
-
         #[async_recursion]
         #[restate::handler]
         pub async fn service(ctx: Context, name: ExecInput) -> Result<ExecOutput, anyhow::Error> {
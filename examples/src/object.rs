@@ -2,7 +2,7 @@ use restate_sdk_api::{self as restate, endpoint, RestateEndpointOptions};
 
 #[restate::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    endpoint(RestateEndpointOptions::default(), service).await
+    endpoint(RestateEndpointOptions::default(), services()).await
 }
 
 #[restate::bundle]
@@ -31,9 +31,6 @@ mod bundle {
 
     #[restate::object]
     impl ObjectService {
-        const NAME: &'static str = "ObjectService";
-        const TYPE: &'static str = "VIRTUAL_OBJECT";
-
         #[restate::handler]
         pub async fn increment(
             ctx: ObjectContext,
@@ -43,7 +40,7 @@ mod bundle {
             Ok(CounterOutput { value: input.value })
         }
 
-        #[restate::handler]
+        #[restate::handler(shared)]
         pub async fn count(ctx: ObjectSharedContext, signal: SignalInput) -> Result<(), anyhow::Error> {
             //let output = ctx.get::<CounterInput, _>("count").await;
             let output: Option<CounterInput> = ctx.get("count").await;
@@ -12,7 +12,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .with(env_filter)
         .with(tracing_subscriber::fmt::layer().with_filter(replay_filter))
         .init();
-    endpoint(RestateEndpointOptions::default(), service).await
+    endpoint(RestateEndpointOptions::default(), services()).await
 }
 
 trait ServiceHandler {
@@ -40,11 +40,8 @@ mod bundle {
         test: String,
     }
 
-    #[restate::service]
+    #[restate::service(name = "Echo")]
     impl EchoService {
-        const NAME: &'static str = "Echo";
-        const TYPE: &'static str = "SERVICE";
-
         #[restate::handler]
         pub async fn echo(ctx: Context, name: ExecInput) -> Result<ExecOutput, anyhow::Error> {
             tokio::time::sleep(Duration::from_secs(25)).await;
@@ -54,9 +51,6 @@ mod bundle {
 
     #[restate::service]
     impl Service {
-        const NAME: &'static str = "Service";
-        const TYPE: &'static str = "SERVICE";
-
         #[restate::handler]
         pub async fn service(ctx: Context, name: ExecInput) -> Result<ExecOutput, anyhow::Error> {
             let output = ctx.echo_service_client().echo(name.clone());
@@ -11,7 +11,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .with(env_filter)
         .with(tracing_subscriber::fmt::layer().with_filter(replay_filter))
         .init();
-    endpoint(RestateEndpointOptions::default(), service).await
+    endpoint(RestateEndpointOptions::default(), services()).await
 }
 
 #[restate::bundle]
@@ -40,10 +40,7 @@ mod bundle {
 
     #[restate::workflow]
     impl WorkflowService {
-        const NAME: &'static str = "WorkflowService";
-        const TYPE: &'static str = "WORKFLOW";
-
-        #[restate::handler]
+        #[restate::handler(workflow)]
         pub async fn run(ctx: WorkflowContext, name: ExecInput) -> Result<ExecOutput, anyhow::Error> {
             let signal_input: SignalInput = ctx.promise("await_user1").awaitable().await;
             info!("Signal1 output: {:?}", signal_input);